@@ -0,0 +1,54 @@
+//! Times a tight loop through the dispatch table, which steps take unless
+//! they are traced or profiled, against the same loop through the decoded
+//! instructions that profiling uses.
+//!
+//! Run with `cargo bench --bench dispatch`.
+
+use std::time::Instant;
+
+use re64::asm;
+use re64::hart::Hart;
+
+const ITERATIONS: i32 = 1_000_000;
+
+/// Sums the numbers up to `ITERATIONS`, and stops on an EBREAK. The low 12
+/// bits of `ITERATIONS` must not have their sign bit set, for the ADDI.
+fn program() -> Vec<u8> {
+    let words = [
+        asm::lui(5, ITERATIONS as u32 >> 12),
+        asm::addi(5, 5, ITERATIONS & 0xfff),
+        asm::addi(6, 6, 1),
+        asm::add(7, 7, 6),
+        asm::addi(5, 5, -1),
+        asm::bne(5, 0, -12),
+        asm::ebreak(),
+    ];
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn bench(name: &str, profile: bool) {
+    let mut hart = Hart::with_memory(program(), 0x10000);
+    hart.set_profile(profile);
+
+    let start = Instant::now();
+    hart.run();
+    let elapsed = start.elapsed();
+
+    let steps = 2 + 4 * ITERATIONS as u64;
+    let mips = steps as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{:<8} {:>10.2?} {:>8.1} MIPS", name, elapsed, mips);
+}
+
+fn main() {
+    let mut hart = Hart::with_memory(program(), 0x10000);
+    hart.run();
+    let expected = ITERATIONS as u64 * (ITERATIONS as u64 + 1) / 2;
+    assert_eq!(
+        hart.read_reg(7),
+        expected,
+        "the loop computed the wrong sum"
+    );
+
+    bench("table", false);
+    bench("profiled", true);
+}
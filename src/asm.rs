@@ -0,0 +1,608 @@
+use crate::instruction::{opcode, Instruction};
+
+/// Encodes a decoded instruction back into its 32-bit word, so that
+/// `encode(decode(word)) == word` for every legal 32-bit word. The bits
+/// that fences ignore are encoded as zero, and compressed instructions as
+/// their expansion.
+pub fn encode(instruction: Instruction) -> u32 {
+    match instruction {
+        Instruction::R(r) | Instruction::SfenceVma(r) => r_type(
+            r.funct7,
+            r.rs2 as u32,
+            r.rs1 as u32,
+            r.funct3,
+            r.rd as u32,
+            r.opcode,
+        ),
+        Instruction::R4(r) => {
+            (r.rs3 as u32) << 27
+                | r.fmt << 25
+                | (r.rs2 as u32) << 20
+                | (r.rs1 as u32) << 15
+                | r.funct3 << 12
+                | (r.rd as u32) << 7
+                | r.opcode
+        }
+        Instruction::I(i) => i_type(i.imm as u32, i.rs1 as u32, i.funct3, i.rd as u32, i.opcode),
+        Instruction::IS(i) => i_type(
+            i.funct6 << 6 | i.shamt,
+            i.rs1 as u32,
+            i.funct3,
+            i.rd as u32,
+            i.opcode,
+        ),
+        Instruction::S(s) => s_type(s.imm as u32, s.rs2 as u32, s.rs1 as u32, s.funct3, s.opcode),
+        Instruction::B(b) => b_type(
+            b.imm as u32,
+            b.rs2 as u32,
+            b.rs1 as u32,
+            b.funct3,
+            opcode::BRANCH,
+        ),
+        Instruction::U(u) => u_type(u.imm as u32, u.rd as u32, u.opcode),
+        Instruction::J(j) => j_type(j.imm as u32, j.rd as u32, opcode::JAL),
+        Instruction::Csr(c) => i_type(
+            c.csr as u32,
+            c.rs1 as u32,
+            c.funct3,
+            c.rd as u32,
+            opcode::SYSTEM,
+        ),
+        Instruction::Fence(f) => f.fm << 28 | f.pred << 24 | f.succ << 20 | opcode::MISC_MEM,
+        Instruction::FenceI => 0b001 << 12 | opcode::MISC_MEM,
+        Instruction::Ecall => ecall(),
+        Instruction::Ebreak => ebreak(),
+        Instruction::Mret => mret(),
+        Instruction::Sret => sret(),
+        Instruction::Wfi => wfi(),
+    }
+}
+
+/// Encodes the R-type instruction with the given fields.
+pub fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+/// Encodes the I-type instruction with the given fields, keeping the low 12
+/// bits of `imm`.
+pub fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+/// Encodes the S-type instruction with the given fields, keeping the low 12
+/// bits of `imm`.
+pub fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm & 0xfff;
+    ((imm >> 5) << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | ((imm & 0x1f) << 7) | opcode
+}
+
+/// Encodes the B-type instruction with the given fields. Bit 0 of `imm` is
+/// dropped, as branch offsets are even.
+pub fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    ((imm >> 12) & 1) << 31
+        | ((imm >> 5) & 0x3f) << 25
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm >> 1) & 0xf) << 8
+        | ((imm >> 11) & 1) << 7
+        | opcode
+}
+
+/// Encodes the U-type instruction with the given fields, keeping the upper
+/// 20 bits of `imm`.
+pub fn u_type(imm: u32, rd: u32, opcode: u32) -> u32 {
+    (imm & 0xffff_f000) | (rd << 7) | opcode
+}
+
+/// Encodes the J-type instruction with the given fields. Bit 0 of `imm` is
+/// dropped, as jump offsets are even.
+pub fn j_type(imm: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm >> 20) & 1) << 31
+        | ((imm >> 1) & 0x3ff) << 21
+        | ((imm >> 11) & 1) << 20
+        | ((imm >> 12) & 0xff) << 12
+        | (rd << 7)
+        | opcode
+}
+
+/// `lui rd, imm`, where `imm` is the 20-bit value placed in bits [31:12].
+pub fn lui(rd: u32, imm: u32) -> u32 {
+    u_type(imm << 12, rd, opcode::LUI)
+}
+
+/// `auipc rd, imm`, where `imm` is the 20-bit value placed in bits [31:12].
+pub fn auipc(rd: u32, imm: u32) -> u32 {
+    u_type(imm << 12, rd, opcode::AUIPC)
+}
+
+/// `jal rd, offset`, with `offset` relative to the instruction.
+pub fn jal(rd: u32, offset: i32) -> u32 {
+    j_type(offset as u32, rd, opcode::JAL)
+}
+
+/// `jalr rd, offset(rs1)`, with `offset` relative to `rs1`.
+pub fn jalr(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b000, rd, opcode::JALR)
+}
+
+/// `beq rs1, rs2, offset`, with `offset` relative to the instruction, as for
+/// the other branches.
+pub fn beq(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b000, opcode::BRANCH)
+}
+
+/// `bne rs1, rs2, offset`.
+pub fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b001, opcode::BRANCH)
+}
+
+/// `blt rs1, rs2, offset`.
+pub fn blt(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b100, opcode::BRANCH)
+}
+
+/// `bge rs1, rs2, offset`.
+pub fn bge(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b101, opcode::BRANCH)
+}
+
+/// `bltu rs1, rs2, offset`.
+pub fn bltu(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b110, opcode::BRANCH)
+}
+
+/// `bgeu rs1, rs2, offset`.
+pub fn bgeu(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    b_type(offset as u32, rs2, rs1, 0b111, opcode::BRANCH)
+}
+
+/// `lb rd, offset(rs1)`, as for the other loads.
+pub fn lb(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b000, rd, opcode::LOAD)
+}
+
+/// `lh rd, offset(rs1)`.
+pub fn lh(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b001, rd, opcode::LOAD)
+}
+
+/// `lw rd, offset(rs1)`.
+pub fn lw(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b010, rd, opcode::LOAD)
+}
+
+/// `ld rd, offset(rs1)`.
+pub fn ld(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b011, rd, opcode::LOAD)
+}
+
+/// `lbu rd, offset(rs1)`.
+pub fn lbu(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b100, rd, opcode::LOAD)
+}
+
+/// `lhu rd, offset(rs1)`.
+pub fn lhu(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b101, rd, opcode::LOAD)
+}
+
+/// `lwu rd, offset(rs1)`.
+pub fn lwu(rd: u32, rs1: u32, offset: i32) -> u32 {
+    i_type(offset as u32, rs1, 0b110, rd, opcode::LOAD)
+}
+
+/// `sb rs2, offset(rs1)`, as for the other stores.
+pub fn sb(rs2: u32, rs1: u32, offset: i32) -> u32 {
+    s_type(offset as u32, rs2, rs1, 0b000, opcode::STORE)
+}
+
+/// `sh rs2, offset(rs1)`.
+pub fn sh(rs2: u32, rs1: u32, offset: i32) -> u32 {
+    s_type(offset as u32, rs2, rs1, 0b001, opcode::STORE)
+}
+
+/// `sw rs2, offset(rs1)`.
+pub fn sw(rs2: u32, rs1: u32, offset: i32) -> u32 {
+    s_type(offset as u32, rs2, rs1, 0b010, opcode::STORE)
+}
+
+/// `sd rs2, offset(rs1)`.
+pub fn sd(rs2: u32, rs1: u32, offset: i32) -> u32 {
+    s_type(offset as u32, rs2, rs1, 0b011, opcode::STORE)
+}
+
+/// `addi rd, rs1, imm`.
+pub fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b000, rd, opcode::OP_IMM)
+}
+
+/// `slti rd, rs1, imm`.
+pub fn slti(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b010, rd, opcode::OP_IMM)
+}
+
+/// `sltiu rd, rs1, imm`.
+pub fn sltiu(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b011, rd, opcode::OP_IMM)
+}
+
+/// `xori rd, rs1, imm`.
+pub fn xori(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b100, rd, opcode::OP_IMM)
+}
+
+/// `ori rd, rs1, imm`.
+pub fn ori(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b110, rd, opcode::OP_IMM)
+}
+
+/// `andi rd, rs1, imm`.
+pub fn andi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b111, rd, opcode::OP_IMM)
+}
+
+/// `slli rd, rs1, shamt`, for a `shamt` from 0 to 63.
+pub fn slli(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(shamt & 0x3f, rs1, 0b001, rd, opcode::OP_IMM)
+}
+
+/// `srli rd, rs1, shamt`.
+pub fn srli(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(shamt & 0x3f, rs1, 0b101, rd, opcode::OP_IMM)
+}
+
+/// `srai rd, rs1, shamt`.
+pub fn srai(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(0x400 | shamt & 0x3f, rs1, 0b101, rd, opcode::OP_IMM)
+}
+
+/// `addiw rd, rs1, imm`.
+pub fn addiw(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(imm as u32, rs1, 0b000, rd, opcode::OP_IMM_32)
+}
+
+/// `slliw rd, rs1, shamt`, for a `shamt` from 0 to 31.
+pub fn slliw(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(shamt & 0x1f, rs1, 0b001, rd, opcode::OP_IMM_32)
+}
+
+/// `srliw rd, rs1, shamt`.
+pub fn srliw(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(shamt & 0x1f, rs1, 0b101, rd, opcode::OP_IMM_32)
+}
+
+/// `sraiw rd, rs1, shamt`.
+pub fn sraiw(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(0x400 | shamt & 0x1f, rs1, 0b101, rd, opcode::OP_IMM_32)
+}
+
+/// `add rd, rs1, rs2`.
+pub fn add(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b000, rd, opcode::OP)
+}
+
+/// `sub rd, rs1, rs2`.
+pub fn sub(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x20, rs2, rs1, 0b000, rd, opcode::OP)
+}
+
+/// `sll rd, rs1, rs2`.
+pub fn sll(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b001, rd, opcode::OP)
+}
+
+/// `slt rd, rs1, rs2`.
+pub fn slt(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b010, rd, opcode::OP)
+}
+
+/// `sltu rd, rs1, rs2`.
+pub fn sltu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b011, rd, opcode::OP)
+}
+
+/// `xor rd, rs1, rs2`.
+pub fn xor(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b100, rd, opcode::OP)
+}
+
+/// `srl rd, rs1, rs2`.
+pub fn srl(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b101, rd, opcode::OP)
+}
+
+/// `sra rd, rs1, rs2`.
+pub fn sra(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x20, rs2, rs1, 0b101, rd, opcode::OP)
+}
+
+/// `or rd, rs1, rs2`.
+pub fn or(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b110, rd, opcode::OP)
+}
+
+/// `and rd, rs1, rs2`.
+pub fn and(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b111, rd, opcode::OP)
+}
+
+/// `mul rd, rs1, rs2`.
+pub fn mul(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b000, rd, opcode::OP)
+}
+
+/// `mulh rd, rs1, rs2`.
+pub fn mulh(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b001, rd, opcode::OP)
+}
+
+/// `mulhsu rd, rs1, rs2`.
+pub fn mulhsu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b010, rd, opcode::OP)
+}
+
+/// `mulhu rd, rs1, rs2`.
+pub fn mulhu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b011, rd, opcode::OP)
+}
+
+/// `div rd, rs1, rs2`.
+pub fn div(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b100, rd, opcode::OP)
+}
+
+/// `divu rd, rs1, rs2`.
+pub fn divu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b101, rd, opcode::OP)
+}
+
+/// `rem rd, rs1, rs2`.
+pub fn rem(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b110, rd, opcode::OP)
+}
+
+/// `remu rd, rs1, rs2`.
+pub fn remu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b111, rd, opcode::OP)
+}
+
+/// `addw rd, rs1, rs2`.
+pub fn addw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b000, rd, opcode::OP_32)
+}
+
+/// `subw rd, rs1, rs2`.
+pub fn subw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x20, rs2, rs1, 0b000, rd, opcode::OP_32)
+}
+
+/// `sllw rd, rs1, rs2`.
+pub fn sllw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b001, rd, opcode::OP_32)
+}
+
+/// `srlw rd, rs1, rs2`.
+pub fn srlw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x00, rs2, rs1, 0b101, rd, opcode::OP_32)
+}
+
+/// `sraw rd, rs1, rs2`.
+pub fn sraw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x20, rs2, rs1, 0b101, rd, opcode::OP_32)
+}
+
+/// `mulw rd, rs1, rs2`.
+pub fn mulw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b000, rd, opcode::OP_32)
+}
+
+/// `divw rd, rs1, rs2`.
+pub fn divw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b100, rd, opcode::OP_32)
+}
+
+/// `divuw rd, rs1, rs2`.
+pub fn divuw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b101, rd, opcode::OP_32)
+}
+
+/// `remw rd, rs1, rs2`.
+pub fn remw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b110, rd, opcode::OP_32)
+}
+
+/// `remuw rd, rs1, rs2`.
+pub fn remuw(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0x01, rs2, rs1, 0b111, rd, opcode::OP_32)
+}
+
+/// `csrrw rd, csr, rs1`, as for the other CSR instructions.
+pub fn csrrw(rd: u32, csr: u32, rs1: u32) -> u32 {
+    i_type(csr, rs1, 0b001, rd, opcode::SYSTEM)
+}
+
+/// `csrrs rd, csr, rs1`.
+pub fn csrrs(rd: u32, csr: u32, rs1: u32) -> u32 {
+    i_type(csr, rs1, 0b010, rd, opcode::SYSTEM)
+}
+
+/// `csrrc rd, csr, rs1`.
+pub fn csrrc(rd: u32, csr: u32, rs1: u32) -> u32 {
+    i_type(csr, rs1, 0b011, rd, opcode::SYSTEM)
+}
+
+/// `csrrwi rd, csr, uimm`, for a `uimm` from 0 to 31, as for the other
+/// immediate CSR instructions.
+pub fn csrrwi(rd: u32, csr: u32, uimm: u32) -> u32 {
+    i_type(csr, uimm & 0x1f, 0b101, rd, opcode::SYSTEM)
+}
+
+/// `csrrsi rd, csr, uimm`.
+pub fn csrrsi(rd: u32, csr: u32, uimm: u32) -> u32 {
+    i_type(csr, uimm & 0x1f, 0b110, rd, opcode::SYSTEM)
+}
+
+/// `csrrci rd, csr, uimm`.
+pub fn csrrci(rd: u32, csr: u32, uimm: u32) -> u32 {
+    i_type(csr, uimm & 0x1f, 0b111, rd, opcode::SYSTEM)
+}
+
+/// `ecall`.
+pub fn ecall() -> u32 {
+    0x0000_0073
+}
+
+/// `ebreak`.
+pub fn ebreak() -> u32 {
+    0x0010_0073
+}
+
+/// `sret`.
+pub fn sret() -> u32 {
+    0x1020_0073
+}
+
+/// `mret`.
+pub fn mret() -> u32 {
+    0x3020_0073
+}
+
+/// `wfi`.
+pub fn wfi() -> u32 {
+    0x1050_0073
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{BFormat, IFormat, InstructionBytes, JFormat, SFormat};
+
+    fn decode(word: u32) -> Instruction {
+        Instruction::try_from(InstructionBytes(word)).unwrap()
+    }
+
+    #[test]
+    fn addi_decodes_to_its_fields() {
+        assert_eq!(
+            decode(addi(1, 2, -4)),
+            Instruction::I(IFormat {
+                rd: 1,
+                rs1: 2,
+                imm: -4,
+                funct3: 0b000,
+                opcode: opcode::OP_IMM,
+            })
+        );
+    }
+
+    #[test]
+    fn branches_jumps_and_stores_decode_to_their_fields() {
+        assert_eq!(
+            decode(beq(3, 4, -4096)),
+            Instruction::B(BFormat {
+                rs1: 3,
+                rs2: 4,
+                imm: -4096,
+                funct3: 0b000,
+            })
+        );
+        assert_eq!(
+            decode(jal(1, 0xf_fffe)),
+            Instruction::J(JFormat {
+                rd: 1,
+                imm: 0xf_fffe
+            })
+        );
+        assert_eq!(
+            decode(sd(5, 6, -2048)),
+            Instruction::S(SFormat {
+                rs1: 6,
+                rs2: 5,
+                imm: -2048,
+                funct3: 0b011,
+                opcode: opcode::STORE,
+            })
+        );
+    }
+
+    /// The bits of `word` that encoding its decoded instruction keeps:
+    /// everything but the fields that fences ignore.
+    fn significant_bits(word: u32, instruction: Instruction) -> u32 {
+        match instruction {
+            // rs1 and rd.
+            Instruction::Fence(_) => word & !(0x1f << 15 | 0x1f << 7),
+            // The immediate, rs1 and rd.
+            Instruction::FenceI => word & 0x7fff & !(0x1f << 7),
+            _ => word,
+        }
+    }
+
+    /// Checks that `word` encodes back to itself once decoded, if it is
+    /// legal, returning whether it was.
+    fn round_trips(word: u32) -> bool {
+        let Ok(instruction) = Instruction::try_from(InstructionBytes(word)) else {
+            return false;
+        };
+        assert_eq!(
+            encode(instruction),
+            significant_bits(word, instruction),
+            "{word:#010x} decoded as {instruction:?}"
+        );
+        true
+    }
+
+    #[test]
+    fn encoding_a_decoded_word_gives_it_back() {
+        const IMPLEMENTED: [u32; 21] = [
+            opcode::LOAD,
+            opcode::LOAD_FP,
+            opcode::MISC_MEM,
+            opcode::OP_IMM,
+            opcode::AUIPC,
+            opcode::OP_IMM_32,
+            opcode::STORE,
+            opcode::STORE_FP,
+            opcode::AMO,
+            opcode::OP,
+            opcode::LUI,
+            opcode::OP_32,
+            opcode::MADD,
+            opcode::MSUB,
+            opcode::NMSUB,
+            opcode::NMADD,
+            opcode::OP_FP,
+            opcode::BRANCH,
+            opcode::JALR,
+            opcode::JAL,
+            opcode::SYSTEM,
+        ];
+        // A xorshift generator, so that the sweep is the same every run.
+        let mut state = 0x9e37_79b9_u32;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        // Every 32-bit major opcode, with random values in the other bits.
+        for major in 0..32 {
+            let low_bits = major << 2 | 0b11;
+            let legal = (0..20_000)
+                .filter(|_| round_trips(random() & !0x7f | low_bits))
+                .count();
+            assert_eq!(
+                legal > 0,
+                IMPLEMENTED.contains(&low_bits),
+                "{low_bits:#09b}"
+            );
+        }
+
+        // The SYSTEM instructions without fields are too rare to be hit at
+        // random.
+        for word in [ecall(), ebreak(), sret(), mret(), wfi(), 0x1220_8073] {
+            assert!(round_trips(word), "{word:#010x}");
+        }
+    }
+}
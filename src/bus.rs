@@ -0,0 +1,211 @@
+use std::ops::Range;
+
+use crate::clint::{Clint, CLINT_BASE, CLINT_SIZE};
+use crate::dram::Dram;
+use crate::exception::InstructionException;
+use crate::htif::Htif;
+use crate::plic::{Plic, PLIC_BASE, PLIC_SIZE};
+
+/// A peripheral that responds to loads and stores within its mapped range.
+///
+/// Addresses are passed as offsets from the start of that range. Loads take
+/// `&mut self` as reading a device register may have side effects.
+pub trait MemoryDevice {
+    /// Reads `size` bytes (1, 2, 4 or 8) at `addr` as a little-endian value.
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, InstructionException>;
+
+    /// Writes the low `size` bytes (1, 2, 4 or 8) of `val` to `addr`.
+    fn store(&mut self, addr: u64, size: u8, val: u64) -> Result<(), InstructionException>;
+}
+
+/// The system bus, routing physical memory accesses to the device mapped at
+/// each address.
+///
+/// DRAM and the interrupt controllers are always present, as the hart needs
+/// direct access to them; other peripherals are mapped with [`Bus::map`].
+/// An HTIF, if attached, takes precedence over DRAM, as its words usually
+/// live in the program's image.
+pub struct Bus {
+    pub dram: Dram,
+    pub clint: Clint,
+    pub plic: Plic,
+    pub htif: Option<Htif>,
+    devices: Vec<(Range<u64>, Box<dyn MemoryDevice>)>,
+}
+
+impl Bus {
+    /// Creates a bus with `dram`, a single-hart CLINT at `CLINT_BASE` and a
+    /// PLIC at `PLIC_BASE`.
+    pub fn new(dram: Dram) -> Self {
+        Bus {
+            dram,
+            clint: Clint::new(1),
+            plic: Plic::new(),
+            htif: None,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Maps `device` at the physical addresses in `range`.
+    pub fn map(&mut self, range: Range<u64>, device: Box<dyn MemoryDevice>) {
+        self.devices.push((range, device));
+    }
+
+    /// Whether all `len` bytes at `addr` are backed by DRAM or a device.
+    pub fn is_mapped(&self, addr: u64, len: usize) -> bool {
+        let Some(end) = addr.checked_add(len as u64) else {
+            return false;
+        };
+
+        self.dram.contains(addr, len)
+            || self.is_htif(addr, len)
+            || (CLINT_BASE <= addr && end <= CLINT_BASE + CLINT_SIZE)
+            || (PLIC_BASE <= addr && end <= PLIC_BASE + PLIC_SIZE)
+            || self
+                .devices
+                .iter()
+                .any(|(range, _)| range.start <= addr && end <= range.end)
+    }
+
+    /// Reads `size` bytes (1, 2, 4 or 8) at `addr` as a little-endian value.
+    pub fn load(&mut self, addr: u64, size: usize) -> Result<u64, InstructionException> {
+        if self.dram.contains(addr, size) && !self.is_htif(addr, size) {
+            return self.dram.load(addr, size);
+        }
+
+        match self.device(addr, size) {
+            Some((offset, device)) => device.load(offset, size as u8),
+            None => Err(InstructionException::LoadAccessFault(addr)),
+        }
+    }
+
+    /// Writes the low `size` bytes (1, 2, 4 or 8) of `value` to `addr` in
+    /// little-endian order.
+    pub fn store(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), InstructionException> {
+        if self.dram.contains(addr, size) && !self.is_htif(addr, size) {
+            return self.dram.store(addr, size, value);
+        }
+
+        match self.device(addr, size) {
+            Some((offset, device)) => device.store(offset, size as u8, value)?,
+            None => return Err(InstructionException::StoreAccessFault(addr)),
+        }
+        if let Some(htif) = &mut self.htif {
+            htif.service(&mut self.dram);
+        }
+
+        Ok(())
+    }
+
+    fn is_htif(&self, addr: u64, len: usize) -> bool {
+        self.htif
+            .as_ref()
+            .is_some_and(|htif| htif.covers(addr, len))
+    }
+
+    /// Finds the device whose range holds all `len` bytes at `addr`, along
+    /// with the offset of `addr` into that range.
+    fn device(
+        &mut self,
+        addr: u64,
+        len: usize,
+    ) -> Option<(u64, &mut (dyn MemoryDevice + 'static))> {
+        let end = addr.checked_add(len as u64)?;
+        if let Some(htif) = self.htif.as_mut().filter(|htif| htif.covers(addr, len)) {
+            return Some((addr.wrapping_sub(htif.base()), htif));
+        }
+        if CLINT_BASE <= addr && end <= CLINT_BASE + CLINT_SIZE {
+            return Some((addr - CLINT_BASE, &mut self.clint));
+        }
+        if PLIC_BASE <= addr && end <= PLIC_BASE + PLIC_SIZE {
+            return Some((addr - PLIC_BASE, &mut self.plic));
+        }
+
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.start <= addr && end <= range.end)
+            .map(|(range, device)| (addr - range.start, device.as_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn bus() -> Bus {
+        Bus::new(Dram::new(0, 0x1000, &[]))
+    }
+
+    /// Records the last byte written to it, and the offset it was written
+    /// to.
+    struct Recorder(Rc<Cell<Option<(u64, u8)>>>);
+
+    impl MemoryDevice for Recorder {
+        fn load(&mut self, addr: u64, _size: u8) -> Result<u64, InstructionException> {
+            Ok(addr)
+        }
+
+        fn store(&mut self, addr: u64, _size: u8, val: u64) -> Result<(), InstructionException> {
+            self.0.set(Some((addr, val as u8)));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unmapped_accesses_fault() {
+        let mut bus = bus();
+
+        assert_eq!(
+            bus.load(0x4000_0000, 4),
+            Err(InstructionException::LoadAccessFault(0x4000_0000))
+        );
+        assert_eq!(
+            bus.store(0x4000_0000, 4, 1),
+            Err(InstructionException::StoreAccessFault(0x4000_0000))
+        );
+        // An access straddling the end of DRAM is not mapped either.
+        assert_eq!(
+            bus.load(0xffc, 8),
+            Err(InstructionException::LoadAccessFault(0xffc))
+        );
+        assert_eq!(
+            bus.load(u64::MAX, 2),
+            Err(InstructionException::LoadAccessFault(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn dram_accesses_are_routed_to_dram() {
+        let mut bus = bus();
+
+        bus.store(8, 8, 0x0123_4567_89ab_cdef).unwrap();
+        assert_eq!(bus.load(8, 8), Ok(0x0123_4567_89ab_cdef));
+        assert_eq!(bus.dram.load(8, 1), Ok(0xef));
+    }
+
+    #[test]
+    fn accesses_are_dispatched_to_the_device_in_range() {
+        let mut bus = bus();
+        let written = Rc::new(Cell::new(None));
+        bus.map(
+            0x1000_0000..0x1000_0100,
+            Box::new(Recorder(written.clone())),
+        );
+
+        bus.store(0x1000_0004, 1, 0x41).unwrap();
+        assert_eq!(written.get(), Some((4, 0x41)));
+        assert_eq!(bus.load(0x1000_00f0, 4), Ok(0xf0));
+        assert_eq!(
+            bus.store(0x1000_0100, 1, 0),
+            Err(InstructionException::StoreAccessFault(0x1000_0100))
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::bus::MemoryDevice;
+use crate::exception::InstructionException;
+
+/// Physical address at which the CLINT's registers are mapped.
+pub const CLINT_BASE: u64 = 0x0200_0000;
+/// Size of the CLINT's register window.
+pub const CLINT_SIZE: u64 = 0x1_0000;
+
+/// Offsets of the per-hart `msip` and `mtimecmp` arrays and of `mtime`, in
+/// the SiFive layout.
+const MSIP: u64 = 0x0;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+/// The core-local interruptor, providing the machine timer and software
+/// interrupts for a set of harts.
+pub struct Clint {
+    msip: Vec<u32>,
+    mtimecmp: Vec<u64>,
+    mtime: u64,
+}
+
+impl Clint {
+    /// Creates a CLINT serving `harts` harts, with `mtime` at zero and no
+    /// interrupts pending.
+    pub fn new(harts: usize) -> Self {
+        Clint {
+            msip: vec![0; harts],
+            mtimecmp: vec![u64::MAX; harts],
+            mtime: 0,
+        }
+    }
+
+    /// Advances `mtime` by `ticks`.
+    pub fn tick(&mut self, ticks: u64) {
+        self.mtime = self.mtime.wrapping_add(ticks);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// Whether the machine timer interrupt of `hart` is pending.
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    /// Whether the machine software interrupt of `hart` is pending.
+    pub fn software_pending(&self, hart: usize) -> bool {
+        self.msip[hart] & 1 != 0
+    }
+
+    /// The hart whose 32-bit `msip` register is at `addr`, if any.
+    fn msip_hart(&self, addr: u64) -> Option<usize> {
+        let hart = (addr.checked_sub(MSIP)? / 4) as usize;
+        (hart < self.msip.len()).then_some(hart)
+    }
+
+    /// Finds the 64-bit register at `addr`, with the byte offset of `addr`
+    /// into it.
+    fn register(&mut self, addr: u64) -> Option<(&mut u64, u64)> {
+        let harts = self.mtimecmp.len() as u64;
+        match addr {
+            MTIMECMP.. if addr < MTIMECMP + 8 * harts => {
+                let hart = (addr - MTIMECMP) / 8;
+                Some((&mut self.mtimecmp[hart as usize], addr % 8))
+            }
+            MTIME..=0xbfff => Some((&mut self.mtime, addr - MTIME)),
+            _ => None,
+        }
+    }
+}
+
+impl MemoryDevice for Clint {
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, InstructionException> {
+        if let Some(hart) = self.msip_hart(addr) {
+            return Ok(self.msip[hart] as u64);
+        }
+
+        Ok(match self.register(addr) {
+            Some((reg, offset)) => (*reg >> (offset * 8)) & mask(size),
+            None => 0,
+        })
+    }
+
+    fn store(&mut self, addr: u64, size: u8, val: u64) -> Result<(), InstructionException> {
+        if let Some(hart) = self.msip_hart(addr) {
+            // Only the low bit of each msip register is writable.
+            self.msip[hart] = val as u32 & 1;
+            return Ok(());
+        }
+
+        if let Some((reg, offset)) = self.register(addr) {
+            let shift = offset * 8;
+            let mask = mask(size) << shift;
+            *reg = (*reg & !mask) | ((val << shift) & mask);
+        }
+
+        Ok(())
+    }
+}
+
+/// A mask of the low `size` bytes of a 64-bit value.
+fn mask(size: u8) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
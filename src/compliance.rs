@@ -0,0 +1,72 @@
+use std::fmt;
+
+use crate::elf::ElfError;
+use crate::exception::InstructionException;
+use crate::hart::{Halt, Hart};
+
+/// The result reported by a riscv-tests program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestOutcome {
+    Pass,
+    /// The number of the first failing test case.
+    Fail(u64),
+}
+
+/// Reasons a riscv-tests program could not run to completion.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TestError {
+    Elf(ElfError),
+    /// The program has no `tohost` and `fromhost` symbols.
+    NoTohost,
+    /// The hart stopped before the program wrote to `tohost`.
+    Halted(InstructionException),
+    /// The program did not write to `tohost` within the given number of
+    /// steps.
+    Timeout(u64),
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestError::Elf(e) => write!(f, "{}", e),
+            TestError::NoTohost => write!(f, "no tohost and fromhost symbols"),
+            TestError::Halted(exception) => {
+                write!(f, "halted before writing tohost: {:?}", exception)
+            }
+            TestError::Timeout(steps) => {
+                write!(f, "did not write tohost within {} steps", steps)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TestError {}
+
+impl From<ElfError> for TestError {
+    fn from(e: ElfError) -> Self {
+        TestError::Elf(e)
+    }
+}
+
+/// Steps given to a test by default, far more than any riscv-tests program
+/// needs.
+pub const MAX_STEPS: u64 = 10_000_000;
+
+/// Runs a riscv-tests ELF executable with `memory_size` bytes of DRAM until
+/// it reports its result through the HTIF: exit code 0 for a pass, or the
+/// number of the failing test. A program still running after `max_steps`
+/// steps fails with [`TestError::Timeout`].
+pub fn run(bytes: &[u8], memory_size: usize, max_steps: u64) -> Result<TestOutcome, TestError> {
+    let mut hart = Hart::from_elf(bytes, memory_size)?;
+    if hart.bus.htif.is_none() {
+        return Err(TestError::NoTohost);
+    }
+
+    match hart.run_until(max_steps) {
+        Some(Halt::Exit(0)) => Ok(TestOutcome::Pass),
+        Some(Halt::Exit(test)) => Ok(TestOutcome::Fail(test)),
+        Some(Halt::Exception(exception)) => Err(TestError::Halted(exception)),
+        Some(Halt::Breakpoint(_) | Halt::Watchpoint(_)) => unreachable!("no breakpoints are set"),
+        None => Err(TestError::Timeout(max_steps)),
+    }
+}
@@ -0,0 +1,189 @@
+use crate::asm::{b_type, i_type, j_type, r_type, s_type, u_type};
+use crate::instruction::{self, opcode};
+
+/// Expands the 16-bit RVC instruction `halfword` to the 32-bit instruction
+/// word it is shorthand for, or returns `None` if it is illegal, reserved or
+/// belongs to an extension that is not implemented.
+///
+/// Registers written rd', rs1' and rs2' are 3-bit fields naming x8 to x15.
+pub fn expand(halfword: u16) -> Option<u32> {
+    let c = halfword as u32;
+    let funct3 = (c >> 13) & 0b111;
+    let rd = (c >> 7) & 0x1f;
+    let rs2 = (c >> 2) & 0x1f;
+    let rd_short = 8 + ((c >> 2) & 0b111);
+    let rs1_short = 8 + ((c >> 7) & 0b111);
+
+    let word = match (c & 0b11, funct3) {
+        // C.ADDI4SPN; the all-zero halfword is defined to be illegal.
+        (0b00, 0b000) => {
+            let imm = bits(c, 12, 11, 4) | bits(c, 10, 7, 6) | bits(c, 6, 6, 2) | bits(c, 5, 5, 3);
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, 0b000, rd_short, opcode::OP_IMM)
+        }
+        // C.FLD, which like the other double-precision forms expands to an
+        // instruction that is illegal without D.
+        (0b00, 0b001) => i_type(ld_offset(c), rs1_short, 0b011, rd_short, opcode::LOAD_FP),
+        // C.LW and C.LD.
+        (0b00, 0b010) => i_type(lw_offset(c), rs1_short, 0b010, rd_short, opcode::LOAD),
+        (0b00, 0b011) => i_type(ld_offset(c), rs1_short, 0b011, rd_short, opcode::LOAD),
+        // C.FSD.
+        (0b00, 0b101) => s_type(ld_offset(c), rd_short, rs1_short, 0b011, opcode::STORE_FP),
+        // C.SW and C.SD.
+        (0b00, 0b110) => s_type(lw_offset(c), rd_short, rs1_short, 0b010, opcode::STORE),
+        (0b00, 0b111) => s_type(ld_offset(c), rd_short, rs1_short, 0b011, opcode::STORE),
+        // C.ADDI, of which C.NOP is the rd = x0 case.
+        (0b01, 0b000) => i_type(imm6(c), rd, 0b000, rd, opcode::OP_IMM),
+        // C.ADDIW.
+        (0b01, 0b001) if rd != 0 => i_type(imm6(c), rd, 0b000, rd, opcode::OP_IMM_32),
+        // C.LI.
+        (0b01, 0b010) => i_type(imm6(c), 0, 0b000, rd, opcode::OP_IMM),
+        // C.ADDI16SP.
+        (0b01, 0b011) if rd == 2 => {
+            let imm = sign_extend(
+                bits(c, 12, 12, 9)
+                    | bits(c, 6, 6, 4)
+                    | bits(c, 5, 5, 6)
+                    | bits(c, 4, 3, 7)
+                    | bits(c, 2, 2, 5),
+                10,
+            );
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, 0b000, 2, opcode::OP_IMM)
+        }
+        // C.LUI.
+        (0b01, 0b011) => {
+            let imm = sign_extend(bits(c, 12, 12, 17) | bits(c, 6, 2, 12), 18);
+            if imm == 0 {
+                return None;
+            }
+            u_type(imm, rd, opcode::LUI)
+        }
+        (0b01, 0b100) => {
+            let shamt = bits(c, 12, 12, 5) | bits(c, 6, 2, 0);
+            match ((c >> 10) & 0b11, (c >> 12) & 1, (c >> 5) & 0b11) {
+                // C.SRLI, C.SRAI and C.ANDI.
+                (0b00, _, _) => i_type(shamt, rs1_short, 0b101, rs1_short, opcode::OP_IMM),
+                (0b01, _, _) => i_type(0x400 | shamt, rs1_short, 0b101, rs1_short, opcode::OP_IMM),
+                (0b10, _, _) => i_type(imm6(c), rs1_short, 0b111, rs1_short, opcode::OP_IMM),
+                // C.SUB, C.XOR, C.OR and C.AND.
+                (0b11, 0, op) => {
+                    let (funct7, funct3) =
+                        [(0x20, 0b000), (0, 0b100), (0, 0b110), (0, 0b111)][op as usize];
+                    r_type(funct7, rd_short, rs1_short, funct3, rs1_short, opcode::OP)
+                }
+                // C.SUBW and C.ADDW.
+                (0b11, 1, 0b00) => {
+                    r_type(0x20, rd_short, rs1_short, 0b000, rs1_short, opcode::OP_32)
+                }
+                (0b11, 1, 0b01) => r_type(0, rd_short, rs1_short, 0b000, rs1_short, opcode::OP_32),
+                _ => return None,
+            }
+        }
+        // C.J.
+        (0b01, 0b101) => {
+            let imm = sign_extend(
+                bits(c, 12, 12, 11)
+                    | bits(c, 11, 11, 4)
+                    | bits(c, 10, 9, 8)
+                    | bits(c, 8, 8, 10)
+                    | bits(c, 7, 7, 6)
+                    | bits(c, 6, 6, 7)
+                    | bits(c, 5, 3, 1)
+                    | bits(c, 2, 2, 5),
+                12,
+            );
+            j_type(imm, 0, opcode::JAL)
+        }
+        // C.BEQZ and C.BNEZ.
+        (0b01, 0b110 | 0b111) => {
+            let imm = sign_extend(
+                bits(c, 12, 12, 8)
+                    | bits(c, 11, 10, 3)
+                    | bits(c, 6, 5, 6)
+                    | bits(c, 4, 3, 1)
+                    | bits(c, 2, 2, 5),
+                9,
+            );
+            b_type(imm, 0, rs1_short, funct3 & 1, opcode::BRANCH)
+        }
+        // C.SLLI.
+        (0b10, 0b000) => {
+            let shamt = bits(c, 12, 12, 5) | bits(c, 6, 2, 0);
+            i_type(shamt, rd, 0b001, rd, opcode::OP_IMM)
+        }
+        // C.FLDSP, which unlike C.LDSP may load f0.
+        (0b10, 0b001) => {
+            let imm = bits(c, 12, 12, 5) | bits(c, 6, 5, 3) | bits(c, 4, 2, 6);
+            i_type(imm, 2, 0b011, rd, opcode::LOAD_FP)
+        }
+        // C.LWSP and C.LDSP.
+        (0b10, 0b010) if rd != 0 => {
+            let imm = bits(c, 12, 12, 5) | bits(c, 6, 4, 2) | bits(c, 3, 2, 6);
+            i_type(imm, 2, 0b010, rd, opcode::LOAD)
+        }
+        (0b10, 0b011) if rd != 0 => {
+            let imm = bits(c, 12, 12, 5) | bits(c, 6, 5, 3) | bits(c, 4, 2, 6);
+            i_type(imm, 2, 0b011, rd, opcode::LOAD)
+        }
+        (0b10, 0b100) => match ((c >> 12) & 1, rd, rs2) {
+            // C.JR and C.MV.
+            (0, 0, 0) => return None,
+            (0, _, 0) => i_type(0, rd, 0b000, 0, opcode::JALR),
+            (0, _, _) => r_type(0, rs2, 0, 0b000, rd, opcode::OP),
+            // C.EBREAK, C.JALR and C.ADD.
+            (_, 0, 0) => 0x0010_0073,
+            (_, _, 0) => i_type(0, rd, 0b000, 1, opcode::JALR),
+            (_, _, _) => r_type(0, rs2, rd, 0b000, rd, opcode::OP),
+        },
+        // C.FSDSP.
+        (0b10, 0b101) => {
+            let imm = bits(c, 12, 10, 3) | bits(c, 9, 7, 6);
+            s_type(imm, rs2, 2, 0b011, opcode::STORE_FP)
+        }
+        // C.SWSP and C.SDSP.
+        (0b10, 0b110) => {
+            let imm = bits(c, 12, 9, 2) | bits(c, 8, 7, 6);
+            s_type(imm, rs2, 2, 0b010, opcode::STORE)
+        }
+        (0b10, 0b111) => {
+            let imm = bits(c, 12, 10, 3) | bits(c, 9, 7, 6);
+            s_type(imm, rs2, 2, 0b011, opcode::STORE)
+        }
+        _ => return None,
+    };
+
+    Some(word)
+}
+
+/// Whether `halfword` is the first half of a compressed instruction rather
+/// than of a 32-bit one, whose low two bits are always `11`.
+pub fn is_compressed(halfword: u16) -> bool {
+    halfword & 0b11 != 0b11
+}
+
+/// Bits [hi:lo] of `c`, moved so that bit `lo` lands at bit `to`.
+fn bits(c: u32, hi: u32, lo: u32, to: u32) -> u32 {
+    instruction::bits(c, hi, lo) << to
+}
+
+fn sign_extend(value: u32, width: u32) -> u32 {
+    instruction::sign_extend(value, width) as u32
+}
+
+/// The sign-extended immediate in bits 12 and [6:2] of the CI format.
+fn imm6(c: u32) -> u32 {
+    sign_extend(bits(c, 12, 12, 5) | bits(c, 6, 2, 0), 6)
+}
+
+fn lw_offset(c: u32) -> u32 {
+    bits(c, 12, 10, 3) | bits(c, 6, 6, 2) | bits(c, 5, 5, 6)
+}
+
+fn ld_offset(c: u32) -> u32 {
+    bits(c, 12, 10, 3) | bits(c, 6, 5, 6)
+}
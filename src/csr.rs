@@ -0,0 +1,405 @@
+use crate::hart::PrivilegeMode;
+use crate::pmp::{self, PMPADDR_MASK};
+
+/// Number of addressable control and status registers.
+pub const CSR_COUNT: usize = 4096;
+
+pub const FFLAGS: usize = 0x001;
+pub const FRM: usize = 0x002;
+pub const FCSR: usize = 0x003;
+pub const SSTATUS: usize = 0x100;
+pub const STVEC: usize = 0x105;
+pub const SCOUNTEREN: usize = 0x106;
+pub const SSCRATCH: usize = 0x140;
+pub const SEPC: usize = 0x141;
+pub const SCAUSE: usize = 0x142;
+pub const STVAL: usize = 0x143;
+pub const SATP: usize = 0x180;
+pub const MSTATUS: usize = 0x300;
+pub const MISA: usize = 0x301;
+pub const MEDELEG: usize = 0x302;
+pub const MIDELEG: usize = 0x303;
+pub const MIE: usize = 0x304;
+pub const MTVEC: usize = 0x305;
+pub const MCOUNTEREN: usize = 0x306;
+pub const MSCRATCH: usize = 0x340;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+pub const MIP: usize = 0x344;
+/// The first of the `pmpcfg` registers, of which RV64 has the even-numbered
+/// ones up to 14.
+pub const PMPCFG0: usize = 0x3a0;
+pub const PMPCFG15: usize = 0x3af;
+pub const PMPADDR0: usize = 0x3b0;
+pub const PMPADDR63: usize = 0x3ef;
+pub const CYCLE: usize = 0xc00;
+pub const TIME: usize = 0xc01;
+pub const INSTRET: usize = 0xc02;
+pub const MVENDORID: usize = 0xf11;
+pub const MARCHID: usize = 0xf12;
+pub const MIMPID: usize = 0xf13;
+pub const MHARTID: usize = 0xf14;
+
+/// Accrued floating-point exception flags, in `fcsr`.
+pub const FCSR_FFLAGS: u64 = 0x1f;
+/// Dynamic rounding mode, in `fcsr`.
+pub const FCSR_FRM: u64 = 0b111 << 5;
+
+/// Supervisor interrupt enable.
+pub const MSTATUS_SIE: u64 = 1 << 1;
+/// Machine interrupt enable.
+pub const MSTATUS_MIE: u64 = 1 << 3;
+/// Supervisor interrupt enable before the current supervisor trap.
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+/// User-mode data accesses are big-endian.
+pub const MSTATUS_UBE: u64 = 1 << 6;
+/// Machine interrupt enable before the current trap.
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+/// Privilege mode before the current supervisor trap, User or Supervisor.
+pub const MSTATUS_SPP: u64 = 1 << 8;
+/// Privilege mode before the current trap.
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+/// State of the floating-point unit.
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+/// Permit Supervisor mode to access User pages.
+pub const MSTATUS_SUM: u64 = 1 << 18;
+/// Make executable pages readable.
+pub const MSTATUS_MXR: u64 = 1 << 19;
+/// Trap SFENCE.VMA and `satp` accesses in Supervisor mode.
+pub const MSTATUS_TVM: u64 = 1 << 20;
+/// Trap WFI below Machine mode.
+pub const MSTATUS_TW: u64 = 1 << 21;
+/// Trap SRET in Supervisor mode.
+pub const MSTATUS_TSR: u64 = 1 << 22;
+/// Supervisor-mode data accesses are big-endian.
+pub const MSTATUS_SBE: u64 = 1 << 36;
+/// Machine-mode data accesses are big-endian.
+pub const MSTATUS_MBE: u64 = 1 << 37;
+
+/// The `mstatus` fields that exist; the rest are reserved and read as zero.
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE
+    | MSTATUS_MIE
+    | MSTATUS_SPIE
+    | MSTATUS_MPIE
+    | MSTATUS_SPP
+    | MSTATUS_MPP
+    | MSTATUS_FS
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_TVM
+    | MSTATUS_TW
+    | MSTATUS_TSR
+    | MSTATUS_UBE
+    | MSTATUS_SBE
+    | MSTATUS_MBE;
+
+/// The `mstatus` fields visible to Supervisor mode through `sstatus`.
+const SSTATUS_MASK: u64 =
+    MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_UBE | MSTATUS_SPP | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+
+/// `mtvec` and `stvec` mode in which interrupts jump to `BASE + 4 * cause`.
+pub const MTVEC_VECTORED: u64 = 0b01;
+
+/// Set in `mcause` when the trap was caused by an interrupt.
+pub const MCAUSE_INTERRUPT: u64 = 1 << 63;
+
+/// Supervisor software interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_SSIP: u64 = 1 << 1;
+/// Supervisor timer interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_STIP: u64 = 1 << 5;
+/// Supervisor external interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_SEIP: u64 = 1 << 9;
+/// Machine software interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_MSIP: u64 = 1 << 3;
+/// Machine timer interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_MTIP: u64 = 1 << 7;
+/// Machine external interrupt pending, in `mip`, and enable, in `mie`.
+pub const MIP_MEIP: u64 = 1 << 11;
+
+/// The value of `mstatus`, with typed access to its fields.
+///
+/// Values are always legal: reserved bits are zero and MPP never holds the
+/// reserved encoding `0b10`, which is replaced by User mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mstatus(u64);
+
+impl Mstatus {
+    /// Legalizes a raw value written to `mstatus`.
+    pub fn from_bits(bits: u64) -> Self {
+        let mut mstatus = Mstatus(bits & MSTATUS_WRITABLE);
+        mstatus.set_mpp(PrivilegeMode::from_bits(bits >> 11));
+        mstatus
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn sie(self) -> bool {
+        self.0 & MSTATUS_SIE != 0
+    }
+
+    pub fn set_sie(&mut self, enabled: bool) {
+        self.set(MSTATUS_SIE, enabled);
+    }
+
+    pub fn mie(self) -> bool {
+        self.0 & MSTATUS_MIE != 0
+    }
+
+    pub fn set_mie(&mut self, enabled: bool) {
+        self.set(MSTATUS_MIE, enabled);
+    }
+
+    pub fn spie(self) -> bool {
+        self.0 & MSTATUS_SPIE != 0
+    }
+
+    pub fn set_spie(&mut self, enabled: bool) {
+        self.set(MSTATUS_SPIE, enabled);
+    }
+
+    pub fn mpie(self) -> bool {
+        self.0 & MSTATUS_MPIE != 0
+    }
+
+    pub fn set_mpie(&mut self, enabled: bool) {
+        self.set(MSTATUS_MPIE, enabled);
+    }
+
+    pub fn spp(self) -> PrivilegeMode {
+        if self.0 & MSTATUS_SPP != 0 {
+            PrivilegeMode::Supervisor
+        } else {
+            PrivilegeMode::User
+        }
+    }
+
+    /// Sets SPP, which only holds User or Supervisor mode, so Machine mode
+    /// is recorded as Supervisor.
+    pub fn set_spp(&mut self, mode: PrivilegeMode) {
+        self.set(MSTATUS_SPP, mode != PrivilegeMode::User);
+    }
+
+    pub fn mpp(self) -> PrivilegeMode {
+        PrivilegeMode::from_bits((self.0 & MSTATUS_MPP) >> 11)
+    }
+
+    pub fn set_mpp(&mut self, mode: PrivilegeMode) {
+        self.0 = (self.0 & !MSTATUS_MPP) | (mode as u64) << 11;
+    }
+
+    pub fn sum(self) -> bool {
+        self.0 & MSTATUS_SUM != 0
+    }
+
+    pub fn mxr(self) -> bool {
+        self.0 & MSTATUS_MXR != 0
+    }
+
+    pub fn tvm(self) -> bool {
+        self.0 & MSTATUS_TVM != 0
+    }
+
+    pub fn tw(self) -> bool {
+        self.0 & MSTATUS_TW != 0
+    }
+
+    pub fn tsr(self) -> bool {
+        self.0 & MSTATUS_TSR != 0
+    }
+
+    /// Whether data accesses made in `mode` are big-endian, as selected by
+    /// UBE, SBE or MBE.
+    pub fn big_endian(self, mode: PrivilegeMode) -> bool {
+        let bit = match mode {
+            PrivilegeMode::User => MSTATUS_UBE,
+            PrivilegeMode::Supervisor => MSTATUS_SBE,
+            PrivilegeMode::Machine => MSTATUS_MBE,
+        };
+        self.0 & bit != 0
+    }
+
+    fn set(&mut self, mask: u64, on: bool) {
+        if on {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
+}
+
+/// The control and status register file of a hart.
+pub struct Csr {
+    regs: Vec<u64>,
+}
+
+impl Csr {
+    pub fn new() -> Self {
+        Csr {
+            regs: vec![0; CSR_COUNT],
+        }
+    }
+
+    pub fn mstatus(&self) -> Mstatus {
+        Mstatus(self.regs[MSTATUS])
+    }
+
+    pub fn set_mstatus(&mut self, mstatus: Mstatus) {
+        self.regs[MSTATUS] = mstatus.bits();
+    }
+
+    /// Reads a CSR. `fflags` and `frm` are views of fields in `fcsr`, and
+    /// `sstatus` is a view of the Supervisor fields of `mstatus`.
+    pub fn read(&self, addr: usize) -> u64 {
+        match addr {
+            FFLAGS => self.regs[FCSR] & FCSR_FFLAGS,
+            FRM => (self.regs[FCSR] & FCSR_FRM) >> 5,
+            SSTATUS => self.regs[MSTATUS] & SSTATUS_MASK,
+            _ => self.regs[addr],
+        }
+    }
+
+    /// Writes a CSR, keeping only the bits of `fcsr` and `mstatus` that
+    /// exist. Writes to `sstatus` leave the Machine fields of `mstatus` as
+    /// they are. Only supervisor interrupts can be delegated, and environment
+    /// calls from Machine mode cannot. Locked PMP entries are left as they
+    /// are.
+    pub fn write(&mut self, addr: usize, value: u64) {
+        match addr {
+            FFLAGS => self.regs[FCSR] = (self.regs[FCSR] & !FCSR_FFLAGS) | (value & FCSR_FFLAGS),
+            FRM => self.regs[FCSR] = (self.regs[FCSR] & !FCSR_FRM) | ((value << 5) & FCSR_FRM),
+            FCSR => self.regs[FCSR] = value & (FCSR_FRM | FCSR_FFLAGS),
+            MSTATUS => self.regs[MSTATUS] = Mstatus::from_bits(value).bits(),
+            SSTATUS => {
+                let mstatus = (self.regs[MSTATUS] & !SSTATUS_MASK) | (value & SSTATUS_MASK);
+                self.regs[MSTATUS] = Mstatus::from_bits(mstatus).bits();
+            }
+            MEDELEG => self.regs[MEDELEG] = value & !(1 << 11),
+            MIDELEG => self.regs[MIDELEG] = value & (MIP_SSIP | MIP_STIP | MIP_SEIP),
+            PMPCFG0..=PMPCFG15 => self.regs[addr] = pmp::write_config(self.regs[addr], value),
+            PMPADDR0..=PMPADDR63 if pmp::addr_locked(self, addr - PMPADDR0) => {}
+            PMPADDR0..=PMPADDR63 => self.regs[addr] = value & PMPADDR_MASK,
+            _ => self.regs[addr] = value,
+        }
+    }
+
+    /// Every CSR, indexed by address.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.regs
+    }
+
+    /// Every CSR, indexed by address, for restoring saved values as they
+    /// are, without the legalization of [`Csr::write`].
+    pub fn as_mut_slice(&mut self) -> &mut [u64] {
+        &mut self.regs
+    }
+
+    /// The lowest privilege mode that may access a CSR, encoded in bits
+    /// [9:8] of its address. There is no hypervisor, so its CSRs, encoded
+    /// as `0b10`, are left to Machine mode.
+    pub fn min_privilege(addr: usize) -> PrivilegeMode {
+        match (addr >> 8) & 0b11 {
+            0b00 => PrivilegeMode::User,
+            0b01 => PrivilegeMode::Supervisor,
+            _ => PrivilegeMode::Machine,
+        }
+    }
+
+    /// CSRs whose top two address bits are set are read-only.
+    pub fn is_read_only(addr: usize) -> bool {
+        (addr >> 10) & 0b11 == 0b11
+    }
+
+    /// Whether the hart implements CSR `addr`. Accessing any other CSR is an
+    /// illegal instruction. RV64 has no odd-numbered `pmpcfg` registers.
+    pub fn is_implemented(addr: usize) -> bool {
+        match addr {
+            FFLAGS | FRM | FCSR => true,
+            SSTATUS | STVEC | SCOUNTEREN | SSCRATCH | SEPC | SCAUSE | STVAL | SATP => true,
+            MSTATUS | MISA | MEDELEG | MIDELEG | MIE | MTVEC | MCOUNTEREN => true,
+            MSCRATCH | MEPC | MCAUSE | MTVAL | MIP => true,
+            PMPCFG0..=PMPCFG15 => addr.is_multiple_of(2),
+            PMPADDR0..=PMPADDR63 => true,
+            CYCLE | TIME | INSTRET => true,
+            MVENDORID | MARCHID | MIMPID | MHARTID => true,
+            _ => false,
+        }
+    }
+}
+
+impl Default for Csr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_mpp_reads_back_as_user_mode() {
+        let mut csr = Csr::new();
+        csr.write(MSTATUS, 0b10 << 11);
+        assert_eq!(csr.mstatus().mpp(), PrivilegeMode::User);
+        assert_eq!(csr.read(MSTATUS) & MSTATUS_MPP, 0);
+
+        csr.write(MSTATUS, MSTATUS_MPP);
+        assert_eq!(csr.mstatus().mpp(), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn wpri_bits_of_mstatus_stay_zero() {
+        let mut csr = Csr::new();
+        csr.write(MSTATUS, u64::MAX);
+        assert_eq!(csr.read(MSTATUS), MSTATUS_WRITABLE);
+        // Bits 2 and 4, and 23 to 31, are among the reserved ones.
+        assert_eq!(csr.read(MSTATUS) & (1 << 2 | 1 << 4 | 0xff80_0000), 0);
+
+        // Writing through sstatus cannot set them either.
+        csr.write(MSTATUS, 0);
+        csr.write(SSTATUS, u64::MAX);
+        assert_eq!(csr.read(MSTATUS), SSTATUS_MASK);
+    }
+
+    #[test]
+    fn sstatus_writes_only_touch_supervisor_fields() {
+        let mut csr = Csr::new();
+        csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_MPP | MSTATUS_TSR);
+
+        csr.write(SSTATUS, MSTATUS_SIE | MSTATUS_MIE);
+        assert!(csr.mstatus().sie());
+        assert!(csr.mstatus().mie());
+        assert_eq!(csr.mstatus().mpp(), PrivilegeMode::Machine);
+        assert!(csr.mstatus().tsr());
+        // Machine-only fields are hidden from sstatus reads.
+        assert_eq!(csr.read(SSTATUS), MSTATUS_SIE);
+
+        csr.write(SSTATUS, 0);
+        assert!(!csr.mstatus().sie());
+        assert!(csr.mstatus().mie());
+    }
+
+    #[test]
+    fn supervisor_trap_registers_are_their_own() {
+        let mut csr = Csr::new();
+        for (i, addr) in [STVEC, SEPC, SCAUSE, STVAL, SSCRATCH]
+            .into_iter()
+            .enumerate()
+        {
+            csr.write(addr, 0x100 + i as u64);
+        }
+
+        for (i, addr) in [STVEC, SEPC, SCAUSE, STVAL, SSCRATCH]
+            .into_iter()
+            .enumerate()
+        {
+            assert_eq!(csr.read(addr), 0x100 + i as u64);
+        }
+        for addr in [MTVEC, MEPC, MCAUSE, MTVAL, MSCRATCH] {
+            assert_eq!(csr.read(addr), 0);
+        }
+    }
+}
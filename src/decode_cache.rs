@@ -0,0 +1,91 @@
+use crate::hart::Handler;
+use crate::instruction::InstructionBytes;
+
+/// Number of entries in the cache; must be a power of two.
+const ENTRIES: usize = 4096;
+
+/// A direct-mapped cache of fetched instructions and the handlers that
+/// execute them, indexed by the physical address they were fetched from.
+///
+/// Only stores made through the hart invalidate entries. Code written to
+/// memory by other means, such as directly through the bus, needs a
+/// [`DecodeCache::flush`] before it runs.
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    addr: u64,
+    word: InstructionBytes,
+    handler: Handler,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        DecodeCache {
+            entries: vec![None; ENTRIES],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Looks up the instruction fetched from `addr`, counting a hit or miss.
+    pub(crate) fn get(&mut self, addr: u64) -> Option<(InstructionBytes, Handler)> {
+        match self.entries[index(addr)] {
+            Some(entry) if entry.addr == addr => {
+                self.hits += 1;
+                Some((entry.word, entry.handler))
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, addr: u64, word: InstructionBytes, handler: Handler) {
+        self.entries[index(addr)] = Some(Entry {
+            addr,
+            word,
+            handler,
+        });
+    }
+
+    /// Drops any instructions overlapping the `len` bytes written at `addr`.
+    ///
+    /// Instructions start on any halfword, and a 4-byte one may begin in the
+    /// halfword before the write.
+    pub fn invalidate(&mut self, addr: u64, len: usize) {
+        let first = (addr & !0b1).wrapping_sub(2);
+        let last = addr.wrapping_add(len as u64 - 1) & !0b1;
+        let mut half = first;
+        loop {
+            let slot = &mut self.entries[index(half)];
+            if slot.is_some_and(|entry| entry.addr == half) {
+                *slot = None;
+            }
+            if half == last {
+                break;
+            }
+            half = half.wrapping_add(2);
+        }
+    }
+
+    /// Drops every cached instruction.
+    pub fn flush(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn index(addr: u64) -> usize {
+    (addr >> 1) as usize & (ENTRIES - 1)
+}
@@ -0,0 +1,513 @@
+use crate::hart::{reg_name, FP_ABI_NAMES};
+use crate::instruction::{
+    opcode, BFormat, CsrFormat, FenceFormat, IFormat, ISType, Instruction, JFormat, R4Format,
+    RFormat, SFormat, UFormat,
+};
+
+/// Renders `inst`, located at `pc`, in assembly syntax with ABI register
+/// names. Branch and jump targets are shown as absolute addresses.
+pub fn disassemble(inst: &Instruction, pc: u64) -> String {
+    match inst {
+        Instruction::R(inst) => disassemble_r(inst),
+        Instruction::R4(inst) => disassemble_r4(inst),
+        Instruction::I(inst) => disassemble_i(inst),
+        Instruction::IS(inst) => disassemble_is(inst),
+        Instruction::S(inst) => disassemble_s(inst),
+        Instruction::B(inst) => disassemble_b(inst, pc),
+        Instruction::U(inst) => disassemble_u(inst),
+        Instruction::J(inst) => disassemble_j(inst, pc),
+        Instruction::Csr(inst) => disassemble_csr(inst),
+        Instruction::Fence(inst) => disassemble_fence(inst),
+        Instruction::FenceI => "fence.i".to_string(),
+        Instruction::Ecall => "ecall".to_string(),
+        Instruction::Ebreak => "ebreak".to_string(),
+        Instruction::Mret => "mret".to_string(),
+        Instruction::Sret => "sret".to_string(),
+        Instruction::Wfi => "wfi".to_string(),
+        Instruction::SfenceVma(inst) => {
+            format!("sfence.vma {}, {}", reg(inst.rs1), reg(inst.rs2))
+        }
+    }
+}
+
+/// The assembler mnemonic of `inst`, such as `addi` or `fcvt.w.s`.
+pub fn mnemonic(inst: &Instruction) -> &'static str {
+    match inst {
+        Instruction::R(inst) => r_mnemonic(inst),
+        Instruction::R4(inst) => r4_mnemonic(inst),
+        Instruction::I(inst) => i_mnemonic(inst),
+        Instruction::IS(inst) => is_mnemonic(inst),
+        Instruction::S(inst) => s_mnemonic(inst),
+        Instruction::B(inst) => b_mnemonic(inst),
+        Instruction::U(inst) => u_mnemonic(inst),
+        Instruction::J(_) => "jal",
+        Instruction::Csr(inst) => csr_mnemonic(inst),
+        Instruction::Fence(inst) if inst.fm == 0b1000 => "fence.tso",
+        Instruction::Fence(_) => "fence",
+        Instruction::FenceI => "fence.i",
+        Instruction::Ecall => "ecall",
+        Instruction::Ebreak => "ebreak",
+        Instruction::Mret => "mret",
+        Instruction::Sret => "sret",
+        Instruction::Wfi => "wfi",
+        Instruction::SfenceVma(_) => "sfence.vma",
+    }
+}
+
+fn reg(i: usize) -> &'static str {
+    reg_name(i)
+}
+
+fn freg(i: usize) -> &'static str {
+    FP_ABI_NAMES[i]
+}
+
+/// The absolute address `offset` bytes from `pc`.
+fn target(pc: u64, offset: i32) -> u64 {
+    pc.wrapping_add(offset as i64 as u64)
+}
+
+fn disassemble_r(inst: &RFormat) -> String {
+    if inst.opcode == opcode::OP_FP {
+        return disassemble_op_fp(inst);
+    }
+    if inst.opcode == opcode::AMO {
+        return disassemble_amo(inst);
+    }
+
+    format!(
+        "{} {}, {}, {}",
+        r_mnemonic(inst),
+        reg(inst.rd),
+        reg(inst.rs1),
+        reg(inst.rs2)
+    )
+}
+
+fn r_mnemonic(inst: &RFormat) -> &'static str {
+    if inst.opcode == opcode::OP_FP {
+        return fp_mnemonic(inst);
+    }
+    if inst.opcode == opcode::AMO {
+        return amo_mnemonic(inst);
+    }
+
+    match (inst.opcode, inst.funct7, inst.funct3) {
+        (opcode::OP, 0x00, 0x0) => "add",
+        (opcode::OP, 0x20, 0x0) => "sub",
+        (opcode::OP, 0x00, 0x1) => "sll",
+        (opcode::OP, 0x00, 0x2) => "slt",
+        (opcode::OP, 0x00, 0x3) => "sltu",
+        (opcode::OP, 0x00, 0x4) => "xor",
+        (opcode::OP, 0x00, 0x5) => "srl",
+        (opcode::OP, 0x20, 0x5) => "sra",
+        (opcode::OP, 0x00, 0x6) => "or",
+        (opcode::OP, 0x00, 0x7) => "and",
+        (opcode::OP, 0x01, 0x0) => "mul",
+        (opcode::OP, 0x01, 0x1) => "mulh",
+        (opcode::OP, 0x01, 0x2) => "mulhsu",
+        (opcode::OP, 0x01, 0x3) => "mulhu",
+        (opcode::OP, 0x01, 0x4) => "div",
+        (opcode::OP, 0x01, 0x5) => "divu",
+        (opcode::OP, 0x01, 0x6) => "rem",
+        (opcode::OP, 0x01, 0x7) => "remu",
+        (opcode::OP_32, 0x00, 0x0) => "addw",
+        (opcode::OP_32, 0x20, 0x0) => "subw",
+        (opcode::OP_32, 0x00, 0x1) => "sllw",
+        (opcode::OP_32, 0x00, 0x5) => "srlw",
+        (opcode::OP_32, 0x20, 0x5) => "sraw",
+        (opcode::OP_32, 0x01, 0x0) => "mulw",
+        (opcode::OP_32, 0x01, 0x4) => "divw",
+        (opcode::OP_32, 0x01, 0x5) => "divuw",
+        (opcode::OP_32, 0x01, 0x6) => "remw",
+        (opcode::OP_32, 0x01, 0x7) => "remuw",
+        _ => unreachable!("invalid R-type instruction"),
+    }
+}
+
+/// Atomics take the address in parentheses, with the aq and rl ordering
+/// bits as a suffix.
+fn disassemble_amo(inst: &RFormat) -> String {
+    let ordering = ["", ".rl", ".aq", ".aqrl"][inst.funct7 as usize & 0b11];
+    let (rd, rs1) = (reg(inst.rd), reg(inst.rs1));
+    match inst.funct7 >> 2 {
+        0b00010 => format!("{}{} {}, ({})", amo_mnemonic(inst), ordering, rd, rs1),
+        _ => format!(
+            "{}{} {}, {}, ({})",
+            amo_mnemonic(inst),
+            ordering,
+            rd,
+            reg(inst.rs2),
+            rs1
+        ),
+    }
+}
+
+fn amo_mnemonic(inst: &RFormat) -> &'static str {
+    let word = inst.funct3 == 0x2;
+    match inst.funct7 >> 2 {
+        0b00010 if word => "lr.w",
+        0b00010 => "lr.d",
+        0b00011 if word => "sc.w",
+        0b00011 => "sc.d",
+        0b00000 if word => "amoadd.w",
+        0b00000 => "amoadd.d",
+        0b00001 if word => "amoswap.w",
+        0b00001 => "amoswap.d",
+        0b00100 if word => "amoxor.w",
+        0b00100 => "amoxor.d",
+        0b01000 if word => "amoor.w",
+        0b01000 => "amoor.d",
+        0b01100 if word => "amoand.w",
+        0b01100 => "amoand.d",
+        0b10000 if word => "amomin.w",
+        0b10000 => "amomin.d",
+        0b10100 if word => "amomax.w",
+        0b10100 => "amomax.d",
+        0b11000 if word => "amominu.w",
+        0b11000 => "amominu.d",
+        0b11100 if word => "amomaxu.w",
+        0b11100 => "amomaxu.d",
+        _ => unreachable!("invalid AMO instruction"),
+    }
+}
+
+fn disassemble_r4(inst: &R4Format) -> String {
+    let operands = format!(
+        "{} {}, {}, {}, {}",
+        r4_mnemonic(inst),
+        freg(inst.rd),
+        freg(inst.rs1),
+        freg(inst.rs2),
+        freg(inst.rs3)
+    );
+    match rounding_mode(inst.funct3) {
+        Some(rm) => format!("{}, {}", operands, rm),
+        None => operands,
+    }
+}
+
+fn r4_mnemonic(inst: &R4Format) -> &'static str {
+    let single = inst.fmt == 0b00;
+    match inst.opcode {
+        opcode::MADD if single => "fmadd.s",
+        opcode::MADD => "fmadd.d",
+        opcode::MSUB if single => "fmsub.s",
+        opcode::MSUB => "fmsub.d",
+        opcode::NMSUB if single => "fnmsub.s",
+        opcode::NMSUB => "fnmsub.d",
+        opcode::NMADD if single => "fnmadd.s",
+        opcode::NMADD => "fnmadd.d",
+        _ => unreachable!("invalid fused multiply-add"),
+    }
+}
+
+fn disassemble_op_fp(inst: &RFormat) -> String {
+    let mnemonic = fp_mnemonic(inst);
+    let (rd, rs1, rs2) = (freg(inst.rd), freg(inst.rs1), freg(inst.rs2));
+    let operands = match inst.funct7 {
+        0x2c | 0x2d | 0x20 | 0x21 => format!("{} {}, {}", mnemonic, rd, rs1),
+        0x60 | 0x61 => format!("{} {}, {}", mnemonic, reg(inst.rd), rs1),
+        0x68 | 0x69 => format!("{} {}, {}", mnemonic, rd, reg(inst.rs1)),
+        0x50 | 0x51 => return format!("{} {}, {}, {}", mnemonic, reg(inst.rd), rs1, rs2),
+        // These have no rounding mode.
+        0x10 | 0x11 | 0x14 | 0x15 => return format!("{} {}, {}, {}", mnemonic, rd, rs1, rs2),
+        0x70 | 0x71 => return format!("{} {}, {}", mnemonic, reg(inst.rd), rs1),
+        0x78 | 0x79 => return format!("{} {}, {}", mnemonic, rd, reg(inst.rs1)),
+        _ => format!("{} {}, {}, {}", mnemonic, rd, rs1, rs2),
+    };
+
+    // The dynamic rounding mode is left implicit, as assemblers do.
+    match rounding_mode(inst.funct3) {
+        Some(rm) => format!("{}, {}", operands, rm),
+        None => operands,
+    }
+}
+
+fn fp_mnemonic(inst: &RFormat) -> &'static str {
+    match (inst.funct7, inst.funct3, inst.rs2) {
+        (0x00, _, _) => "fadd.s",
+        (0x04, _, _) => "fsub.s",
+        (0x08, _, _) => "fmul.s",
+        (0x0c, _, _) => "fdiv.s",
+        (0x2c, _, _) => "fsqrt.s",
+        (0x01, _, _) => "fadd.d",
+        (0x05, _, _) => "fsub.d",
+        (0x09, _, _) => "fmul.d",
+        (0x0d, _, _) => "fdiv.d",
+        (0x2d, _, _) => "fsqrt.d",
+        (0x10, 0b000, _) => "fsgnj.s",
+        (0x10, 0b001, _) => "fsgnjn.s",
+        (0x10, _, _) => "fsgnjx.s",
+        (0x11, 0b000, _) => "fsgnj.d",
+        (0x11, 0b001, _) => "fsgnjn.d",
+        (0x11, _, _) => "fsgnjx.d",
+        (0x14, 0b000, _) => "fmin.s",
+        (0x14, _, _) => "fmax.s",
+        (0x15, 0b000, _) => "fmin.d",
+        (0x15, _, _) => "fmax.d",
+        // FCVT selects the integer type with rs2.
+        (0x60, _, 0) => "fcvt.w.s",
+        (0x60, _, 1) => "fcvt.wu.s",
+        (0x60, _, 2) => "fcvt.l.s",
+        (0x60, _, 3) => "fcvt.lu.s",
+        (0x68, _, 0) => "fcvt.s.w",
+        (0x68, _, 1) => "fcvt.s.wu",
+        (0x68, _, 2) => "fcvt.s.l",
+        (0x68, _, 3) => "fcvt.s.lu",
+        (0x61, _, 0) => "fcvt.w.d",
+        (0x61, _, 1) => "fcvt.wu.d",
+        (0x61, _, 2) => "fcvt.l.d",
+        (0x61, _, 3) => "fcvt.lu.d",
+        (0x69, _, 0) => "fcvt.d.w",
+        (0x69, _, 1) => "fcvt.d.wu",
+        (0x69, _, 2) => "fcvt.d.l",
+        (0x69, _, 3) => "fcvt.d.lu",
+        (0x50, 0b000, _) => "fle.s",
+        (0x50, 0b001, _) => "flt.s",
+        (0x50, _, _) => "feq.s",
+        (0x51, 0b000, _) => "fle.d",
+        (0x51, 0b001, _) => "flt.d",
+        (0x51, _, _) => "feq.d",
+        (0x70, 0b000, _) => "fmv.x.w",
+        (0x70, _, _) => "fclass.s",
+        (0x71, 0b000, _) => "fmv.x.d",
+        (0x71, _, _) => "fclass.d",
+        (0x78, _, _) => "fmv.w.x",
+        (0x79, _, _) => "fmv.d.x",
+        (0x20, _, _) => "fcvt.s.d",
+        (0x21, _, _) => "fcvt.d.s",
+        _ => unreachable!("invalid floating-point instruction"),
+    }
+}
+
+/// The assembler name of a static rounding mode.
+fn rounding_mode(rm: u32) -> Option<&'static str> {
+    match rm {
+        0b000 => Some("rne"),
+        0b001 => Some("rtz"),
+        0b010 => Some("rdn"),
+        0b011 => Some("rup"),
+        0b100 => Some("rmm"),
+        _ => None,
+    }
+}
+
+fn disassemble_i(inst: &IFormat) -> String {
+    let mnemonic = i_mnemonic(inst);
+    let (rd, rs1, imm) = (reg(inst.rd), reg(inst.rs1), inst.imm);
+    match inst.opcode {
+        opcode::LOAD | opcode::JALR => format!("{} {}, {}({})", mnemonic, rd, imm, rs1),
+        opcode::LOAD_FP => format!("{} {}, {}({})", mnemonic, freg(inst.rd), imm, rs1),
+        _ => format!("{} {}, {}, {}", mnemonic, rd, rs1, imm),
+    }
+}
+
+fn i_mnemonic(inst: &IFormat) -> &'static str {
+    match inst.opcode {
+        opcode::LOAD => match inst.funct3 {
+            0x0 => "lb",
+            0x1 => "lh",
+            0x2 => "lw",
+            0x3 => "ld",
+            0x4 => "lbu",
+            0x5 => "lhu",
+            0x6 => "lwu",
+            _ => unreachable!("invalid load width"),
+        },
+        opcode::LOAD_FP if inst.funct3 == 0x3 => "fld",
+        opcode::LOAD_FP => "flw",
+        opcode::JALR => "jalr",
+        opcode::OP_IMM_32 => "addiw",
+        _ => match inst.funct3 {
+            0x0 => "addi",
+            0x2 => "slti",
+            0x3 => "sltiu",
+            0x4 => "xori",
+            0x6 => "ori",
+            0x7 => "andi",
+            _ => unreachable!("invalid OP-IMM instruction"),
+        },
+    }
+}
+
+fn disassemble_is(inst: &ISType) -> String {
+    format!(
+        "{} {}, {}, {}",
+        is_mnemonic(inst),
+        reg(inst.rd),
+        reg(inst.rs1),
+        inst.shamt
+    )
+}
+
+fn is_mnemonic(inst: &ISType) -> &'static str {
+    match (inst.opcode, inst.funct3, inst.funct6) {
+        (opcode::OP_IMM, 0x1, _) => "slli",
+        (opcode::OP_IMM, 0x5, 0x10) => "srai",
+        (opcode::OP_IMM, 0x5, _) => "srli",
+        (opcode::OP_IMM_32, 0x1, _) => "slliw",
+        (opcode::OP_IMM_32, 0x5, 0x10) => "sraiw",
+        (opcode::OP_IMM_32, 0x5, _) => "srliw",
+        _ => unreachable!("invalid shift-immediate instruction"),
+    }
+}
+
+fn disassemble_s(inst: &SFormat) -> String {
+    let rs2 = if inst.opcode == opcode::STORE_FP {
+        freg(inst.rs2)
+    } else {
+        reg(inst.rs2)
+    };
+
+    format!(
+        "{} {}, {}({})",
+        s_mnemonic(inst),
+        rs2,
+        inst.imm,
+        reg(inst.rs1)
+    )
+}
+
+fn s_mnemonic(inst: &SFormat) -> &'static str {
+    if inst.opcode == opcode::STORE_FP {
+        return if inst.funct3 == 0x3 { "fsd" } else { "fsw" };
+    }
+
+    match inst.funct3 {
+        0x0 => "sb",
+        0x1 => "sh",
+        0x2 => "sw",
+        0x3 => "sd",
+        _ => unreachable!("invalid store width"),
+    }
+}
+
+fn disassemble_b(inst: &BFormat, pc: u64) -> String {
+    format!(
+        "{} {}, {}, {:#x}",
+        b_mnemonic(inst),
+        reg(inst.rs1),
+        reg(inst.rs2),
+        target(pc, inst.imm)
+    )
+}
+
+fn b_mnemonic(inst: &BFormat) -> &'static str {
+    match inst.funct3 {
+        0x0 => "beq",
+        0x1 => "bne",
+        0x4 => "blt",
+        0x5 => "bge",
+        0x6 => "bltu",
+        0x7 => "bgeu",
+        _ => unreachable!("invalid branch condition"),
+    }
+}
+
+fn disassemble_u(inst: &UFormat) -> String {
+    format!(
+        "{} {}, {:#x}",
+        u_mnemonic(inst),
+        reg(inst.rd),
+        (inst.imm as u32) >> 12
+    )
+}
+
+fn u_mnemonic(inst: &UFormat) -> &'static str {
+    match inst.opcode {
+        opcode::LUI => "lui",
+        _ => "auipc",
+    }
+}
+
+fn disassemble_j(inst: &JFormat, pc: u64) -> String {
+    format!("jal {}, {:#x}", reg(inst.rd), target(pc, inst.imm))
+}
+
+fn disassemble_csr(inst: &CsrFormat) -> String {
+    let mnemonic = csr_mnemonic(inst);
+
+    // The immediate forms encode a 5-bit value in place of rs1.
+    if inst.funct3 & 0x4 != 0 {
+        format!(
+            "{} {}, {:#x}, {}",
+            mnemonic,
+            reg(inst.rd),
+            inst.csr,
+            inst.rs1
+        )
+    } else {
+        format!(
+            "{} {}, {:#x}, {}",
+            mnemonic,
+            reg(inst.rd),
+            inst.csr,
+            reg(inst.rs1)
+        )
+    }
+}
+
+fn csr_mnemonic(inst: &CsrFormat) -> &'static str {
+    match inst.funct3 {
+        0x1 => "csrrw",
+        0x2 => "csrrs",
+        0x3 => "csrrc",
+        0x5 => "csrrwi",
+        0x6 => "csrrsi",
+        0x7 => "csrrci",
+        _ => unreachable!("invalid CSR instruction"),
+    }
+}
+
+fn disassemble_fence(inst: &FenceFormat) -> String {
+    if inst.fm == 0b1000 {
+        return "fence.tso".to_string();
+    }
+
+    format!("fence {}, {}", fence_set(inst.pred), fence_set(inst.succ))
+}
+
+/// Renders a FENCE predecessor or successor set as a subset of `iorw`.
+fn fence_set(bits: u32) -> String {
+    "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| bits & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::instruction::InstructionBytes;
+
+    fn disassemble_word(word: u32, pc: u64) -> String {
+        let inst = Instruction::try_from(InstructionBytes(word)).unwrap();
+        disassemble(&inst, pc)
+    }
+
+    #[test]
+    fn disassembles_each_format() {
+        let pc = 0x8000_0000;
+        for (word, text) in [
+            (asm::addi(1, 2, -4), "addi ra, sp, -4"),
+            (asm::add(10, 11, 12), "add a0, a1, a2"),
+            (asm::slli(5, 6, 63), "slli t0, t1, 63"),
+            (asm::sd(1, 2, -8), "sd ra, -8(sp)"),
+            (asm::lw(8, 2, 16), "lw s0, 16(sp)"),
+            (asm::beq(5, 6, 0x100), "beq t0, t1, 0x80000100"),
+            (asm::bne(5, 6, -8), "bne t0, t1, 0x7ffffff8"),
+            (asm::jal(1, 0x20), "jal ra, 0x80000020"),
+            (asm::lui(10, 0x12345), "lui a0, 0x12345"),
+            (asm::csrrs(10, 0x300, 0), "csrrs a0, 0x300, zero"),
+            (asm::csrrwi(0, 0x340, 5), "csrrwi zero, 0x340, 5"),
+            (0x0ff0_000f, "fence iorw, iorw"),
+            (asm::ecall(), "ecall"),
+        ] {
+            assert_eq!(disassemble_word(word, pc), text);
+        }
+    }
+}
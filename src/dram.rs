@@ -0,0 +1,153 @@
+use crate::exception::InstructionException;
+
+/// Physical address at which main memory starts.
+pub const DRAM_BASE: u64 = 0x8000_0000;
+/// Default size of the main memory, 128 MiB.
+pub const DRAM_SIZE: usize = 1024 * 1024 * 128;
+
+/// Parses a memory size such as `4096`, `128K`, `64M` or `1G`. The suffixes
+/// are binary multiples and case-insensitive.
+pub fn parse_size(size: &str) -> Option<usize> {
+    let (digits, shift) = match size.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&size[..size.len() - 1], 10),
+        b'M' => (&size[..size.len() - 1], 20),
+        b'G' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// Main memory, mapped at a fixed physical base address.
+pub struct Dram {
+    base: u64,
+    data: Vec<u8>,
+}
+
+impl Dram {
+    /// Creates `size` bytes of zeroed memory at `base`, with `code` copied to
+    /// its start.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is larger than `size`.
+    pub fn new(base: u64, size: usize, code: &[u8]) -> Self {
+        assert!(code.len() <= size, "code does not fit in memory");
+
+        let mut data = vec![0; size];
+        data[..code.len()].copy_from_slice(code);
+
+        Dram { base, data }
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// The contents of memory, starting at `base`.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Whether all `len` bytes at physical address `addr` are backed by this
+    /// memory.
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        self.offset(addr, len).is_some()
+    }
+
+    /// Reads `size` bytes (1, 2, 4 or 8) at `addr` as a little-endian value.
+    pub fn load(&self, addr: u64, size: usize) -> Result<u64, InstructionException> {
+        let offset = self
+            .offset(addr, size)
+            .ok_or(InstructionException::LoadAccessFault(addr))?;
+
+        Ok(self.data[offset..offset + size]
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    /// Writes the low `size` bytes (1, 2, 4 or 8) of `value` to `addr` in
+    /// little-endian order.
+    pub fn store(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), InstructionException> {
+        let offset = self
+            .offset(addr, size)
+            .ok_or(InstructionException::StoreAccessFault(addr))?;
+        self.data[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+
+        Ok(())
+    }
+
+    /// Copies `bytes` to physical address `addr`.
+    pub fn write_bytes(&mut self, addr: u64, bytes: &[u8]) -> Result<(), InstructionException> {
+        let offset = self
+            .offset(addr, bytes.len())
+            .ok_or(InstructionException::StoreAccessFault(addr))?;
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Translates a physical address into an offset into `data`, if all `len`
+    /// bytes starting there are in bounds.
+    fn offset(&self, addr: u64, len: usize) -> Option<usize> {
+        let offset = usize::try_from(addr.checked_sub(self.base)?).ok()?;
+        (offset.checked_add(len)? <= self.data.len()).then_some(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_base_address_is_offset_zero() {
+        let dram = Dram::new(DRAM_BASE, 0x100, &[0xaa, 0xbb]);
+
+        assert_eq!(dram.load(DRAM_BASE, 2), Ok(0xbbaa));
+        assert_eq!(
+            dram.load(DRAM_BASE - 1, 1),
+            Err(InstructionException::LoadAccessFault(DRAM_BASE - 1))
+        );
+        assert_eq!(
+            dram.load(DRAM_BASE + 0xff, 2),
+            Err(InstructionException::LoadAccessFault(DRAM_BASE + 0xff))
+        );
+    }
+
+    #[test]
+    fn stores_below_the_base_fault() {
+        let mut dram = Dram::new(DRAM_BASE, 0x100, &[]);
+
+        assert_eq!(
+            dram.store(0, 1, 0),
+            Err(InstructionException::StoreAccessFault(0))
+        );
+        dram.store(DRAM_BASE + 0xfe, 2, 0x1234).unwrap();
+        assert_eq!(dram.load(DRAM_BASE + 0xfe, 2), Ok(0x1234));
+    }
+
+    #[test]
+    fn parses_sizes_with_suffixes() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("128K"), Some(128 * 1024));
+        assert_eq!(parse_size("64m"), Some(64 << 20));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("12Q"), None);
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+
+/// `e_machine` value for RISC-V.
+const EM_RISCV: u16 = 243;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+/// Reasons an ELF file cannot be loaded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file does not start with the ELF magic number.
+    NotElf,
+    /// The file is a 32-bit ELF.
+    Not64Bit,
+    /// The file is a big-endian ELF.
+    NotLittleEndian,
+    /// The file targets a machine other than RISC-V.
+    NotRiscV(u16),
+    /// The file is not a statically linked executable.
+    NotExecutable(u16),
+    /// A header or segment extends past the end of the file.
+    Truncated,
+    /// A loadable segment does not fit in memory at its physical address.
+    SegmentOutOfMemory(u64),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::Not64Bit => write!(f, "not a 64-bit ELF file"),
+            ElfError::NotLittleEndian => write!(f, "not a little-endian ELF file"),
+            ElfError::NotRiscV(machine) => {
+                write!(f, "not a RISC-V ELF file (machine {})", machine)
+            }
+            ElfError::NotExecutable(kind) => write!(f, "not an executable (type {})", kind),
+            ElfError::Truncated => write!(f, "truncated ELF file"),
+            ElfError::SegmentOutOfMemory(addr) => {
+                write!(f, "segment at {:#x} does not fit in memory", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+/// A `PT_LOAD` segment: `data` is copied to physical address `addr`, and the
+/// rest of its `mem_size` bytes are zeroed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u64,
+    pub data: Vec<u8>,
+    pub mem_size: u64,
+}
+
+/// The parts of a static RV64 ELF executable needed to run it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Elf {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+/// The symbols of an executable that name addresses, sorted by address so
+/// that an address can be shown relative to the nearest one below it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<(u64, String)>,
+}
+
+impl SymbolTable {
+    /// The symbol at or nearest below `addr`, and how far past it `addr` is.
+    pub fn lookup(&self, addr: u64) -> Option<(&str, u64)> {
+        let i = self.symbols.partition_point(|&(value, _)| value <= addr);
+        let (value, name) = self.symbols.get(i.checked_sub(1)?)?;
+        Some((name, addr - value))
+    }
+
+    /// `addr` written as a symbol and offset, such as `kmain+0x10`, or just
+    /// the symbol if `addr` is its value.
+    pub fn describe(&self, addr: u64) -> Option<String> {
+        match self.lookup(addr)? {
+            (name, 0) => Some(name.to_string()),
+            (name, offset) => Some(format!("{}+{:#x}", name, offset)),
+        }
+    }
+}
+
+/// Whether `bytes` starts with the ELF magic number.
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x7fELF")
+}
+
+/// Parses the ELF header and loadable segments of a static RV64 executable.
+pub fn parse(bytes: &[u8]) -> Result<Elf, ElfError> {
+    if !is_elf(bytes) {
+        return Err(ElfError::NotElf);
+    }
+    let header = bytes.get(..EHDR_SIZE).ok_or(ElfError::Truncated)?;
+    if header[4] != ELFCLASS64 {
+        return Err(ElfError::Not64Bit);
+    }
+    if header[5] != ELFDATA2LSB {
+        return Err(ElfError::NotLittleEndian);
+    }
+    let machine = read_u16(header, 18);
+    if machine != EM_RISCV {
+        return Err(ElfError::NotRiscV(machine));
+    }
+    let kind = read_u16(header, 16);
+    if kind != ET_EXEC {
+        return Err(ElfError::NotExecutable(kind));
+    }
+
+    let entry = read_u64(header, 24);
+    let phoff = read_u64(header, 32) as usize;
+    let phentsize = read_u16(header, 54) as usize;
+    let phnum = read_u16(header, 56) as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let start = phoff.saturating_add(i.saturating_mul(phentsize));
+        let phdr = bytes
+            .get(start..start.saturating_add(PHDR_SIZE))
+            .ok_or(ElfError::Truncated)?;
+        if read_u32(phdr, 0) != PT_LOAD {
+            continue;
+        }
+
+        let offset = read_u64(phdr, 8) as usize;
+        let file_size = read_u64(phdr, 32) as usize;
+        let data = bytes
+            .get(offset..offset.saturating_add(file_size))
+            .ok_or(ElfError::Truncated)?;
+        segments.push(Segment {
+            addr: read_u64(phdr, 24),
+            data: data.to_vec(),
+            mem_size: read_u64(phdr, 40),
+        });
+    }
+
+    Ok(Elf { entry, segments })
+}
+
+/// Looks up the value of the symbol `name` in the symbol table of a file
+/// already accepted by [`parse`]. Stripped files have no symbol table.
+pub fn symbol(bytes: &[u8], name: &str) -> Result<Option<u64>, ElfError> {
+    let symbols = symbol_entries(bytes)?;
+    let found = symbols
+        .iter()
+        .find(|(sym_name, _)| *sym_name == name.as_bytes());
+    Ok(found.map(|(_, sym)| read_u64(sym, 8)))
+}
+
+/// The defined symbols of a file already accepted by [`parse`] that name
+/// addresses, leaving out those for sections and source files, local
+/// labels, and mapping symbols such as `$x`.
+pub fn symbols(bytes: &[u8]) -> Result<SymbolTable, ElfError> {
+    let mut symbols: Vec<(u64, String)> = symbol_entries(bytes)?
+        .into_iter()
+        .filter(|(name, sym)| {
+            let kind = sym[4] & 0xf;
+            !name.is_empty()
+                && !name.starts_with(b"$")
+                && !name.starts_with(b".L")
+                && kind != STT_SECTION
+                && kind != STT_FILE
+                && read_u16(sym, 6) != SHN_UNDEF
+        })
+        .map(|(name, sym)| (read_u64(sym, 8), String::from_utf8_lossy(name).into_owned()))
+        .collect();
+    // Of several symbols at one address, the first by name is shown.
+    symbols.sort();
+    symbols.dedup_by_key(|&mut (value, _)| value);
+
+    Ok(SymbolTable { symbols })
+}
+
+/// A symbol's name, and its raw entry in the symbol table.
+type SymbolEntry<'a> = (&'a [u8], &'a [u8]);
+
+/// Every symbol in the symbol tables.
+fn symbol_entries(bytes: &[u8]) -> Result<Vec<SymbolEntry<'_>>, ElfError> {
+    let header = bytes.get(..EHDR_SIZE).ok_or(ElfError::Truncated)?;
+    let shoff = read_u64(header, 40) as usize;
+    let shentsize = read_u16(header, 58) as usize;
+    let shnum = read_u16(header, 60) as usize;
+
+    let section = |i: usize| {
+        let start = shoff.saturating_add(i.saturating_mul(shentsize));
+        bytes
+            .get(start..start.saturating_add(SHDR_SIZE))
+            .ok_or(ElfError::Truncated)
+    };
+    let contents = |shdr: &[u8]| {
+        let offset = read_u64(shdr, 24) as usize;
+        let size = read_u64(shdr, 32) as usize;
+        bytes
+            .get(offset..offset.saturating_add(size))
+            .ok_or(ElfError::Truncated)
+    };
+
+    let mut entries = Vec::new();
+    for i in 0..shnum {
+        let shdr = section(i)?;
+        if read_u32(shdr, 4) != SHT_SYMTAB {
+            continue;
+        }
+
+        let symbols = contents(shdr)?;
+        let strings = contents(section(read_u32(shdr, 40) as usize)?)?;
+        for sym in symbols.chunks_exact(SYM_SIZE) {
+            let start = read_u32(sym, 0) as usize;
+            let sym_name = strings.get(start..).ok_or(ElfError::Truncated)?;
+            let len = sym_name
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(sym_name.len());
+            entries.push((&sym_name[..len], sym));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
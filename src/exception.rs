@@ -0,0 +1,112 @@
+/// Synchronous exceptions raised while decoding or executing an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionException {
+    /// A jump or branch targeted an address that is not 4-byte aligned.
+    InstructionAddressMisaligned(u64),
+    /// An instruction was fetched from outside of memory.
+    InstructionAccessFault(u64),
+    /// A load was not naturally aligned, and misaligned accesses trap.
+    LoadAddressMisaligned(u64),
+    /// A load read from outside of memory.
+    LoadAccessFault(u64),
+    /// A store was not naturally aligned, and misaligned accesses trap.
+    StoreAddressMisaligned(u64),
+    /// A store wrote to outside of memory.
+    StoreAccessFault(u64),
+    /// The word could not be decoded into a supported instruction.
+    IllegalInstruction(u32),
+    /// An EBREAK executed at the given address.
+    Breakpoint(u64),
+    /// An ECALL executed in User mode.
+    EnvironmentCallFromUMode,
+    /// An ECALL executed in Supervisor mode.
+    EnvironmentCallFromSMode,
+    /// An ECALL executed in Machine mode.
+    EnvironmentCallFromMMode,
+    /// Translating the address of an instruction fetch failed.
+    InstructionPageFault(u64),
+    /// Translating the address of a load failed.
+    LoadPageFault(u64),
+    /// Translating the address of a store failed.
+    StorePageFault(u64),
+}
+
+impl InstructionException {
+    /// The exception code reported in `mcause`.
+    pub fn code(&self) -> u64 {
+        match self {
+            InstructionException::InstructionAddressMisaligned(_) => 0,
+            InstructionException::InstructionAccessFault(_) => 1,
+            InstructionException::IllegalInstruction(_) => 2,
+            InstructionException::Breakpoint(_) => 3,
+            InstructionException::LoadAddressMisaligned(_) => 4,
+            InstructionException::LoadAccessFault(_) => 5,
+            InstructionException::StoreAddressMisaligned(_) => 6,
+            InstructionException::StoreAccessFault(_) => 7,
+            InstructionException::EnvironmentCallFromUMode => 8,
+            InstructionException::EnvironmentCallFromSMode => 9,
+            InstructionException::EnvironmentCallFromMMode => 11,
+            InstructionException::InstructionPageFault(_) => 12,
+            InstructionException::LoadPageFault(_) => 13,
+            InstructionException::StorePageFault(_) => 15,
+        }
+    }
+
+    /// The exception-specific value reported in `mtval`: the faulting
+    /// address, the illegal instruction word, or zero.
+    pub fn tval(&self) -> u64 {
+        match *self {
+            InstructionException::InstructionAddressMisaligned(addr)
+            | InstructionException::InstructionAccessFault(addr)
+            | InstructionException::Breakpoint(addr)
+            | InstructionException::LoadAddressMisaligned(addr)
+            | InstructionException::LoadAccessFault(addr)
+            | InstructionException::StoreAddressMisaligned(addr)
+            | InstructionException::StoreAccessFault(addr)
+            | InstructionException::InstructionPageFault(addr)
+            | InstructionException::LoadPageFault(addr)
+            | InstructionException::StorePageFault(addr) => addr,
+            InstructionException::IllegalInstruction(word) => word as u64,
+            InstructionException::EnvironmentCallFromUMode
+            | InstructionException::EnvironmentCallFromSMode
+            | InstructionException::EnvironmentCallFromMMode => 0,
+        }
+    }
+}
+
+/// Asynchronous interrupts, in the order the spec prioritizes them when
+/// several are pending at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    MachineExternal,
+    MachineSoftware,
+    MachineTimer,
+    SupervisorExternal,
+    SupervisorSoftware,
+    SupervisorTimer,
+}
+
+impl Interrupt {
+    /// All interrupts, from highest to lowest priority.
+    pub const PRIORITY: [Interrupt; 6] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+    ];
+
+    /// The interrupt code reported in `mcause`, which is also its bit in
+    /// `mip` and `mie`.
+    pub fn code(&self) -> u64 {
+        match self {
+            Interrupt::SupervisorSoftware => 1,
+            Interrupt::MachineSoftware => 3,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::MachineTimer => 7,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::MachineExternal => 11,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::instruction::{opcode, Instruction};
+
+/// The optional ISA extensions a hart implements on top of RV64I.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extensions {
+    /// Integer multiplication and division.
+    pub m: bool,
+    /// Atomic memory operations.
+    pub a: bool,
+    /// Single-precision floating point.
+    pub f: bool,
+    /// Double-precision floating point, which requires F.
+    pub d: bool,
+    /// 16-bit compressed instructions.
+    pub c: bool,
+}
+
+impl Extensions {
+    /// Parses an ISA string such as `rv64imac` or `rv64imfc_zicsr`,
+    /// case-insensitively, into the extensions it names. Zicsr and Zifencei
+    /// are always implemented, so naming them has no effect. Returns `None`
+    /// if the string is not an RV64I ISA made of implemented extensions.
+    pub fn from_isa(isa: &str) -> Option<Self> {
+        let isa = isa.to_ascii_lowercase();
+        let mut parts = isa.split('_');
+        let letters = parts.next()?.strip_prefix("rv64i")?;
+        if !parts.all(|part| part == "zicsr" || part == "zifencei") {
+            return None;
+        }
+
+        let mut extensions = Extensions {
+            m: false,
+            a: false,
+            f: false,
+            d: false,
+            c: false,
+        };
+        for letter in letters.chars() {
+            let enabled = match letter {
+                'm' => &mut extensions.m,
+                'a' => &mut extensions.a,
+                'f' => &mut extensions.f,
+                'd' => &mut extensions.d,
+                'c' => &mut extensions.c,
+                _ => return None,
+            };
+            *enabled = true;
+        }
+        if extensions.d && !extensions.f {
+            return None;
+        }
+
+        Some(extensions)
+    }
+
+    /// The value of `misa`: MXL reporting 64-bit, and a bit for each letter
+    /// of the ISA implemented, which always includes I and the Supervisor
+    /// and User modes.
+    pub fn misa(&self) -> u64 {
+        let letter = |enabled: bool, letter: u8| (enabled as u64) << (letter - b'A');
+        2 << 62
+            | letter(true, b'I')
+            | letter(true, b'S')
+            | letter(true, b'U')
+            | letter(self.m, b'M')
+            | letter(self.a, b'A')
+            | letter(self.f, b'F')
+            | letter(self.d, b'D')
+            | letter(self.c, b'C')
+    }
+
+    /// Whether executing `inst` is allowed with this set of extensions.
+    pub fn supports(&self, inst: &Instruction) -> bool {
+        match inst {
+            // The low two bits of funct7 give the format, 01 for doubles.
+            // FCVT.S.D has a single-precision result, but still needs D.
+            Instruction::R(r) if r.opcode == opcode::OP_FP => match (r.funct7 & 0b11, r.funct7) {
+                (0b01, _) | (_, 0x20) => self.d,
+                _ => self.f,
+            },
+            Instruction::R(r) if r.opcode == opcode::AMO => self.a,
+            Instruction::R4(r4) => match r4.fmt {
+                0b01 => self.d,
+                _ => self.f,
+            },
+            Instruction::R(r) if r.funct7 == 0x01 => match r.opcode {
+                opcode::OP | opcode::OP_32 => self.m,
+                _ => true,
+            },
+            // FLD and FSD are the loads and stores with funct3 0b011.
+            Instruction::I(i) if i.opcode == opcode::LOAD_FP => match i.funct3 {
+                0b011 => self.d,
+                _ => self.f,
+            },
+            Instruction::S(s) if s.opcode == opcode::STORE_FP => match s.funct3 {
+                0b011 => self.d,
+                _ => self.f,
+            },
+            _ => true,
+        }
+    }
+}
+
+impl Default for Extensions {
+    /// Every implemented extension is enabled by default.
+    fn default() -> Self {
+        Extensions {
+            m: true,
+            a: true,
+            f: true,
+            d: true,
+            c: true,
+        }
+    }
+}
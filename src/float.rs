@@ -0,0 +1,1069 @@
+/// The upper half of an f register holding a single-precision value.
+const NAN_BOX: u64 = 0xffff_ffff_0000_0000;
+
+/// The canonical NaN, which every single-precision operation returning a
+/// NaN produces.
+pub const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+
+/// The canonical NaN of double-precision operations.
+pub const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// Invalid operation, in `fflags`.
+pub const FLAG_NV: u64 = 1 << 4;
+/// Divide by zero, in `fflags`.
+pub const FLAG_DZ: u64 = 1 << 3;
+/// Overflow, in `fflags`.
+pub const FLAG_OF: u64 = 1 << 2;
+/// Underflow, in `fflags`.
+pub const FLAG_UF: u64 = 1 << 1;
+/// Inexact, in `fflags`.
+pub const FLAG_NX: u64 = 1 << 0;
+
+const SIGN_F32: u32 = 1 << 31;
+const INFINITY_F32: u32 = 0x7f80_0000;
+const MAX_F32: u32 = 0x7f7f_ffff;
+
+const SIGN_F64: u64 = 1 << 63;
+const INFINITY_F64: u64 = 0x7ff0_0000_0000_0000;
+const MAX_F64: u64 = 0x7fef_ffff_ffff_ffff;
+
+/// The rounding modes selectable by an instruction's `rm` field or `frm`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven = 0b000,
+    TowardZero = 0b001,
+    Down = 0b010,
+    Up = 0b011,
+    NearestMaxMagnitude = 0b100,
+}
+
+impl RoundingMode {
+    /// Decodes a rounding mode, or `None` for the reserved encodings and
+    /// the dynamic mode `0b111`.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0b000 => Some(RoundingMode::NearestEven),
+            0b001 => Some(RoundingMode::TowardZero),
+            0b010 => Some(RoundingMode::Down),
+            0b011 => Some(RoundingMode::Up),
+            0b100 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// NaN-boxes the single-precision value `bits` into a 64-bit f register, so
+/// that it reads as a NaN if used as a double.
+pub fn box_f32(bits: u32) -> u64 {
+    NAN_BOX | bits as u64
+}
+
+/// The single-precision value held in a 64-bit f register. A value that is
+/// not properly NaN-boxed reads as the canonical NaN.
+pub fn unbox_f32(value: u64) -> u32 {
+    if value & NAN_BOX == NAN_BOX {
+        value as u32
+    } else {
+        CANONICAL_NAN_F32
+    }
+}
+
+fn is_nan_f32(a: u32) -> bool {
+    a & !SIGN_F32 > INFINITY_F32
+}
+
+/// Signaling NaNs have the top fraction bit clear.
+fn is_signaling_f32(a: u32) -> bool {
+    is_nan_f32(a) && a & (1 << 22) == 0
+}
+
+/// The canonical NaN, raising NV if any operand is a signaling NaN, if any
+/// operand is a NaN.
+fn propagate_nan_f32(operands: &[u32]) -> Option<(u32, u64)> {
+    if !operands.iter().any(|&a| is_nan_f32(a)) {
+        return None;
+    }
+
+    let signaling = operands.iter().any(|&a| is_signaling_f32(a));
+    Some((CANONICAL_NAN_F32, if signaling { FLAG_NV } else { 0 }))
+}
+
+/// The sign of an exact zero sum: negative only if both operands are, or
+/// when rounding down.
+fn zero_sum_f32(a: u32, b: u32, rm: RoundingMode) -> u32 {
+    if a & b & SIGN_F32 != 0 || (rm == RoundingMode::Down && (a | b) & SIGN_F32 != 0) {
+        SIGN_F32
+    } else {
+        0
+    }
+}
+
+/// FADD.S, returning the result and the exception flags it raises.
+pub fn add_f32(a: u32, b: u32, rm: RoundingMode) -> (u32, u64) {
+    if let Some(nan) = propagate_nan_f32(&[a, b]) {
+        return nan;
+    }
+
+    let (x, y) = (f32::from_bits(a) as f64, f32::from_bits(b) as f64);
+    if x.is_infinite() && y.is_infinite() && x != y {
+        return (CANONICAL_NAN_F32, FLAG_NV);
+    }
+    if x.is_infinite() || y.is_infinite() {
+        return (if x.is_infinite() { a } else { b }, 0);
+    }
+    if x == 0.0 && y == 0.0 {
+        return (zero_sum_f32(a, b, rm), 0);
+    }
+
+    // Two-sum: the exact result is sum + error.
+    let sum = x + y;
+    let virtual_y = sum - x;
+    let error = (x - (sum - virtual_y)) + (y - virtual_y);
+    if sum == 0.0 {
+        // x and y cancel exactly.
+        let sign = if rm == RoundingMode::Down {
+            SIGN_F32
+        } else {
+            0
+        };
+        return (sign, 0);
+    }
+
+    round_f32(sum, error, rm)
+}
+
+/// FSUB.S, returning the result and the exception flags it raises.
+pub fn sub_f32(a: u32, b: u32, rm: RoundingMode) -> (u32, u64) {
+    if let Some(nan) = propagate_nan_f32(&[a, b]) {
+        return nan;
+    }
+
+    add_f32(a, b ^ SIGN_F32, rm)
+}
+
+/// FMUL.S, returning the result and the exception flags it raises.
+pub fn mul_f32(a: u32, b: u32, rm: RoundingMode) -> (u32, u64) {
+    if let Some(nan) = propagate_nan_f32(&[a, b]) {
+        return nan;
+    }
+
+    let sign = (a ^ b) & SIGN_F32;
+    let (x, y) = (f32::from_bits(a) as f64, f32::from_bits(b) as f64);
+    if (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite()) {
+        return (CANONICAL_NAN_F32, FLAG_NV);
+    }
+    if x.is_infinite() || y.is_infinite() {
+        return (sign | INFINITY_F32, 0);
+    }
+    if x == 0.0 || y == 0.0 {
+        return (sign, 0);
+    }
+
+    // Products of singles are exact in double precision.
+    round_f32(x * y, 0.0, rm)
+}
+
+/// FDIV.S, returning the result and the exception flags it raises.
+pub fn div_f32(a: u32, b: u32, rm: RoundingMode) -> (u32, u64) {
+    if let Some(nan) = propagate_nan_f32(&[a, b]) {
+        return nan;
+    }
+
+    let sign = (a ^ b) & SIGN_F32;
+    let (x, y) = (f32::from_bits(a) as f64, f32::from_bits(b) as f64);
+    if (x == 0.0 && y == 0.0) || (x.is_infinite() && y.is_infinite()) {
+        return (CANONICAL_NAN_F32, FLAG_NV);
+    }
+    if x.is_infinite() {
+        return (sign | INFINITY_F32, 0);
+    }
+    if y == 0.0 {
+        return (sign | INFINITY_F32, FLAG_DZ);
+    }
+    if x == 0.0 || y.is_infinite() {
+        return (sign, 0);
+    }
+
+    // The remainder x - q * y is exact, and the true quotient lies on the
+    // side of q given by the sign of remainder / y.
+    let quotient = x / y;
+    let remainder = (-quotient).mul_add(y, x);
+    round_f32(quotient, remainder * y.signum(), rm)
+}
+
+/// FSQRT.S, returning the result and the exception flags it raises.
+pub fn sqrt_f32(a: u32, rm: RoundingMode) -> (u32, u64) {
+    if let Some(nan) = propagate_nan_f32(&[a]) {
+        return nan;
+    }
+
+    let x = f32::from_bits(a) as f64;
+    if x == 0.0 {
+        return (a, 0);
+    }
+    if x < 0.0 {
+        return (CANONICAL_NAN_F32, FLAG_NV);
+    }
+    if x.is_infinite() {
+        return (a, 0);
+    }
+
+    let root = x.sqrt();
+    let remainder = (-root).mul_add(root, x);
+    round_f32(root, remainder, rm)
+}
+
+/// FMADD.S, computing `a * b + c` with a single rounding, and returning the
+/// result and the exception flags it raises. The other fused multiply-adds
+/// negate `a`, `c` or both first.
+///
+/// Multiplying infinity by zero raises NV even if `c` is a quiet NaN.
+pub fn fma_f32(a: u32, b: u32, c: u32, rm: RoundingMode) -> (u32, u64) {
+    let (x, y, z) = (
+        f32::from_bits(a) as f64,
+        f32::from_bits(b) as f64,
+        f32::from_bits(c) as f64,
+    );
+    let invalid = (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite());
+    if let Some((nan, flags)) = propagate_nan_f32(&[a, b, c]) {
+        return (nan, flags | if invalid { FLAG_NV } else { 0 });
+    }
+    if invalid {
+        return (CANONICAL_NAN_F32, FLAG_NV);
+    }
+
+    let sign = (a ^ b) & SIGN_F32;
+    if x.is_infinite() || y.is_infinite() {
+        if z.is_infinite() && (c & SIGN_F32) != sign {
+            return (CANONICAL_NAN_F32, FLAG_NV);
+        }
+        return (sign | INFINITY_F32, 0);
+    }
+    if z.is_infinite() {
+        return (c, 0);
+    }
+    if x == 0.0 || y == 0.0 {
+        return (
+            if z == 0.0 {
+                zero_sum_f32(sign, c, rm)
+            } else {
+                c
+            },
+            0,
+        );
+    }
+
+    // Products of singles are exact in double precision, which leaves a sum
+    // to compute exactly as in add_f32.
+    let product = x * y;
+    if z == 0.0 {
+        return round_f32(product, 0.0, rm);
+    }
+    let sum = product + z;
+    let virtual_z = sum - product;
+    let error = (product - (sum - virtual_z)) + (z - virtual_z);
+    if sum == 0.0 {
+        let sign = if rm == RoundingMode::Down {
+            SIGN_F32
+        } else {
+            0
+        };
+        return (sign, 0);
+    }
+
+    round_f32(sum, error, rm)
+}
+
+fn is_nan_f64(a: u64) -> bool {
+    a & !SIGN_F64 > INFINITY_F64
+}
+
+fn is_signaling_f64(a: u64) -> bool {
+    is_nan_f64(a) && a & (1 << 51) == 0
+}
+
+/// The double-precision counterpart of `propagate_nan_f32`.
+fn propagate_nan_f64(operands: &[u64]) -> Option<(u64, u64)> {
+    if !operands.iter().any(|&a| is_nan_f64(a)) {
+        return None;
+    }
+
+    let signaling = operands.iter().any(|&a| is_signaling_f64(a));
+    Some((CANONICAL_NAN_F64, if signaling { FLAG_NV } else { 0 }))
+}
+
+fn zero_sum_f64(a: u64, b: u64, rm: RoundingMode) -> u64 {
+    if a & b & SIGN_F64 != 0 || (rm == RoundingMode::Down && (a | b) & SIGN_F64 != 0) {
+        SIGN_F64
+    } else {
+        0
+    }
+}
+
+/// FMADD.D, the double-precision counterpart of `fma_f32`. The sum is
+/// computed exactly with `Unrounded` rather than by `f64::mul_add`, as
+/// rounding it here is what gives the NX, UF and OF flags.
+pub fn fma_f64(a: u64, b: u64, c: u64, rm: RoundingMode) -> (u64, u64) {
+    let (x, y, z) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+    let invalid = (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite());
+    if let Some((nan, flags)) = propagate_nan_f64(&[a, b, c]) {
+        return (nan, flags | if invalid { FLAG_NV } else { 0 });
+    }
+    if invalid {
+        return (CANONICAL_NAN_F64, FLAG_NV);
+    }
+
+    let sign = (a ^ b) & SIGN_F64;
+    if x.is_infinite() || y.is_infinite() {
+        if z.is_infinite() && (c & SIGN_F64) != sign {
+            return (CANONICAL_NAN_F64, FLAG_NV);
+        }
+        return (sign | INFINITY_F64, 0);
+    }
+    if z.is_infinite() {
+        return (c, 0);
+    }
+    if x == 0.0 || y == 0.0 {
+        return (
+            if z == 0.0 {
+                zero_sum_f64(sign, c, rm)
+            } else {
+                c
+            },
+            0,
+        );
+    }
+
+    let product = Unrounded::from_f64(a).mul(Unrounded::from_f64(b));
+    if z == 0.0 {
+        return round_f64(product, rm);
+    }
+    match product.add(Unrounded::from_f64(c)) {
+        Some(sum) => round_f64(sum, rm),
+        None if rm == RoundingMode::Down => (SIGN_F64, 0),
+        None => (0, 0),
+    }
+}
+
+/// FADD.D, returning the result and the exception flags it raises.
+pub fn add_f64(a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if let Some(nan) = propagate_nan_f64(&[a, b]) {
+        return nan;
+    }
+
+    let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+    if x.is_infinite() && y.is_infinite() && x != y {
+        return (CANONICAL_NAN_F64, FLAG_NV);
+    }
+    if x.is_infinite() || y.is_infinite() {
+        return (if x.is_infinite() { a } else { b }, 0);
+    }
+    if x == 0.0 && y == 0.0 {
+        return (zero_sum_f64(a, b, rm), 0);
+    }
+    if x == 0.0 || y == 0.0 {
+        return (if x == 0.0 { b } else { a }, 0);
+    }
+
+    match Unrounded::from_f64(a).add(Unrounded::from_f64(b)) {
+        Some(sum) => round_f64(sum, rm),
+        // x and y cancel exactly.
+        None if rm == RoundingMode::Down => (SIGN_F64, 0),
+        None => (0, 0),
+    }
+}
+
+/// FSUB.D, returning the result and the exception flags it raises.
+pub fn sub_f64(a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if let Some(nan) = propagate_nan_f64(&[a, b]) {
+        return nan;
+    }
+
+    add_f64(a, b ^ SIGN_F64, rm)
+}
+
+/// FMUL.D, returning the result and the exception flags it raises.
+pub fn mul_f64(a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if let Some(nan) = propagate_nan_f64(&[a, b]) {
+        return nan;
+    }
+
+    let sign = (a ^ b) & SIGN_F64;
+    let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+    if (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite()) {
+        return (CANONICAL_NAN_F64, FLAG_NV);
+    }
+    if x.is_infinite() || y.is_infinite() {
+        return (sign | INFINITY_F64, 0);
+    }
+    if x == 0.0 || y == 0.0 {
+        return (sign, 0);
+    }
+
+    round_f64(Unrounded::from_f64(a).mul(Unrounded::from_f64(b)), rm)
+}
+
+/// FDIV.D, returning the result and the exception flags it raises.
+pub fn div_f64(a: u64, b: u64, rm: RoundingMode) -> (u64, u64) {
+    if let Some(nan) = propagate_nan_f64(&[a, b]) {
+        return nan;
+    }
+
+    let sign = (a ^ b) & SIGN_F64;
+    let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+    if (x == 0.0 && y == 0.0) || (x.is_infinite() && y.is_infinite()) {
+        return (CANONICAL_NAN_F64, FLAG_NV);
+    }
+    if x.is_infinite() {
+        return (sign | INFINITY_F64, 0);
+    }
+    if y == 0.0 {
+        return (sign | INFINITY_F64, FLAG_DZ);
+    }
+    if x == 0.0 || y.is_infinite() {
+        return (sign, 0);
+    }
+
+    let (x, y) = (Unrounded::from_f64(a), Unrounded::from_f64(b));
+    // Scaling the dividend up to 117 bits leaves at least 64 in the
+    // quotient, as the divisor has at most 53.
+    let shift = x.mantissa.leading_zeros() - 11;
+    let dividend = x.mantissa << shift;
+    let quotient = dividend / y.mantissa;
+    let inexact = dividend % y.mantissa != 0;
+    let quotient = Unrounded {
+        negative: sign != 0,
+        mantissa: quotient << 1 | inexact as u128,
+        exponent: x.exponent - shift as i32 - y.exponent - 1,
+    };
+    round_f64(quotient, rm)
+}
+
+/// FSQRT.D, returning the result and the exception flags it raises.
+pub fn sqrt_f64(a: u64, rm: RoundingMode) -> (u64, u64) {
+    if let Some(nan) = propagate_nan_f64(&[a]) {
+        return nan;
+    }
+
+    let x = f64::from_bits(a);
+    if x == 0.0 {
+        return (a, 0);
+    }
+    if x < 0.0 {
+        return (CANONICAL_NAN_F64, FLAG_NV);
+    }
+    if x.is_infinite() {
+        return (a, 0);
+    }
+
+    // Scaling the mantissa up to 117 or 118 bits, keeping the exponent even
+    // so that it can be halved, leaves at least 58 in the root.
+    let x = Unrounded::from_f64(a);
+    let mut shift = x.mantissa.leading_zeros() as i32 - 11;
+    if (x.exponent - shift) % 2 != 0 {
+        shift += 1;
+    }
+    let radicand = x.mantissa << shift;
+    let root = radicand.isqrt();
+    let inexact = root * root != radicand;
+    let root = Unrounded {
+        negative: false,
+        mantissa: root << 1 | inexact as u128,
+        exponent: (x.exponent - shift) / 2 - 1,
+    };
+    round_f64(root, rm)
+}
+
+/// FSGNJ.S, FSGNJN.S or FSGNJX.S, selected by `funct3`: `a` with its sign
+/// replaced by that of `b`, its inverse, or the two signs' exclusive or.
+/// With `a` and `b` the same register, these are FMV.S, FNEG.S and FABS.S.
+pub fn sign_inject_f32(a: u32, b: u32, funct3: u32) -> u32 {
+    let sign = match funct3 {
+        0b000 => b,
+        0b001 => !b,
+        _ => a ^ b,
+    };
+    (a & !SIGN_F32) | (sign & SIGN_F32)
+}
+
+/// The double-precision counterpart of `sign_inject_f32`.
+pub fn sign_inject_f64(a: u64, b: u64, funct3: u32) -> u64 {
+    let sign = match funct3 {
+        0b000 => b,
+        0b001 => !b,
+        _ => a ^ b,
+    };
+    (a & !SIGN_F64) | (sign & SIGN_F64)
+}
+
+/// FMIN.S or FMAX.S, selected by `funct3`, returning the result and the
+/// exception flags raised.
+///
+/// -0.0 is taken to be less than +0.0. If only one operand is a NaN, the
+/// result is the other, and if both are it is the canonical NaN. Either
+/// way, NV is only raised for signaling NaNs.
+pub fn min_max_f32(a: u32, b: u32, funct3: u32) -> (u32, u64) {
+    let signaling = is_signaling_f32(a) || is_signaling_f32(b);
+    let flags = if signaling { FLAG_NV } else { 0 };
+    match (is_nan_f32(a), is_nan_f32(b)) {
+        (true, true) => return (CANONICAL_NAN_F32, flags),
+        (true, false) => return (b, flags),
+        (false, true) => return (a, flags),
+        (false, false) => {}
+    }
+
+    let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+    // Equal operands are either identical or zeros of opposite sign, where
+    // the minimum is the negative one.
+    let a_is_less = x < y || (x == y && a & SIGN_F32 != 0);
+    let min = funct3 == 0b000;
+    (if a_is_less == min { a } else { b }, 0)
+}
+
+/// The double-precision counterpart of `min_max_f32`.
+pub fn min_max_f64(a: u64, b: u64, funct3: u32) -> (u64, u64) {
+    let signaling = is_signaling_f64(a) || is_signaling_f64(b);
+    let flags = if signaling { FLAG_NV } else { 0 };
+    match (is_nan_f64(a), is_nan_f64(b)) {
+        (true, true) => return (CANONICAL_NAN_F64, flags),
+        (true, false) => return (b, flags),
+        (false, true) => return (a, flags),
+        (false, false) => {}
+    }
+
+    let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+    let a_is_less = x < y || (x == y && a & SIGN_F64 != 0);
+    let min = funct3 == 0b000;
+    (if a_is_less == min { a } else { b }, 0)
+}
+
+/// FCVT.S.D, returning the result and the exception flags it raises.
+pub fn f64_to_f32(a: u64, rm: RoundingMode) -> (u32, u64) {
+    if let Some((_, flags)) = propagate_nan_f64(&[a]) {
+        return (CANONICAL_NAN_F32, flags);
+    }
+
+    let x = f64::from_bits(a);
+    let sign = if a & SIGN_F64 != 0 { SIGN_F32 } else { 0 };
+    if x.is_infinite() {
+        return (sign | INFINITY_F32, 0);
+    }
+    if x == 0.0 {
+        return (sign, 0);
+    }
+
+    round_f32(x, 0.0, rm)
+}
+
+/// FCVT.D.S, which is always exact.
+pub fn f32_to_f64(a: u32) -> (u64, u64) {
+    if let Some((_, flags)) = propagate_nan_f32(&[a]) {
+        return (CANONICAL_NAN_F64, flags);
+    }
+
+    ((f32::from_bits(a) as f64).to_bits(), 0)
+}
+
+/// FLE.S, FLT.S or FEQ.S, selected by `funct3`, returning whether the
+/// comparison holds and the exception flags raised.
+///
+/// Comparisons with a NaN are false. FEQ is a quiet comparison, raising NV
+/// only for signaling NaNs, while FLT and FLE raise it for any NaN.
+pub fn compare_f32(a: u32, b: u32, funct3: u32) -> (bool, u64) {
+    if is_nan_f32(a) || is_nan_f32(b) {
+        let signals = funct3 != 0b010 || is_signaling_f32(a) || is_signaling_f32(b);
+        return (false, if signals { FLAG_NV } else { 0 });
+    }
+
+    let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+    let holds = match funct3 {
+        0b000 => x <= y,
+        0b001 => x < y,
+        _ => x == y,
+    };
+    (holds, 0)
+}
+
+/// FLE.D, FLT.D or FEQ.D, like `compare_f32`.
+pub fn compare_f64(a: u64, b: u64, funct3: u32) -> (bool, u64) {
+    if is_nan_f64(a) || is_nan_f64(b) {
+        let signals = funct3 != 0b010 || is_signaling_f64(a) || is_signaling_f64(b);
+        return (false, if signals { FLAG_NV } else { 0 });
+    }
+
+    let (x, y) = (f64::from_bits(a), f64::from_bits(b));
+    let holds = match funct3 {
+        0b000 => x <= y,
+        0b001 => x < y,
+        _ => x == y,
+    };
+    (holds, 0)
+}
+
+/// FCLASS.S: a mask with the one bit set that classifies `a`, from bit 0
+/// for negative infinity through to bit 9 for a quiet NaN.
+pub fn classify_f32(a: u32) -> u64 {
+    let negative = a & SIGN_F32 != 0;
+    let biased = (a >> 23) & 0xff;
+    let fraction = a & ((1 << 23) - 1);
+
+    if is_nan_f32(a) {
+        return if is_signaling_f32(a) { 1 << 8 } else { 1 << 9 };
+    }
+
+    // Negative classes mirror the positive ones below bit 4.
+    let positive = match (biased, fraction) {
+        (0xff, _) => 7,
+        (0, 0) => 4,
+        (0, _) => 5,
+        _ => 6,
+    };
+    let bit = if negative { 7 - positive } else { positive };
+    1 << bit
+}
+
+/// FCLASS.D, with the same mask as `classify_f32`.
+pub fn classify_f64(a: u64) -> u64 {
+    let negative = a & SIGN_F64 != 0;
+    let biased = (a >> 52) & 0x7ff;
+    let fraction = a & ((1 << 52) - 1);
+
+    if is_nan_f64(a) {
+        return if is_signaling_f64(a) { 1 << 8 } else { 1 << 9 };
+    }
+
+    let positive = match (biased, fraction) {
+        (0x7ff, _) => 7,
+        (0, 0) => 4,
+        (0, _) => 5,
+        _ => 6,
+    };
+    let bit = if negative { 7 - positive } else { positive };
+    1 << bit
+}
+
+/// The integer types FCVT converts to and from, selected by its rs2 field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntType {
+    Word = 0,
+    UnsignedWord = 1,
+    Long = 2,
+    UnsignedLong = 3,
+}
+
+impl IntType {
+    pub fn from_bits(bits: usize) -> Option<Self> {
+        match bits {
+            0 => Some(IntType::Word),
+            1 => Some(IntType::UnsignedWord),
+            2 => Some(IntType::Long),
+            3 => Some(IntType::UnsignedLong),
+            _ => None,
+        }
+    }
+
+    fn range(self) -> (i128, i128) {
+        match self {
+            IntType::Word => (i32::MIN as i128, i32::MAX as i128),
+            IntType::UnsignedWord => (0, u32::MAX as i128),
+            IntType::Long => (i64::MIN as i128, i64::MAX as i128),
+            IntType::UnsignedLong => (0, u64::MAX as i128),
+        }
+    }
+}
+
+/// FCVT.{W,WU,L,LU}.S, returning the integer in an x register and the
+/// exception flags raised. Word results are sign-extended, even unsigned
+/// ones.
+///
+/// Values that round to outside the type's range, including infinities,
+/// saturate to its minimum or maximum and raise NV instead of NX. NaNs
+/// convert to the maximum.
+pub fn f32_to_int(a: u32, to: IntType, rm: RoundingMode) -> (u64, u64) {
+    let (min, max) = to.range();
+    let negative = a & SIGN_F32 != 0;
+    let biased = (a >> 23) & 0xff;
+    let fraction = (a & ((1 << 23) - 1)) as u64;
+
+    let value = if is_nan_f32(a) {
+        None
+    } else if biased == 0xff {
+        Some((if negative { min - 1 } else { max + 1 }, false))
+    } else if biased == 0 {
+        Some(scale_to_int(fraction, -149, negative, rm))
+    } else {
+        Some(scale_to_int(
+            fraction | (1 << 23),
+            biased as i32 - 150,
+            negative,
+            rm,
+        ))
+    };
+
+    saturate(value, to)
+}
+
+/// FCVT.{W,WU,L,LU}.D, like `f32_to_int`.
+pub fn f64_to_int(a: u64, to: IntType, rm: RoundingMode) -> (u64, u64) {
+    let (min, max) = to.range();
+    let negative = a & SIGN_F64 != 0;
+    let biased = (a >> 52) & 0x7ff;
+    let fraction = a & ((1 << 52) - 1);
+
+    let value = if is_nan_f64(a) {
+        None
+    } else if biased == 0x7ff {
+        Some((if negative { min - 1 } else { max + 1 }, false))
+    } else if biased == 0 {
+        Some(scale_to_int(fraction, -1074, negative, rm))
+    } else {
+        Some(scale_to_int(
+            fraction | (1 << 52),
+            biased as i32 - 1075,
+            negative,
+            rm,
+        ))
+    };
+
+    saturate(value, to)
+}
+
+/// The integer nearest to `mantissa * 2^exponent` by `rm`, negated if
+/// `negative`, and whether it is inexact. `mantissa` must be at most 53 bits
+/// wide.
+fn scale_to_int(mantissa: u64, exponent: i32, negative: bool, rm: RoundingMode) -> (i128, bool) {
+    // Anything shifted past bit 64 is out of range of every type.
+    let (magnitude, inexact) = match exponent {
+        65.. => (1 << 65, false),
+        0.. => ((mantissa as i128) << exponent, false),
+        _ => {
+            let (kept, inexact) = round_bits(mantissa, -exponent, negative, rm);
+            (kept as i128, inexact)
+        }
+    };
+    (if negative { -magnitude } else { magnitude }, inexact)
+}
+
+/// Saturates the converted `value`, `None` for a NaN, to the range of `to`,
+/// returning it as it is written to an x register and the flags raised.
+fn saturate(value: Option<(i128, bool)>, to: IntType) -> (u64, u64) {
+    let (min, max) = to.range();
+    let (value, flags) = match value {
+        None => (max, FLAG_NV),
+        Some((value, _)) if value < min => (min, FLAG_NV),
+        Some((value, _)) if value > max => (max, FLAG_NV),
+        Some((value, inexact)) => (value, if inexact { FLAG_NX } else { 0 }),
+    };
+
+    match to {
+        IntType::Word | IntType::UnsignedWord => (value as i32 as i64 as u64, flags),
+        IntType::Long | IntType::UnsignedLong => (value as u64, flags),
+    }
+}
+
+/// FCVT.S.{W,WU,L,LU}, converting the integer in an x register and
+/// returning the exception flags raised, which can only be NX.
+pub fn int_to_f32(value: u64, from: IntType, rm: RoundingMode) -> (u32, u64) {
+    let value = match from {
+        IntType::Word => value as i32 as i128,
+        IntType::UnsignedWord => value as u32 as i128,
+        IntType::Long => value as i64 as i128,
+        IntType::UnsignedLong => value as i128,
+    };
+    if value == 0 {
+        return (0, 0);
+    }
+
+    let negative = value < 0;
+    let magnitude = value.unsigned_abs() as u64;
+    let top = 63 - magnitude.leading_zeros() as i32;
+    let (mut kept, inexact) = round_bits(magnitude, top - 23, negative, rm);
+    let mut exponent = top;
+    if kept == 1 << 24 {
+        kept >>= 1;
+        exponent += 1;
+    }
+
+    let sign = if negative { SIGN_F32 } else { 0 };
+    let bits = ((exponent + 127) as u32) << 23 | (kept as u32 & ((1 << 23) - 1));
+    (sign | bits, if inexact { FLAG_NX } else { 0 })
+}
+
+/// FCVT.D.{W,WU,L,LU}, like `int_to_f32`. Only the 64-bit types can be
+/// inexact.
+pub fn int_to_f64(value: u64, from: IntType, rm: RoundingMode) -> (u64, u64) {
+    let value = match from {
+        IntType::Word => value as i32 as i128,
+        IntType::UnsignedWord => value as u32 as i128,
+        IntType::Long => value as i64 as i128,
+        IntType::UnsignedLong => value as i128,
+    };
+    if value == 0 {
+        return (0, 0);
+    }
+
+    let negative = value < 0;
+    let magnitude = value.unsigned_abs() as u64;
+    let top = 63 - magnitude.leading_zeros() as i32;
+    let (mut kept, inexact) = round_bits(magnitude, top - 52, negative, rm);
+    let mut exponent = top;
+    if kept == 1 << 53 {
+        kept >>= 1;
+        exponent += 1;
+    }
+
+    let sign = if negative { SIGN_F64 } else { 0 };
+    let bits = ((exponent + 1023) as u64) << 52 | (kept & ((1 << 52) - 1));
+    (sign | bits, if inexact { FLAG_NX } else { 0 })
+}
+
+/// Rounds the exact value `value + error` to single precision, where
+/// `value` is a finite, nonzero double and `error` is at most half an ulp of
+/// it. Only the sign of `error` is used.
+fn round_f32(value: f64, error: f64, rm: RoundingMode) -> (u32, u64) {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) != 0;
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (mantissa, exponent) = if biased == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), biased - 1075)
+    };
+
+    // Double the mantissa so the error can be added as half an ulp, which
+    // is exact for rounding purposes: no single-precision rounding boundary
+    // lies strictly between value and value + error.
+    let toward = if error == 0.0 {
+        0
+    } else if (error < 0.0) == sign {
+        1
+    } else {
+        -1
+    };
+    let magnitude = ((mantissa << 1) as i64 + toward) as u64;
+    let lsb = exponent - 1;
+    let top = lsb + 63 - magnitude.leading_zeros() as i32;
+
+    // Tininess is detected after rounding, as though the exponent range were
+    // unbounded.
+    let tiny = top < -126 && {
+        let (rounded, _) = round_bits(magnitude, top - 23 - lsb, sign, rm);
+        top < -127 || rounded < 1 << 24
+    };
+
+    let quantum = (top - 23).max(-149);
+    let (mut kept, inexact) = round_bits(magnitude, quantum - lsb, sign, rm);
+    let mut quantum = quantum;
+    if kept == 1 << 24 {
+        kept >>= 1;
+        quantum += 1;
+    }
+
+    let mut flags = 0;
+    if inexact {
+        flags |= FLAG_NX;
+        if tiny {
+            flags |= FLAG_UF;
+        }
+    }
+
+    let sign_bit = if sign { SIGN_F32 } else { 0 };
+    let result = if kept < 1 << 23 {
+        kept as u32
+    } else {
+        let biased = quantum + 23 + 127;
+        if biased >= 0xff {
+            flags |= FLAG_OF | FLAG_NX;
+            let to_infinity = match rm {
+                RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+                RoundingMode::TowardZero => false,
+                RoundingMode::Down => sign,
+                RoundingMode::Up => !sign,
+            };
+            return (
+                sign_bit | if to_infinity { INFINITY_F32 } else { MAX_F32 },
+                flags,
+            );
+        }
+        ((biased as u32) << 23) | (kept as u32 & ((1 << 23) - 1))
+    };
+
+    (sign_bit | result, flags)
+}
+
+/// A finite, nonzero value of `mantissa * 2^exponent`, for computing
+/// double-precision results before they are rounded. Bits dropped along the
+/// way are jammed into bit 0, which keeps the value on the same side of every
+/// rounding boundary as long as enough bits remain above it.
+#[derive(Clone, Copy, Debug)]
+struct Unrounded {
+    negative: bool,
+    mantissa: u128,
+    exponent: i32,
+}
+
+impl Unrounded {
+    /// The value of the finite, nonzero double `a`.
+    fn from_f64(a: u64) -> Self {
+        let biased = ((a >> 52) & 0x7ff) as i32;
+        let fraction = (a & ((1 << 52) - 1)) as u128;
+        let (mantissa, exponent) = if biased == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), biased - 1075)
+        };
+        Unrounded {
+            negative: a & SIGN_F64 != 0,
+            mantissa,
+            exponent,
+        }
+    }
+
+    /// The position of the highest set bit.
+    fn top(&self) -> i32 {
+        self.exponent + 127 - self.mantissa.leading_zeros() as i32
+    }
+
+    /// The exact sum, or `None` if it is zero. Neither mantissa may be wider
+    /// than 106 bits, that of a product of doubles.
+    fn add(self, other: Unrounded) -> Option<Unrounded> {
+        let (x, y) = if self.top() >= other.top() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        // Moving x's top bit to bit 124 leaves room for the sum. y only loses
+        // bits if its top bit is at least 18 below x's, so the sum keeps far
+        // more bits above them than rounding needs.
+        let shift = x.mantissa.leading_zeros() as i32 - 3;
+        let exponent = x.exponent - shift;
+        let x_mantissa = x.mantissa << shift;
+        let y_mantissa = if y.exponent >= exponent {
+            y.mantissa << (y.exponent - exponent)
+        } else {
+            shift_right_jam(y.mantissa, (exponent - y.exponent) as u32)
+        };
+
+        let signed = |negative: bool, mantissa: u128| {
+            if negative {
+                -(mantissa as i128)
+            } else {
+                mantissa as i128
+            }
+        };
+        let sum = signed(x.negative, x_mantissa) + signed(y.negative, y_mantissa);
+        (sum != 0).then(|| Unrounded {
+            negative: sum < 0,
+            mantissa: sum.unsigned_abs(),
+            exponent,
+        })
+    }
+
+    /// The exact product. Both mantissas must be at most 64 bits wide.
+    fn mul(self, other: Unrounded) -> Unrounded {
+        Unrounded {
+            negative: self.negative != other.negative,
+            mantissa: self.mantissa * other.mantissa,
+            exponent: self.exponent + other.exponent,
+        }
+    }
+}
+
+/// Shifts `mantissa` right by `shift` bits, jamming any set bits that are
+/// shifted out into bit 0.
+fn shift_right_jam(mantissa: u128, shift: u32) -> u128 {
+    match shift {
+        0 => mantissa,
+        1..=127 => mantissa >> shift | (mantissa & ((1 << shift) - 1) != 0) as u128,
+        _ => (mantissa != 0) as u128,
+    }
+}
+
+/// Rounds `value` to double precision, like `round_f32` does to single.
+fn round_f64(value: Unrounded, rm: RoundingMode) -> (u64, u64) {
+    let sign = value.negative;
+    // 62 bits are plenty for the 53 kept and the two below them that
+    // rounding depends on, and leave round_bits room to see that anything
+    // shifted out entirely is less than half.
+    let excess = (66 - value.mantissa.leading_zeros() as i32).max(0);
+    let magnitude = shift_right_jam(value.mantissa, excess as u32) as u64;
+    let lsb = value.exponent + excess;
+    let top = lsb + 63 - magnitude.leading_zeros() as i32;
+
+    let tiny = top < -1022 && {
+        let (rounded, _) = round_bits(magnitude, top - 52 - lsb, sign, rm);
+        top < -1023 || rounded < 1 << 53
+    };
+
+    let quantum = (top - 52).max(-1074);
+    let (mut kept, inexact) = round_bits(magnitude, quantum - lsb, sign, rm);
+    let mut quantum = quantum;
+    if kept == 1 << 53 {
+        kept >>= 1;
+        quantum += 1;
+    }
+
+    let mut flags = 0;
+    if inexact {
+        flags |= FLAG_NX;
+        if tiny {
+            flags |= FLAG_UF;
+        }
+    }
+
+    let sign_bit = if sign { SIGN_F64 } else { 0 };
+    let result = if kept < 1 << 52 {
+        kept
+    } else {
+        let biased = quantum + 52 + 1023;
+        if biased >= 0x7ff {
+            flags |= FLAG_OF | FLAG_NX;
+            let to_infinity = match rm {
+                RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+                RoundingMode::TowardZero => false,
+                RoundingMode::Down => sign,
+                RoundingMode::Up => !sign,
+            };
+            return (
+                sign_bit | if to_infinity { INFINITY_F64 } else { MAX_F64 },
+                flags,
+            );
+        }
+        ((biased as u64) << 52) | (kept & ((1 << 52) - 1))
+    };
+
+    (sign_bit | result, flags)
+}
+
+/// Drops the low `shift` bits of `magnitude`, rounding by `rm`, and returns
+/// what is left and whether any dropped bit was set.
+fn round_bits(magnitude: u64, shift: i32, negative: bool, rm: RoundingMode) -> (u64, bool) {
+    if shift <= 0 {
+        return (magnitude << -shift, false);
+    }
+    // Everything is dropped, and is less than half of the last place kept.
+    if shift >= 64 {
+        let up = match rm {
+            RoundingMode::Down => negative,
+            RoundingMode::Up => !negative,
+            _ => false,
+        };
+        return (up as u64, magnitude != 0);
+    }
+
+    let kept = magnitude >> shift;
+    let rest = magnitude & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    let inexact = rest != 0;
+    let up = match rm {
+        RoundingMode::NearestEven => rest > half || (rest == half && kept & 1 != 0),
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => negative && inexact,
+        RoundingMode::Up => !negative && inexact,
+        RoundingMode::NearestMaxMagnitude => rest >= half,
+    };
+
+    (kept + up as u64, inexact)
+}
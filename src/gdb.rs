@@ -0,0 +1,262 @@
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+
+use crate::hart::Hart;
+
+/// GDB's register number for `pc`, after the 32 integer registers.
+const PC_REGNUM: usize = 32;
+
+/// Stop reply reporting SIGTRAP, sent whenever the hart stops.
+const STOP_TRAP: &str = "S05";
+
+/// Listens on localhost `port`, waits for a single GDB connection and serves
+/// it until the debugger detaches or kills the target.
+pub fn serve(hart: &mut Hart, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    GdbStub::new(stream).run(hart)
+}
+
+/// A GDB remote serial protocol server driving a hart over `stream`.
+///
+/// Software breakpoints are set on the hart rather than patched into guest
+/// memory.
+pub struct GdbStub<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    pub fn new(stream: S) -> Self {
+        GdbStub { stream }
+    }
+
+    /// Handles packets until the debugger detaches, kills the target or
+    /// closes the connection.
+    pub fn run(&mut self, hart: &mut Hart) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.write_packet("OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                _ => self.handle(hart, &packet),
+            };
+            self.write_packet(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single packet and returns its reply. Unsupported packets
+    /// get an empty reply, as the protocol requires.
+    fn handle(&mut self, hart: &mut Hart, packet: &str) -> String {
+        let mut chars = packet.chars();
+        let (command, args) = match chars.next() {
+            Some(c) => (c, &packet[c.len_utf8()..]),
+            None => return String::new(),
+        };
+        let reply = match command {
+            '?' => Some(STOP_TRAP.to_string()),
+            'g' => Some(
+                (0..=PC_REGNUM)
+                    .map(|i| encode_reg(read_reg(hart, i)))
+                    .collect(),
+            ),
+            'G' => write_regs(hart, args),
+            'p' => usize::from_str_radix(args, 16)
+                .ok()
+                .filter(|&i| i <= PC_REGNUM)
+                .map(|i| encode_reg(read_reg(hart, i))),
+            'P' => write_reg(hart, args),
+            'm' => read_memory(hart, args),
+            'M' => write_memory(hart, args),
+            's' => {
+                let _ = hart.step();
+                Some(STOP_TRAP.to_string())
+            }
+            'c' => {
+                hart.run();
+                match hart.exit_code() {
+                    Some(code) => Some(format!("W{:02x}", code as u8)),
+                    None => Some(STOP_TRAP.to_string()),
+                }
+            }
+            'Z' | 'z' => breakpoint(hart, command == 'Z', args),
+            'q' if args.starts_with("Supported") => Some("PacketSize=4000".to_string()),
+            _ => return String::new(),
+        };
+
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    /// Reads the next packet, acknowledging it. Packets with a bad checksum
+    /// are rejected, asking the debugger to send them again. Returns `None`
+    /// once the connection is closed.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and anything else outside a packet.
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(checksum_of(&data));
+            if !valid {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn read_reg(hart: &Hart, i: usize) -> u64 {
+    if i == PC_REGNUM {
+        hart.pc
+    } else {
+        hart.read_reg(i)
+    }
+}
+
+/// Encodes a register as 16 hex digits in target (little-endian) byte order.
+fn encode_reg(value: u64) -> String {
+    encode_bytes(&value.to_le_bytes())
+}
+
+fn decode_reg(hex: &str) -> Option<u64> {
+    let bytes: [u8; 8] = decode_bytes(hex)?.try_into().ok()?;
+    Some(u64::from_le_bytes(bytes))
+}
+
+fn write_regs(hart: &mut Hart, args: &str) -> Option<String> {
+    if args.len() != 16 * (PC_REGNUM + 1) || !args.is_ascii() {
+        return None;
+    }
+
+    for i in 0..=PC_REGNUM {
+        let value = decode_reg(&args[i * 16..(i + 1) * 16])?;
+        if i == PC_REGNUM {
+            hart.pc = value;
+        } else {
+            hart.write_reg(i, value);
+        }
+    }
+    Some("OK".to_string())
+}
+
+/// Handles `P n=r`.
+fn write_reg(hart: &mut Hart, args: &str) -> Option<String> {
+    let (regnum, value) = args.split_once('=')?;
+    let regnum = usize::from_str_radix(regnum, 16).ok()?;
+    let value = decode_reg(value)?;
+
+    match regnum {
+        PC_REGNUM => hart.pc = value,
+        0..PC_REGNUM => hart.write_reg(regnum, value),
+        _ => return None,
+    }
+    Some("OK".to_string())
+}
+
+/// Parses the `addr,length` arguments of a memory packet.
+fn parse_range(args: &str) -> Option<(u64, u64)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        u64::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Handles `m addr,length`, reading through the hart's view of memory.
+/// Device registers cannot be read, as that could change their state.
+fn read_memory(hart: &mut Hart, args: &str) -> Option<String> {
+    let (addr, len) = parse_range(args)?;
+    let bytes = hart.debug_read(addr, usize::try_from(len).ok()?).ok()?;
+
+    Some(encode_bytes(&bytes))
+}
+
+/// Handles `M addr,length:XX...`.
+fn write_memory(hart: &mut Hart, args: &str) -> Option<String> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_range(range)?;
+    let bytes = decode_bytes(data)?;
+    if bytes.len() as u64 != len {
+        return None;
+    }
+
+    hart.debug_write(addr, &bytes).ok()?;
+    Some("OK".to_string())
+}
+
+/// Handles `Z0,addr,kind` and `z0,addr,kind`, inserting or removing a
+/// software breakpoint.
+fn breakpoint(hart: &mut Hart, insert: bool, args: &str) -> Option<String> {
+    let mut fields = args.split(',');
+    if fields.next()? != "0" {
+        return Some(String::new());
+    }
+    let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+
+    if insert {
+        hart.add_breakpoint(addr);
+    } else {
+        hart.remove_breakpoint(addr);
+    }
+    Some("OK".to_string())
+}
+
+fn encode_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
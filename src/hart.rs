@@ -0,0 +1,4112 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bus::Bus;
+use crate::compressed;
+use crate::csr::{
+    Csr, CYCLE, FFLAGS, FRM, INSTRET, MCAUSE, MCAUSE_INTERRUPT, MCOUNTEREN, MEDELEG, MEPC, MIDELEG,
+    MIE, MIP, MIP_MEIP, MIP_MSIP, MIP_MTIP, MISA, MTVAL, MTVEC, MTVEC_VECTORED, SATP, SCAUSE,
+    SCOUNTEREN, SEPC, STVAL, STVEC, TIME,
+};
+use crate::decode_cache::DecodeCache;
+use crate::disassembler::{disassemble, mnemonic};
+use crate::dram::{Dram, DRAM_BASE, DRAM_SIZE};
+use crate::elf::{self, ElfError, SymbolTable};
+use crate::exception::{InstructionException, Interrupt};
+use crate::extensions::Extensions;
+use crate::float::{self, IntType, RoundingMode};
+use crate::htif::Htif;
+use crate::instruction::{
+    opcode, BFormat, CsrFormat, IFormat, ISType, Instruction, InstructionBytes, JFormat, R4Format,
+    RFormat, SFormat, UFormat,
+};
+use crate::mmu::{self, AccessType, SATP_MODE_SV39};
+use crate::pmp;
+use crate::snapshot::{self, MachineState};
+use crate::trace::{TraceFormat, TraceWrite, Tracer};
+use crate::uart::{Uart, UART_BASE, UART_SIZE};
+
+/// ABI names of the integer registers, indexed by register number.
+pub(crate) const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The ABI name of integer register `x{i}`.
+///
+/// # Panics
+///
+/// Panics if `i` is not a register number below 32.
+pub fn reg_name(i: usize) -> &'static str {
+    ABI_NAMES[i]
+}
+
+/// The number of the integer register called `name`, which may be either
+/// its ABI name, such as `t0` or `fp`, or its architectural one, such as
+/// `x5`.
+pub fn reg_index(name: &str) -> Option<usize> {
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(number) = name.strip_prefix('x') {
+        // Reject forms like `x05` or `x+5`, which are not register names.
+        let i = number.parse::<usize>().ok()?;
+        return (i < 32 && i.to_string() == number).then_some(i);
+    }
+
+    ABI_NAMES.iter().position(|&abi| abi == name)
+}
+
+/// ABI names of the floating-point registers, indexed by register number.
+pub(crate) const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// The privilege level a hart is executing at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PrivilegeMode {
+    User = 0b00,
+    Supervisor = 0b01,
+    Machine = 0b11,
+}
+
+impl PrivilegeMode {
+    /// Decodes a two-bit privilege field such as `mstatus.MPP`. The reserved
+    /// encoding `0b10` is treated as User mode.
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0b01 => PrivilegeMode::Supervisor,
+            0b11 => PrivilegeMode::Machine,
+            _ => PrivilegeMode::User,
+        }
+    }
+
+    /// The single-letter name of the mode: `M`, `S` or `U`.
+    pub fn letter(self) -> &'static str {
+        match self {
+            PrivilegeMode::User => "U",
+            PrivilegeMode::Supervisor => "S",
+            PrivilegeMode::Machine => "M",
+        }
+    }
+}
+
+/// Whether executing an instruction already moved the program counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CounterState {
+    /// The instruction set `pc` itself (branches and jumps).
+    Updated,
+    /// The caller still has to advance `pc` to the next instruction.
+    NotUpdated,
+}
+
+/// What a hart does with loads and stores that are not naturally aligned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MisalignedAccess {
+    /// Perform the access one byte at a time.
+    #[default]
+    Emulate,
+    /// Raise an address-misaligned exception.
+    Trap,
+}
+
+/// Why [`Hart::run`] stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    /// An exception that was not handled as a trap, such as an EBREAK.
+    Exception(InstructionException),
+    /// The program exited through the HTIF with the given code, or made an
+    /// ECALL with the code in `a0` when [`Hart::set_halt_on_ecall`] is set.
+    Exit(u64),
+    /// `pc` reached a breakpoint added with [`Hart::add_breakpoint`].
+    Breakpoint(u64),
+    /// An instruction accessed memory watched with [`Hart::add_watchpoint`].
+    Watchpoint(WatchpointHit),
+}
+
+/// The accesses a watchpoint stops on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Both reads and writes.
+    Access,
+}
+
+/// A memory access that hit a watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// The address of the instruction that made the access.
+    pub pc: u64,
+    /// The virtual address accessed.
+    pub addr: u64,
+    /// The value read or written.
+    pub value: u64,
+    /// [`WatchKind::Read`] for a load, [`WatchKind::Write`] for a store.
+    pub kind: WatchKind,
+}
+
+/// What a single step did, as returned by [`Hart::step_verbose`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepRecord {
+    /// `pc` before the step.
+    pub pc: u64,
+    /// The instruction at `pc`, or `None` if it could not be fetched and
+    /// decoded, or it did not execute as the hart stayed stalled on a WFI or
+    /// took an interrupt instead.
+    pub instruction: Option<Instruction>,
+    /// The registers written, then the stores made, in order.
+    pub writes: Vec<TraceWrite>,
+    /// `pc` after the step.
+    pub next_pc: u64,
+    /// The cause of the trap taken, as written to `mcause` or `scause`.
+    pub trap: Option<u64>,
+    /// An exception the step returned rather than took as a trap.
+    pub exception: Option<InstructionException>,
+}
+
+/// Executes one decoded instruction per instruction format.
+pub trait InstructionProcessor {
+    fn process_r(&mut self, inst: RFormat) -> Result<CounterState, InstructionException>;
+    fn process_r4(&mut self, inst: R4Format) -> Result<CounterState, InstructionException>;
+    fn process_i(&mut self, inst: IFormat) -> Result<CounterState, InstructionException>;
+    fn process_is(&mut self, inst: ISType) -> Result<CounterState, InstructionException>;
+    fn process_s(&mut self, inst: SFormat) -> Result<CounterState, InstructionException>;
+    fn process_b(&mut self, inst: BFormat) -> Result<CounterState, InstructionException>;
+    fn process_u(&mut self, inst: UFormat) -> Result<CounterState, InstructionException>;
+    fn process_j(&mut self, inst: JFormat) -> Result<CounterState, InstructionException>;
+    fn process_csr(&mut self, inst: CsrFormat) -> Result<CounterState, InstructionException>;
+}
+
+/// A single RV64 hardware thread.
+pub struct Hart {
+    regs: [u64; 32],
+    /// The floating-point registers. Single-precision values are NaN-boxed.
+    fregs: [u64; 32],
+    pub pc: u64,
+    /// Where `pc` is set on reset.
+    pub reset_vector: u64,
+    pub bus: Bus,
+    pub extensions: Extensions,
+    pub csr: Csr,
+    /// The symbols of the ELF executable loaded, which traces show
+    /// instruction addresses relative to.
+    pub symbols: SymbolTable,
+    mode: PrivilegeMode,
+    /// Instructions decoded so far, by physical address.
+    pub decode_cache: DecodeCache,
+    /// Where executed instructions are traced to, if tracing is enabled.
+    trace: Option<Tracer>,
+    /// How many times each instruction has executed, by mnemonic, if
+    /// profiling is enabled.
+    profile: Option<BTreeMap<&'static str, u64>>,
+    /// Addresses [`Hart::run`] stops at before executing the instruction
+    /// there.
+    breakpoints: HashSet<u64>,
+    /// Watched virtual address ranges, as a start, length and kind.
+    watchpoints: Vec<(u64, u64, WatchKind)>,
+    /// A watchpoint hit by the instruction being executed.
+    watchpoint_hit: Option<WatchpointHit>,
+    /// How loads and stores that are not naturally aligned are handled.
+    misaligned: MisalignedAccess,
+    /// How many ticks `mtime` advances on each step.
+    time_per_step: u64,
+    /// Whether an ECALL stops the hart rather than trapping.
+    halt_on_ecall: bool,
+    /// The stores made since recording them began, for tracing and
+    /// [`Hart::step_verbose`].
+    store_log: Option<Vec<TraceWrite>>,
+    /// The cause of the last trap taken.
+    trap_cause: Option<u64>,
+    /// The physical address of the word reserved by the last LR, until a
+    /// store to it or an SC.
+    reservation: Option<u64>,
+    /// Whether a WFI is stalling the hart until an interrupt is pending.
+    waiting: bool,
+    /// The length in bytes of the instruction being executed: 2 if it is
+    /// compressed, otherwise 4.
+    instruction_size: u64,
+}
+
+impl Hart {
+    /// Creates a hart with `code` loaded at the start of DRAM, where execution
+    /// begins. The stack pointer starts at the top of DRAM, and a UART writing
+    /// to stdout is mapped at `UART_BASE`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` does not fit in DRAM.
+    pub fn new(code: Vec<u8>) -> Self {
+        Self::with_memory(code, DRAM_SIZE)
+    }
+
+    /// Like [`Hart::new`], with `memory_size` bytes of DRAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` does not fit in DRAM.
+    pub fn with_memory(code: Vec<u8>, memory_size: usize) -> Self {
+        let mut bus = Bus::new(Dram::new(DRAM_BASE, memory_size, &code));
+        bus.map(
+            UART_BASE..UART_BASE + UART_SIZE,
+            Box::new(Uart::new(Box::new(io::stdout()))),
+        );
+
+        let mut hart = Hart {
+            regs: [0; 32],
+            fregs: [0; 32],
+            pc: DRAM_BASE,
+            reset_vector: DRAM_BASE,
+            bus,
+            extensions: Extensions::default(),
+            csr: Csr::new(),
+            symbols: SymbolTable::default(),
+            mode: PrivilegeMode::Machine,
+            decode_cache: DecodeCache::new(),
+            trace: None,
+            profile: None,
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            misaligned: MisalignedAccess::default(),
+            time_per_step: 1,
+            halt_on_ecall: false,
+            store_log: None,
+            trap_cause: None,
+            reservation: None,
+            waiting: false,
+            instruction_size: 4,
+        };
+        hart.reset();
+        hart
+    }
+
+    /// Like [`Hart::new`], but execution begins, and restarts on reset, at
+    /// `entry` rather than at the start of DRAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` does not fit in DRAM.
+    pub fn with_entry(code: Vec<u8>, entry: u64) -> Self {
+        let mut hart = Self::new(code);
+        hart.reset_vector = entry;
+        hart.pc = entry;
+        hart
+    }
+
+    /// Creates a hart with `memory_size` bytes of DRAM and `code` loaded at
+    /// physical address `addr` in it, where execution begins and restarts on
+    /// reset. Returns `None` if `code` does not fit in DRAM at `addr`.
+    pub fn with_load_addr(code: &[u8], memory_size: usize, addr: u64) -> Option<Self> {
+        let mut hart = Self::with_memory(Vec::new(), memory_size);
+        hart.bus.dram.write_bytes(addr, code).ok()?;
+        hart.reset_vector = addr;
+        hart.pc = addr;
+        Some(hart)
+    }
+
+    /// Restores the registers, `pc`, CSRs and privilege mode to their reset
+    /// values: `pc` to `reset_vector`, the stack pointer to the top of DRAM,
+    /// and everything else to zero in Machine mode. Memory and devices are
+    /// left as they are.
+    pub fn reset(&mut self) {
+        self.regs = [0; 32];
+        self.fregs = [0; 32];
+        self.regs[2] = self.bus.dram.base() + self.bus.dram.size();
+        self.pc = self.reset_vector;
+        self.csr = Csr::new();
+        self.mode = PrivilegeMode::Machine;
+        self.reservation = None;
+        self.waiting = false;
+    }
+
+    /// Captures the registers, `pc`, CSRs, privilege mode and DRAM contents.
+    pub fn snapshot(&self) -> MachineState {
+        let csrs = self.csr.as_slice().iter().enumerate();
+        let csrs = csrs.filter(|(_, &value)| value != 0);
+
+        MachineState {
+            regs: self.regs,
+            fregs: self.fregs,
+            pc: self.pc,
+            csrs: csrs.map(|(addr, &value)| (addr, value)).collect(),
+            privilege: self.mode,
+            dram: snapshot::encode(self.bus.dram.bytes()),
+        }
+    }
+
+    /// Restores state captured by [`Hart::snapshot`]. Device state, such as
+    /// the timer, is left as it is. CSRs are restored as they were saved, so
+    /// that, for instance, `pmpaddr` registers saved after their entry was
+    /// locked are not dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the snapshot's DRAM is not the same size as this hart's, or
+    /// it holds a CSR address out of range.
+    pub fn restore(&mut self, state: &MachineState) {
+        assert_eq!(
+            snapshot::decoded_len(&state.dram),
+            self.bus.dram.size(),
+            "snapshot DRAM size does not match"
+        );
+
+        self.regs = state.regs;
+        self.fregs = state.fregs;
+        self.pc = state.pc;
+        self.csr = Csr::new();
+        let csrs = self.csr.as_mut_slice();
+        for &(addr, value) in &state.csrs {
+            csrs[addr] = value;
+        }
+        self.mode = state.privilege;
+        self.reservation = None;
+        self.waiting = false;
+        snapshot::decode_into(&state.dram, self.bus.dram.bytes_mut());
+        self.decode_cache.flush();
+    }
+
+    /// Creates a hart with `memory_size` bytes of DRAM holding the loadable
+    /// segments of a static RV64 ELF executable, starting at the ELF's entry
+    /// point. If the ELF defines `tohost` and `fromhost`, an HTIF writing to
+    /// stdout is attached at those addresses.
+    pub fn from_elf(bytes: &[u8], memory_size: usize) -> Result<Self, ElfError> {
+        let elf = elf::parse(bytes)?;
+
+        let mut hart = Hart::with_memory(Vec::new(), memory_size);
+        for segment in &elf.segments {
+            // DRAM starts zeroed, so the part of the segment past its file
+            // data only needs to fit.
+            let size = segment.mem_size.max(segment.data.len() as u64) as usize;
+            if !hart.bus.dram.contains(segment.addr, size) {
+                return Err(ElfError::SegmentOutOfMemory(segment.addr));
+            }
+            hart.bus
+                .dram
+                .write_bytes(segment.addr, &segment.data)
+                .map_err(|_| ElfError::SegmentOutOfMemory(segment.addr))?;
+        }
+        hart.reset_vector = elf.entry;
+        hart.pc = elf.entry;
+        hart.symbols = elf::symbols(bytes)?;
+
+        let tohost = elf::symbol(bytes, "tohost")?;
+        let fromhost = elf::symbol(bytes, "fromhost")?;
+        if let (Some(tohost), Some(fromhost)) = (tohost, fromhost) {
+            hart.bus.htif = Some(Htif::new(tohost, fromhost, Box::new(io::stdout())));
+        }
+
+        Ok(hart)
+    }
+
+    /// The privilege mode the hart is currently executing in.
+    pub fn privilege(&self) -> PrivilegeMode {
+        self.mode
+    }
+
+    /// Reads integer register `x{i}`.
+    pub fn read_reg(&self, i: usize) -> u64 {
+        self.regs[i]
+    }
+
+    /// Writes integer register `x{i}`. Writes to `x0` are discarded, as it is
+    /// hardwired to zero.
+    pub fn write_reg(&mut self, i: usize, v: u64) {
+        if i != 0 {
+            self.regs[i] = v;
+        }
+    }
+
+    /// Reads floating-point register `f{i}`.
+    pub fn read_freg(&self, i: usize) -> u64 {
+        self.fregs[i]
+    }
+
+    /// Writes floating-point register `f{i}`.
+    pub fn write_freg(&mut self, i: usize, v: u64) {
+        self.fregs[i] = v;
+    }
+
+    /// Reads the instruction at `pc`, a halfword at a time so that only a
+    /// 32-bit instruction reads its upper half. Its length follows from the
+    /// low bits, see [`InstructionBytes::size`].
+    pub fn fetch(&mut self) -> Result<InstructionBytes, InstructionException> {
+        let addr = self.translate(self.pc, 2, AccessType::Instruction)?;
+        Ok(self.fetch_from(addr)?.0)
+    }
+
+    /// Fetches the instruction at `pc` and looks up its handler in the
+    /// dispatch table, going through the decode cache.
+    fn fetch_cached(&mut self) -> Result<(InstructionBytes, Handler), InstructionException> {
+        let addr = self.translate(self.pc, 2, AccessType::Instruction)?;
+        if let Some(cached) = self.decode_cache.get(addr) {
+            return Ok(cached);
+        }
+
+        let (instruction, contiguous) = self.fetch_from(addr)?;
+        let handler = dispatch_table()[dispatch_key(instruction)];
+        // Stores only invalidate the entry at the start of the instruction's
+        // physical address, which misses an upper half mapped elsewhere.
+        if contiguous {
+            self.decode_cache.insert(addr, instruction, handler);
+        }
+
+        Ok((instruction, handler))
+    }
+
+    /// Reads the instruction at `pc`, whose first halfword is at physical
+    /// address `addr`, and whether its halves are physically adjacent.
+    ///
+    /// A 32-bit instruction need only be 2-byte aligned, so its upper half
+    /// may be on the next page and is translated separately.
+    fn fetch_from(&mut self, addr: u64) -> Result<(InstructionBytes, bool), InstructionException> {
+        let low = self.fetch_half(addr, self.pc)?;
+        if compressed::is_compressed(low) {
+            return Ok((InstructionBytes(low as u32), true));
+        }
+
+        let next = self.pc.wrapping_add(2);
+        let upper_addr = if next & (mmu::PAGE_SIZE - 1) == 0 {
+            self.translate(next, 2, AccessType::Instruction)?
+        } else {
+            let upper_addr = addr.wrapping_add(2);
+            self.check_pmp(next, upper_addr, 2, AccessType::Instruction)?;
+            upper_addr
+        };
+        let high = self.fetch_half(upper_addr, next)?;
+
+        Ok((
+            InstructionBytes(low as u32 | (high as u32) << 16),
+            upper_addr == addr.wrapping_add(2),
+        ))
+    }
+
+    /// Reads the halfword at physical address `addr`, which `vaddr` maps to.
+    fn fetch_half(&mut self, addr: u64, vaddr: u64) -> Result<u16, InstructionException> {
+        self.bus
+            .load(addr, 2)
+            .map(|half| half as u16)
+            .map_err(|_| InstructionException::InstructionAccessFault(vaddr))
+    }
+
+    /// Sets how loads and stores that are not naturally aligned are
+    /// handled. They are emulated by default.
+    pub fn set_misaligned(&mut self, policy: MisalignedAccess) {
+        self.misaligned = policy;
+    }
+
+    /// Sets how many ticks `mtime` advances on each step, which is 1 by
+    /// default. Time only moves as the hart steps, whether it retires an
+    /// instruction, takes a trap or waits in WFI, so timer interrupts come
+    /// due after a fixed number of steps.
+    pub fn set_time_per_step(&mut self, ticks: u64) {
+        self.time_per_step = ticks;
+    }
+
+    /// Makes an ECALL stop [`Hart::run`] with [`Halt::Exit`] and the code in
+    /// `a0`, instead of trapping, for bare-metal programs without a trap
+    /// handler. ECALLs trap by default.
+    pub fn set_halt_on_ecall(&mut self, halt: bool) {
+        self.halt_on_ecall = halt;
+    }
+
+    /// Reads `size` bytes (1, 2, 4 or 8) at virtual address `addr` as a
+    /// little-endian value, or big-endian if `mstatus` selects that for the
+    /// current mode.
+    pub fn load(&mut self, addr: u64, size: usize) -> Result<u64, InstructionException> {
+        let value = if addr.is_multiple_of(size as u64) {
+            self.load_aligned(addr, size)?
+        } else {
+            match self.misaligned {
+                MisalignedAccess::Trap => {
+                    return Err(InstructionException::LoadAddressMisaligned(addr))
+                }
+                // Each byte is translated on its own, as the access may
+                // cross into another page.
+                MisalignedAccess::Emulate => (0..size).try_fold(0, |value, i| {
+                    let byte = self.load_aligned(addr.wrapping_add(i as u64), 1)?;
+                    Ok(value | byte << (8 * i))
+                })?,
+            }
+        };
+        let value = self.data_endian(value, size);
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, size, value, WatchKind::Read);
+        }
+
+        Ok(value)
+    }
+
+    fn load_aligned(&mut self, addr: u64, size: usize) -> Result<u64, InstructionException> {
+        let paddr = self.translate(addr, size, AccessType::Load)?;
+        self.bus
+            .load(paddr, size)
+            .map_err(|_| InstructionException::LoadAccessFault(addr))
+    }
+
+    /// Writes the low `size` bytes (1, 2, 4 or 8) of `value` to virtual
+    /// address `addr` in little-endian order, or big-endian if `mstatus`
+    /// selects that for the current mode.
+    pub fn store(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), InstructionException> {
+        let value = value & (u64::MAX >> (64 - 8 * size));
+        let bytes = self.data_endian(value, size);
+        if addr.is_multiple_of(size as u64) {
+            self.store_aligned(addr, size, bytes)?;
+        } else {
+            match self.misaligned {
+                MisalignedAccess::Trap => {
+                    return Err(InstructionException::StoreAddressMisaligned(addr))
+                }
+                // A fault part way leaves the earlier bytes written, which
+                // the spec allows for misaligned stores.
+                MisalignedAccess::Emulate => {
+                    for i in 0..size {
+                        let byte = bytes >> (8 * i);
+                        self.store_aligned(addr.wrapping_add(i as u64), 1, byte)?;
+                    }
+                }
+            }
+        }
+        if let Some(log) = &mut self.store_log {
+            log.push(TraceWrite::Mem { addr, size, value });
+        }
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, size, value, WatchKind::Write);
+        }
+
+        Ok(())
+    }
+
+    /// Converts between the little-endian order of memory and that of data
+    /// accesses in the current mode, a swap of the low `size` bytes of
+    /// `value` if they are big-endian. Instruction fetches are always
+    /// little-endian.
+    fn data_endian(&self, value: u64, size: usize) -> u64 {
+        if self.csr.mstatus().big_endian(self.mode) {
+            value.swap_bytes() >> (64 - 8 * size)
+        } else {
+            value
+        }
+    }
+
+    fn store_aligned(
+        &mut self,
+        addr: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), InstructionException> {
+        let paddr = self.translate(addr, size, AccessType::Store)?;
+        self.bus
+            .store(paddr, size, value)
+            .map_err(|_| InstructionException::StoreAccessFault(addr))?;
+        self.decode_cache.invalidate(paddr, size);
+        if self
+            .reservation
+            .is_some_and(|reserved| paddr < reserved + 4 && reserved < paddr + size as u64)
+        {
+            self.reservation = None;
+        }
+
+        Ok(())
+    }
+
+    /// Records a hit if the `size` bytes accessed at `addr` overlap a
+    /// watchpoint of a matching kind.
+    fn watch(&mut self, addr: u64, size: usize, value: u64, kind: WatchKind) {
+        let last = addr.wrapping_add(size as u64 - 1);
+        let hit = self.watchpoints.iter().any(|&(start, len, watched)| {
+            let end = start.wrapping_add(len - 1);
+            (watched == kind || watched == WatchKind::Access) && addr <= end && start <= last
+        });
+        if hit {
+            self.watchpoint_hit = Some(WatchpointHit {
+                pc: self.pc,
+                addr,
+                value,
+                kind,
+            });
+        }
+    }
+
+    /// Reads `len` bytes at virtual address `addr` as the current mode sees
+    /// them, for debuggers. Unlike loads, this has no side effects: PMP and
+    /// watchpoints are ignored, and only DRAM is read, as reading a device
+    /// register could change its state.
+    pub fn debug_read(&mut self, addr: u64, len: usize) -> Result<Vec<u8>, InstructionException> {
+        (0..len as u64)
+            .map(|i| {
+                let byte = addr.wrapping_add(i);
+                let paddr = self.translate_page(byte, AccessType::Load)?;
+                let value = self.bus.dram.load(paddr, 1);
+                value
+                    .map(|value| value as u8)
+                    .map_err(|_| InstructionException::LoadAccessFault(byte))
+            })
+            .collect()
+    }
+
+    /// Writes `bytes` to virtual address `addr` as the current mode sees it,
+    /// for debuggers. Like [`Hart::debug_read`], only DRAM can be written,
+    /// and PMP and watchpoints are ignored. A fault part way leaves the
+    /// earlier bytes written.
+    pub fn debug_write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), InstructionException> {
+        for (i, &value) in bytes.iter().enumerate() {
+            let byte = addr.wrapping_add(i as u64);
+            let paddr = self.translate_page(byte, AccessType::Store)?;
+            self.bus
+                .dram
+                .store(paddr, 1, value as u64)
+                .map_err(|_| InstructionException::StoreAccessFault(byte))?;
+            self.decode_cache.invalidate(paddr, 1);
+        }
+
+        Ok(())
+    }
+
+    /// Reads `len` bytes at physical address `addr` straight from DRAM,
+    /// bypassing translation, for inspecting memory from tests and
+    /// debuggers. Devices are not readable, as reading their registers can
+    /// change their state.
+    pub fn mem_read(&self, addr: u64, len: usize) -> Result<Vec<u8>, InstructionException> {
+        if !self.bus.dram.contains(addr, len) {
+            return Err(InstructionException::LoadAccessFault(addr));
+        }
+
+        let start = (addr - self.bus.dram.base()) as usize;
+        Ok(self.bus.dram.bytes()[start..start + len].to_vec())
+    }
+
+    /// Writes `bytes` to physical address `addr` straight to DRAM, bypassing
+    /// translation, for seeding memory from tests and debuggers. Nothing is
+    /// written unless all of `bytes` fit in DRAM.
+    pub fn mem_write(&mut self, addr: u64, bytes: &[u8]) -> Result<(), InstructionException> {
+        if !self.bus.dram.contains(addr, bytes.len()) {
+            return Err(InstructionException::StoreAccessFault(addr));
+        }
+
+        let start = (addr - self.bus.dram.base()) as usize;
+        self.bus.dram.bytes_mut()[start..start + bytes.len()].copy_from_slice(bytes);
+        if !bytes.is_empty() {
+            self.decode_cache.invalidate(addr, bytes.len());
+        }
+
+        Ok(())
+    }
+
+    /// Translates virtual address `addr` for an `access` to `size` bytes,
+    /// which PMP then has to allow.
+    fn translate(
+        &mut self,
+        addr: u64,
+        size: usize,
+        access: AccessType,
+    ) -> Result<u64, InstructionException> {
+        let paddr = self.translate_page(addr, access)?;
+        self.check_pmp(addr, paddr, size, access)?;
+
+        Ok(paddr)
+    }
+
+    /// Translates virtual address `addr` through the page tables alone.
+    /// Machine mode, and any mode while `satp` selects Bare, uses physical
+    /// addresses directly.
+    fn translate_page(
+        &mut self,
+        addr: u64,
+        access: AccessType,
+    ) -> Result<u64, InstructionException> {
+        if self.mode == PrivilegeMode::Machine || self.csr.read(SATP) >> 60 != SATP_MODE_SV39 {
+            return Ok(addr);
+        }
+
+        mmu::translate(&mut self.bus, &self.csr, self.mode, addr, access)
+    }
+
+    /// Faults unless PMP allows an `access` to the `size` bytes at physical
+    /// address `paddr`, which virtual address `addr` maps to.
+    fn check_pmp(
+        &self,
+        addr: u64,
+        paddr: u64,
+        size: usize,
+        access: AccessType,
+    ) -> Result<(), InstructionException> {
+        if pmp::permits(&self.csr, paddr, size, access, self.mode) {
+            Ok(())
+        } else {
+            Err(access.access_fault(addr))
+        }
+    }
+
+    /// Fetches, decodes and executes the instruction at `pc`.
+    ///
+    /// After a WFI, steps only advance time until an interrupt enabled in
+    /// `mie` is pending. If an enabled interrupt is pending, it is taken
+    /// instead, and the instruction runs on a later step.
+    ///
+    /// Exceptions raised by the instruction are taken as traps, except for
+    /// breakpoints, which stop the hart: they are returned with `pc` still
+    /// pointing at the EBREAK. ECALLs stop the hart the same way while
+    /// [`Hart::set_halt_on_ecall`] is set. An exception is also returned
+    /// rather than taken when the trap vector points at unmapped memory, as
+    /// the trap would only fault again, for instance when a program without a
+    /// handler runs off the end of memory.
+    pub fn step(&mut self) -> Result<(), InstructionException> {
+        self.tick();
+        if self.waiting {
+            if self.csr.read(MIP) & self.csr.read(MIE) == 0 {
+                return Ok(());
+            }
+            self.waiting = false;
+        }
+        if let Some(interrupt) = self.check_interrupts() {
+            self.take_interrupt(interrupt);
+            return Ok(());
+        }
+
+        match self.execute_next() {
+            Err(InstructionException::Breakpoint(addr)) => {
+                Err(InstructionException::Breakpoint(addr))
+            }
+            Err(exception) if self.halts_on(exception) => Err(exception),
+            Err(exception)
+                if !self
+                    .bus
+                    .is_mapped(self.trap_vector(exception.code()) & !0b11, 4) =>
+            {
+                Err(exception)
+            }
+            Err(exception) => {
+                self.take_trap(exception);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Advances the timer and cycle counter, and reflects the interrupt lines
+    /// of the CLINT and PLIC in `mip`.
+    fn tick(&mut self) {
+        self.bus.clint.tick(self.time_per_step);
+        self.csr.write(CYCLE, self.csr.read(CYCLE).wrapping_add(1));
+        self.csr.write(TIME, self.bus.clint.mtime());
+
+        let mut mip = self.csr.read(MIP) & !(MIP_MSIP | MIP_MTIP | MIP_MEIP);
+        if self.bus.clint.software_pending(0) {
+            mip |= MIP_MSIP;
+        }
+        if self.bus.clint.timer_pending(0) {
+            mip |= MIP_MTIP;
+        }
+        if self.bus.plic.interrupt_pending() {
+            mip |= MIP_MEIP;
+        }
+        self.csr.write(MIP, mip);
+    }
+
+    /// The highest-priority interrupt that is pending, enabled in `mie`, and
+    /// not masked by the interrupt enable of the mode it is taken in.
+    /// Interrupts taken in Machine mode come before delegated ones.
+    fn check_interrupts(&self) -> Option<Interrupt> {
+        // Interrupts are always enabled in modes less privileged than the
+        // one they are taken in, only by xIE in that mode itself, and never
+        // in a more privileged one.
+        let mstatus = self.csr.mstatus();
+        let machine_enabled = self.mode != PrivilegeMode::Machine || mstatus.mie();
+        let supervisor_enabled = match self.mode {
+            PrivilegeMode::User => true,
+            PrivilegeMode::Supervisor => mstatus.sie(),
+            PrivilegeMode::Machine => false,
+        };
+
+        let pending = self.csr.read(MIP) & self.csr.read(MIE);
+        let delegated = self.csr.read(MIDELEG);
+        let machine = if machine_enabled {
+            pending & !delegated
+        } else {
+            0
+        };
+        let supervisor = if supervisor_enabled {
+            pending & delegated
+        } else {
+            0
+        };
+        [machine, supervisor].into_iter().find_map(|pending| {
+            Interrupt::PRIORITY
+                .into_iter()
+                .find(|interrupt| pending & (1 << interrupt.code()) != 0)
+        })
+    }
+
+    /// Executes the instruction at `pc` with its handler from the dispatch
+    /// table, or decodes it if it is traced or profiled.
+    fn execute_next(&mut self) -> Result<(), InstructionException> {
+        let (instruction, handler) = self.fetch_cached()?;
+        self.instruction_size = instruction.size();
+        let state = if self.trace.is_none() && self.profile.is_none() {
+            handler(self, instruction)?
+        } else {
+            execute_slow(self, instruction)?
+        };
+        match state {
+            CounterState::Updated => {}
+            CounterState::NotUpdated => self.pc = self.pc.wrapping_add(self.instruction_size),
+        }
+        self.csr
+            .write(INSTRET, self.csr.read(INSTRET).wrapping_add(1));
+
+        Ok(())
+    }
+
+    /// Enters the trap handler for `exception` raised by the instruction at
+    /// `pc`.
+    pub fn take_trap(&mut self, exception: InstructionException) {
+        let tvec = self.enter_trap(exception.code(), exception.tval());
+
+        // Direct and vectored mode both send synchronous exceptions to the
+        // base address.
+        self.pc = tvec & !0b11;
+    }
+
+    /// Enters the trap handler for `interrupt`, which resumes at the not yet
+    /// executed instruction at `pc`.
+    pub fn take_interrupt(&mut self, interrupt: Interrupt) {
+        let tvec = self.enter_trap(MCAUSE_INTERRUPT | interrupt.code(), 0);
+
+        // In vectored mode, each interrupt has its own entry after the base.
+        self.pc = match tvec & 0b11 {
+            MTVEC_VECTORED => (tvec & !0b11).wrapping_add(4 * interrupt.code()),
+            _ => tvec & !0b11,
+        };
+    }
+
+    /// Whether a trap with `cause` is delegated to Supervisor mode by
+    /// `medeleg` or `mideleg`. Traps taken in Machine mode never are.
+    fn is_delegated(&self, cause: u64) -> bool {
+        let (deleg, code) = if cause & MCAUSE_INTERRUPT != 0 {
+            (MIDELEG, cause & !MCAUSE_INTERRUPT)
+        } else {
+            (MEDELEG, cause)
+        };
+        self.mode != PrivilegeMode::Machine && (self.csr.read(deleg) >> code) & 1 != 0
+    }
+
+    /// The `mtvec` or `stvec` of the mode a trap with `cause` is taken in.
+    fn trap_vector(&self, cause: u64) -> u64 {
+        if self.is_delegated(cause) {
+            self.csr.read(STVEC)
+        } else {
+            self.csr.read(MTVEC)
+        }
+    }
+
+    /// Records the cause of a trap and switches to the mode it is taken in,
+    /// returning that mode's trap vector for `pc` to be pointed at.
+    fn enter_trap(&mut self, cause: u64, tval: u64) -> u64 {
+        self.trap_cause = Some(cause);
+        let tvec = self.trap_vector(cause);
+        let mut mstatus = self.csr.mstatus();
+        if self.is_delegated(cause) {
+            self.csr.write(SEPC, self.pc);
+            self.csr.write(SCAUSE, cause);
+            self.csr.write(STVAL, tval);
+
+            // The same as below, with the supervisor fields of mstatus,
+            // which are also those of sstatus.
+            mstatus.set_spie(mstatus.sie());
+            mstatus.set_sie(false);
+            mstatus.set_spp(self.mode);
+            self.mode = PrivilegeMode::Supervisor;
+        } else {
+            self.csr.write(MEPC, self.pc);
+            self.csr.write(MCAUSE, cause);
+            self.csr.write(MTVAL, tval);
+
+            // Push MIE onto MPIE, disable interrupts and record the mode the
+            // trap was taken from.
+            mstatus.set_mpie(mstatus.mie());
+            mstatus.set_mie(false);
+            mstatus.set_mpp(self.mode);
+            self.mode = PrivilegeMode::Machine;
+        }
+        self.csr.set_mstatus(mstatus);
+        tvec
+    }
+
+    /// Returns from a machine-mode trap handler to `mepc`.
+    fn mret(&mut self) -> CounterState {
+        // Pop MPIE back into MIE, set MPIE, and return to the mode in MPP,
+        // leaving MPP at the least-privileged mode.
+        let mut mstatus = self.csr.mstatus();
+        self.mode = mstatus.mpp();
+        mstatus.set_mie(mstatus.mpie());
+        mstatus.set_mpie(true);
+        mstatus.set_mpp(PrivilegeMode::User);
+        self.csr.set_mstatus(mstatus);
+
+        self.pc = self.csr.read(MEPC);
+
+        CounterState::Updated
+    }
+
+    /// Returns from a supervisor-mode trap handler to `sepc`.
+    fn sret(&mut self) -> CounterState {
+        // Like MRET, but with SPIE, SIE and SPP, which can only hold
+        // Supervisor or User mode.
+        let mut mstatus = self.csr.mstatus();
+        self.mode = mstatus.spp();
+        mstatus.set_sie(mstatus.spie());
+        mstatus.set_spie(true);
+        mstatus.set_spp(PrivilegeMode::User);
+        self.csr.set_mstatus(mstatus);
+
+        self.pc = self.csr.read(SEPC);
+
+        CounterState::Updated
+    }
+
+    /// Steps the hart until it stops on an exception that is not handled as
+    /// a trap, such as an EBREAK, the program exits, `pc` reaches a
+    /// breakpoint or an instruction hits a watchpoint. A breakpoint at `pc`
+    /// when called is stepped over, so a run can resume from a breakpoint it
+    /// stopped at.
+    pub fn run(&mut self) -> Halt {
+        loop {
+            if let Some(halt) = self.step_or_halt() {
+                return halt;
+            }
+        }
+    }
+
+    /// Like [`Hart::run`], but gives up after `max_steps` steps, returning
+    /// `None` if the hart has not stopped by then.
+    pub fn run_until(&mut self, max_steps: u64) -> Option<Halt> {
+        (0..max_steps).find_map(|_| self.step_or_halt())
+    }
+
+    /// Like [`Hart::run_until`], but also stops when `pc` reaches virtual
+    /// address `addr`, returning [`Halt::Breakpoint`] as if one were set
+    /// there.
+    pub fn run_to(&mut self, addr: u64, max_steps: u64) -> Option<Halt> {
+        let added = self.breakpoints.insert(addr);
+        let halt = self.run_until(max_steps);
+        if added {
+            self.breakpoints.remove(&addr);
+        }
+        halt
+    }
+
+    /// Steps once, returning why the hart stopped if it did.
+    fn step_or_halt(&mut self) -> Option<Halt> {
+        self.watchpoint_hit = None;
+        match self.step() {
+            Err(exception) if self.halts_on(exception) => {
+                return Some(Halt::Exit(self.read_reg(10)));
+            }
+            Err(exception) => return Some(Halt::Exception(exception)),
+            Ok(()) => {}
+        }
+        if let Some(code) = self.exit_code() {
+            return Some(Halt::Exit(code));
+        }
+        if let Some(hit) = self.watchpoint_hit {
+            return Some(Halt::Watchpoint(hit));
+        }
+        self.breakpoints
+            .contains(&self.pc)
+            .then_some(Halt::Breakpoint(self.pc))
+    }
+
+    /// Whether `exception` is an ECALL that stops the hart, as set by
+    /// [`Hart::set_halt_on_ecall`].
+    fn halts_on(&self, exception: InstructionException) -> bool {
+        self.halt_on_ecall
+            && matches!(
+                exception,
+                InstructionException::EnvironmentCallFromUMode
+                    | InstructionException::EnvironmentCallFromSMode
+                    | InstructionException::EnvironmentCallFromMMode
+            )
+    }
+
+    /// Makes [`Hart::run`] stop when `pc` reaches virtual address `addr`,
+    /// without modifying guest memory.
+    pub fn add_breakpoint(&mut self, addr: u64) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at `addr`; removing one that is not set does
+    /// nothing.
+    pub fn remove_breakpoint(&mut self, addr: u64) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Makes [`Hart::run`] stop after an instruction makes a `kind` access
+    /// to any of the `len` bytes at virtual address `addr`, once the
+    /// instruction completes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn add_watchpoint(&mut self, addr: u64, len: u64, kind: WatchKind) {
+        assert!(len > 0, "watchpoints cover at least one byte");
+        self.watchpoints.push((addr, len, kind));
+    }
+
+    /// Removes the watchpoint added with the same `addr`, `len` and `kind`;
+    /// removing one that is not set does nothing.
+    pub fn remove_watchpoint(&mut self, addr: u64, len: u64, kind: WatchKind) {
+        self.watchpoints.retain(|&watch| watch != (addr, len, kind));
+    }
+
+    /// The code the program exited with through the HTIF, once it has.
+    pub fn exit_code(&self) -> Option<u64> {
+        self.bus.htif.as_ref().and_then(Htif::exit_code)
+    }
+
+    /// `pc`, the privilege mode and every integer register as a JSON object,
+    /// with values as hex strings: `{"pc":"0x…","mode":"M","x":["0x…",…]}`.
+    pub fn registers_json(&self) -> String {
+        let regs: Vec<String> = self
+            .regs
+            .iter()
+            .map(|reg| format!("\"{:#018x}\"", reg))
+            .collect();
+        format!(
+            "{{\"pc\":\"{:#018x}\",\"mode\":\"{}\",\"x\":[{}]}}",
+            self.pc,
+            self.mode.letter(),
+            regs.join(",")
+        )
+    }
+
+    /// Prints `pc` and every integer register to stdout.
+    pub fn dump_registers(&self) {
+        println!("pc = {:#018x}", self.pc);
+        for row in 0..8 {
+            let line: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|i| format!("x{:<2}({:>4}) = {:#018x}", i, reg_name(i), self.regs[i]))
+                .collect();
+            println!("{}", line.join("  "));
+        }
+    }
+
+    /// Decodes a single instruction word and executes it.
+    pub fn execute(
+        &mut self,
+        instruction: InstructionBytes,
+    ) -> Result<CounterState, InstructionException> {
+        self.instruction_size = instruction.size();
+        if self.trace.is_none() && self.profile.is_none() {
+            return dispatch_table()[dispatch_key(instruction)](self, instruction);
+        }
+
+        let decoded = Instruction::try_from(instruction)?;
+        self.execute_decoded(instruction, decoded)
+    }
+
+    /// Executes an already decoded instruction, tracing it if enabled.
+    fn execute_decoded(
+        &mut self,
+        instruction: InstructionBytes,
+        decoded: Instruction,
+    ) -> Result<CounterState, InstructionException> {
+        if !self.extensions.supports(&decoded)
+            || (instruction.is_compressed() && !self.extensions.c)
+            || !self.permits(&decoded)
+        {
+            return Err(InstructionException::IllegalInstruction(instruction.0));
+        }
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(mnemonic(&decoded)).or_default() += 1;
+        }
+        if self.trace.is_none() {
+            return self.dispatch(decoded);
+        }
+
+        let (pc, mode) = (self.pc, self.mode);
+        let disassembly = disassemble(&decoded, pc);
+        if let Some(tracer) = &mut self.trace {
+            tracer.start(pc, self.symbols.describe(pc), instruction, &disassembly);
+        }
+        let (before, before_f) = (self.regs, self.fregs);
+        // Stores may already be recorded for step_verbose, in which case
+        // they are left recorded.
+        let recording = self.store_log.is_some();
+        let log = self.store_log.get_or_insert_with(Vec::new);
+        let first_store = log.len();
+        let result = self.dispatch(decoded);
+
+        let mut writes = self.register_writes(&before, &before_f);
+        if let Some(log) = &mut self.store_log {
+            writes.extend_from_slice(&log[first_store..]);
+        }
+        if !recording {
+            self.store_log = None;
+        }
+        if let Some(tracer) = &mut self.trace {
+            tracer.finish(pc, mode, instruction, &disassembly, &writes, result.is_ok());
+        }
+
+        result
+    }
+
+    /// The integer and then floating-point registers that differ from
+    /// `before` and `before_f`.
+    fn register_writes(&self, before: &[u64; 32], before_f: &[u64; 32]) -> Vec<TraceWrite> {
+        let mut writes = Vec::new();
+        for i in 1..32 {
+            if self.regs[i] != before[i] {
+                writes.push(TraceWrite::Reg {
+                    name: ABI_NAMES[i],
+                    old: before[i],
+                    new: self.regs[i],
+                });
+            }
+        }
+        for i in 0..32 {
+            if self.fregs[i] != before_f[i] {
+                writes.push(TraceWrite::Reg {
+                    name: FP_ABI_NAMES[i],
+                    old: before_f[i],
+                    new: self.fregs[i],
+                });
+            }
+        }
+        writes
+    }
+
+    /// Like [`Hart::step`], but records what the step did: the instruction
+    /// executed, the registers and memory it wrote, where `pc` went, and any
+    /// trap taken. Plain steps record none of this.
+    pub fn step_verbose(&mut self) -> StepRecord {
+        let (pc, waiting) = (self.pc, self.waiting);
+        let instruction = self
+            .fetch()
+            .ok()
+            .and_then(|instruction| Instruction::try_from(instruction).ok());
+        let (before, before_f) = (self.regs, self.fregs);
+        self.store_log = Some(Vec::new());
+        self.trap_cause = None;
+
+        let exception = self.step().err();
+
+        let mut writes = self.register_writes(&before, &before_f);
+        writes.extend(self.store_log.take().unwrap_or_default());
+        let stalled = waiting && self.waiting;
+        let interrupted = self
+            .trap_cause
+            .is_some_and(|cause| cause & MCAUSE_INTERRUPT != 0);
+        StepRecord {
+            pc,
+            instruction: instruction.filter(|_| !interrupted && !stalled),
+            writes,
+            next_pc: self.pc,
+            trap: self.trap_cause,
+            exception,
+        }
+    }
+
+    /// Enables or disables tracing each executed instruction to stdout.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = enabled.then(|| Tracer::new(Box::new(io::stdout()), TraceFormat::Text));
+    }
+
+    /// Enables tracing each executed instruction to `output`.
+    pub fn trace_to(&mut self, output: Box<dyn Write>) {
+        self.trace = Some(Tracer::new(output, TraceFormat::Text));
+    }
+
+    /// Enables tracing each retired instruction to `output` as a line of
+    /// JSON, with its pc, bits, disassembly, privilege mode, and the
+    /// registers and memory it wrote.
+    pub fn trace_json_to(&mut self, output: Box<dyn Write>) {
+        self.trace = Some(Tracer::new(output, TraceFormat::Json));
+    }
+
+    /// Enables or disables counting how many times each instruction
+    /// executes. Enabling it starts the counts from zero.
+    pub fn set_profile(&mut self, enabled: bool) {
+        self.profile = enabled.then(BTreeMap::new);
+    }
+
+    /// How many times each instruction has executed since profiling was
+    /// enabled, by mnemonic. Empty if profiling is disabled.
+    pub fn instruction_counts(&self) -> BTreeMap<&'static str, u64> {
+        self.profile.clone().unwrap_or_default()
+    }
+
+    fn dispatch(&mut self, decoded: Instruction) -> Result<CounterState, InstructionException> {
+        match decoded {
+            Instruction::R(inst) => self.process_r(inst),
+            Instruction::R4(inst) => self.process_r4(inst),
+            Instruction::I(inst) => self.process_i(inst),
+            Instruction::IS(inst) => self.process_is(inst),
+            Instruction::S(inst) => self.process_s(inst),
+            Instruction::B(inst) => self.process_b(inst),
+            Instruction::U(inst) => self.process_u(inst),
+            Instruction::J(inst) => self.process_j(inst),
+            Instruction::Csr(inst) => self.process_csr(inst),
+            // Memory and instruction fetch are never reordered, so fences
+            // have nothing to do.
+            Instruction::Fence(_) => Ok(CounterState::NotUpdated),
+            // Stores already keep the decode cache coherent, but FENCE.I is
+            // where software expects modified code to become visible.
+            Instruction::FenceI => {
+                self.decode_cache.flush();
+                Ok(CounterState::NotUpdated)
+            }
+            Instruction::Ecall => Err(match self.mode {
+                PrivilegeMode::User => InstructionException::EnvironmentCallFromUMode,
+                PrivilegeMode::Supervisor => InstructionException::EnvironmentCallFromSMode,
+                PrivilegeMode::Machine => InstructionException::EnvironmentCallFromMMode,
+            }),
+            Instruction::Ebreak => Err(InstructionException::Breakpoint(self.pc)),
+            Instruction::Mret => Ok(self.mret()),
+            Instruction::Sret => Ok(self.sret()),
+            Instruction::Wfi => {
+                self.waiting = true;
+                Ok(CounterState::NotUpdated)
+            }
+            // There is no TLB, so page-table updates are always visible. The
+            // decode cache is physically indexed but is flushed anyway, as
+            // the mapping of pc to code has changed.
+            Instruction::SfenceVma(_) => {
+                self.decode_cache.flush();
+                Ok(CounterState::NotUpdated)
+            }
+        }
+    }
+
+    /// Whether the hart's current state allows `inst` to execute.
+    fn permits(&self, inst: &Instruction) -> bool {
+        match inst {
+            Instruction::Csr(csr) => {
+                Csr::is_implemented(csr.csr)
+                    && Csr::min_privilege(csr.csr) <= self.mode
+                    && !(csr.writes_csr() && Csr::is_read_only(csr.csr))
+                    && !(csr.csr == SATP && self.traps_vm())
+                    && self.counter_enabled(csr.csr)
+            }
+            Instruction::Mret => self.mode == PrivilegeMode::Machine,
+            // mstatus.TSR traps SRET in Supervisor mode.
+            Instruction::Sret => match self.mode {
+                PrivilegeMode::Machine => true,
+                PrivilegeMode::Supervisor => !self.csr.mstatus().tsr(),
+                PrivilegeMode::User => false,
+            },
+            // mstatus.TW traps WFI below Machine mode.
+            Instruction::Wfi => self.mode == PrivilegeMode::Machine || !self.csr.mstatus().tw(),
+            Instruction::SfenceVma(_) => self.mode != PrivilegeMode::User && !self.traps_vm(),
+            // A dynamic rounding mode has to select a valid mode in frm.
+            Instruction::R(r) if r.has_rounding_mode() => self.rounding_mode(r.funct3).is_some(),
+            Instruction::R4(r4) => self.rounding_mode(r4.funct3).is_some(),
+            _ => true,
+        }
+    }
+
+    /// The rounding mode selected by an instruction's `rm` field, reading
+    /// `frm` for the dynamic mode. `None` if the mode is reserved.
+    fn rounding_mode(&self, rm: u32) -> Option<RoundingMode> {
+        match rm {
+            0b111 => RoundingMode::from_bits(self.csr.read(FRM)),
+            _ => RoundingMode::from_bits(rm as u64),
+        }
+    }
+
+    /// Whether the current mode may read CSR `addr` if it is one of the
+    /// `cycle`, `time` or `instret` counters. `mcounteren` gates them below
+    /// Machine mode, and `scounteren` also gates them in User mode.
+    fn counter_enabled(&self, addr: usize) -> bool {
+        let enabled = |counteren| self.csr.read(counteren) & (1 << (addr - CYCLE)) != 0;
+        match (addr, self.mode) {
+            (CYCLE..=INSTRET, PrivilegeMode::Supervisor) => enabled(MCOUNTEREN),
+            (CYCLE..=INSTRET, PrivilegeMode::User) => enabled(MCOUNTEREN) && enabled(SCOUNTEREN),
+            _ => true,
+        }
+    }
+
+    /// Whether mstatus.TVM traps virtual-memory management in the current
+    /// mode, which it only does in Supervisor mode.
+    fn traps_vm(&self) -> bool {
+        self.mode == PrivilegeMode::Supervisor && self.csr.mstatus().tvm()
+    }
+
+    fn execute_op(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+        let rs2 = self.read_reg(inst.rs2);
+        // RV64 shifts only look at the low 6 bits of rs2.
+        let shamt = (rs2 & 0x3f) as u32;
+
+        let value = match inst.funct3 {
+            // ADD / SUB
+            0x0 if inst.funct7 == 0x20 => rs1.wrapping_sub(rs2),
+            0x0 => rs1.wrapping_add(rs2),
+            // SLL
+            0x1 => rs1 << shamt,
+            // SLT
+            0x2 => ((rs1 as i64) < (rs2 as i64)) as u64,
+            // SLTU
+            0x3 => (rs1 < rs2) as u64,
+            // XOR
+            0x4 => rs1 ^ rs2,
+            // SRA / SRL
+            0x5 if inst.funct7 == 0x20 => ((rs1 as i64) >> shamt) as u64,
+            0x5 => rs1 >> shamt,
+            // OR
+            0x6 => rs1 | rs2,
+            // AND
+            0x7 => rs1 & rs2,
+            _ => unreachable!("funct3 is a 3-bit field"),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_op_m(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+        let rs2 = self.read_reg(inst.rs2);
+
+        let value = match inst.funct3 {
+            // MUL
+            0x0 => rs1.wrapping_mul(rs2),
+            // MULH
+            0x1 => ((rs1 as i64 as i128 * rs2 as i64 as i128) >> 64) as u64,
+            // MULHSU
+            0x2 => ((rs1 as i64 as i128 * rs2 as i128) >> 64) as u64,
+            // MULHU
+            0x3 => ((rs1 as u128 * rs2 as u128) >> 64) as u64,
+            // DIV / DIVU / REM / REMU
+            _ => divide(inst.funct3, rs1, rs2, 64),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_op_32_m(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+        let rs2 = self.read_reg(inst.rs2);
+
+        let value = match inst.funct3 {
+            // MULW
+            0x0 => (rs1 as u32).wrapping_mul(rs2 as u32) as i32 as i64 as u64,
+            // DIVW / DIVUW / REMW / REMUW
+            _ => divide(inst.funct3, rs1, rs2, 32),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    /// Executes LR, SC or an AMO, on a word (funct3 0x2) or a doubleword
+    /// (0x3). Every access is sequentially consistent, so the aq and rl bits
+    /// need no handling.
+    fn execute_amo(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let addr = self.read_reg(inst.rs1);
+        let size = if inst.funct3 == 0x2 { 4 } else { 8 };
+
+        let value = match inst.funct7 >> 2 {
+            // LR.W / LR.D
+            0b00010 => {
+                if !addr.is_multiple_of(size as u64) {
+                    return Err(InstructionException::LoadAccessFault(addr));
+                }
+                let paddr = self.translate(addr, size, AccessType::Load)?;
+                let value = match size {
+                    4 => self.load(addr, 4)? as i32 as i64 as u64,
+                    _ => self.load(addr, 8)?,
+                };
+                self.reservation = Some(paddr);
+                value
+            }
+            // SC.W / SC.D, which fail with a nonzero rd and always end the
+            // reservation.
+            0b00011 => {
+                if !addr.is_multiple_of(size as u64) {
+                    return Err(InstructionException::StoreAccessFault(addr));
+                }
+                let paddr = self.translate(addr, size, AccessType::Store)?;
+                if self.reservation.take() == Some(paddr) {
+                    self.store(addr, size, self.read_reg(inst.rs2))?;
+                    0
+                } else {
+                    1
+                }
+            }
+            funct5 => self.execute_amo_op(funct5, inst.funct3, addr, self.read_reg(inst.rs2))?,
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    /// Replaces the word (funct3 0x2) or doubleword (0x3) at `addr` with the
+    /// result of the AMO selected by `funct5`, returning the old value.
+    ///
+    /// Words are sign-extended first, which leaves both the signed and the
+    /// unsigned order of the operands as it was for their low 32 bits, so
+    /// both widths are computed the same way.
+    fn execute_amo_op(
+        &mut self,
+        funct5: u32,
+        funct3: u32,
+        addr: u64,
+        rs2: u64,
+    ) -> Result<u64, InstructionException> {
+        let size = if funct3 == 0x2 { 4 } else { 8 };
+        if !addr.is_multiple_of(size as u64) {
+            return Err(InstructionException::StoreAccessFault(addr));
+        }
+        // AMOs fault as stores, even while reading.
+        self.translate(addr, size, AccessType::Store)?;
+        let old = self
+            .load(addr, size)
+            .map_err(|_| InstructionException::StoreAccessFault(addr))?;
+        let (old, rs2) = match size {
+            4 => (old as i32 as i64 as u64, rs2 as i32 as i64 as u64),
+            _ => (old, rs2),
+        };
+
+        let value = match funct5 {
+            // AMOADD
+            0b00000 => old.wrapping_add(rs2),
+            // AMOSWAP
+            0b00001 => rs2,
+            // AMOXOR
+            0b00100 => old ^ rs2,
+            // AMOOR
+            0b01000 => old | rs2,
+            // AMOAND
+            0b01100 => old & rs2,
+            // AMOMIN
+            0b10000 => (old as i64).min(rs2 as i64) as u64,
+            // AMOMAX
+            0b10100 => (old as i64).max(rs2 as i64) as u64,
+            // AMOMINU
+            0b11000 => old.min(rs2),
+            // AMOMAXU
+            0b11100 => old.max(rs2),
+            _ => unreachable!("funct7 is validated during decode"),
+        };
+        self.store(addr, size, value)?;
+
+        Ok(old)
+    }
+
+    fn execute_op_fp(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let rm = self.rounding_mode(inst.funct3);
+        let rm = || rm.expect("rounding mode is validated before dispatch");
+        let rs1 = float::unbox_f32(self.read_freg(inst.rs1));
+        let rs2 = float::unbox_f32(self.read_freg(inst.rs2));
+        // Doubles fill the whole register.
+        let (rs1_double, rs2_double) = (self.read_freg(inst.rs1), self.read_freg(inst.rs2));
+        // The conversions use the rs2 field to select the integer type.
+        let int_type = || IntType::from_bits(inst.rs2).expect("rs2 is validated during decode");
+
+        let (result, flags) = match inst.funct7 {
+            // FADD.S
+            0x00 => single(float::add_f32(rs1, rs2, rm())),
+            // FSUB.S
+            0x04 => single(float::sub_f32(rs1, rs2, rm())),
+            // FMUL.S
+            0x08 => single(float::mul_f32(rs1, rs2, rm())),
+            // FDIV.S
+            0x0c => single(float::div_f32(rs1, rs2, rm())),
+            // FSQRT.S
+            0x2c => single(float::sqrt_f32(rs1, rm())),
+            // FADD.D
+            0x01 => double(float::add_f64(rs1_double, rs2_double, rm())),
+            // FSUB.D
+            0x05 => double(float::sub_f64(rs1_double, rs2_double, rm())),
+            // FMUL.D
+            0x09 => double(float::mul_f64(rs1_double, rs2_double, rm())),
+            // FDIV.D
+            0x0d => double(float::div_f64(rs1_double, rs2_double, rm())),
+            // FSQRT.D
+            0x2d => double(float::sqrt_f64(rs1_double, rm())),
+            // FSGNJ.S / FSGNJN.S / FSGNJX.S
+            0x10 => (
+                FpResult::Single(float::sign_inject_f32(rs1, rs2, inst.funct3)),
+                0,
+            ),
+            // FSGNJ.D / FSGNJN.D / FSGNJX.D
+            0x11 => (
+                FpResult::Double(float::sign_inject_f64(rs1_double, rs2_double, inst.funct3)),
+                0,
+            ),
+            // FMIN.S / FMAX.S
+            0x14 => single(float::min_max_f32(rs1, rs2, inst.funct3)),
+            // FMIN.D / FMAX.D
+            0x15 => double(float::min_max_f64(rs1_double, rs2_double, inst.funct3)),
+            // FCVT.W.S / FCVT.WU.S / FCVT.L.S / FCVT.LU.S
+            0x60 => {
+                let (value, flags) = float::f32_to_int(rs1, int_type(), rm());
+                (FpResult::Integer(value), flags)
+            }
+            // FCVT.S.W / FCVT.S.WU / FCVT.S.L / FCVT.S.LU
+            0x68 => single(float::int_to_f32(self.read_reg(inst.rs1), int_type(), rm())),
+            // FCVT.W.D / FCVT.WU.D / FCVT.L.D / FCVT.LU.D
+            0x61 => {
+                let (value, flags) = float::f64_to_int(rs1_double, int_type(), rm());
+                (FpResult::Integer(value), flags)
+            }
+            // FCVT.D.W / FCVT.D.WU / FCVT.D.L / FCVT.D.LU
+            0x69 => double(float::int_to_f64(self.read_reg(inst.rs1), int_type(), rm())),
+            // FLE.S / FLT.S / FEQ.S
+            0x50 => {
+                let (value, flags) = float::compare_f32(rs1, rs2, inst.funct3);
+                (FpResult::Integer(value as u64), flags)
+            }
+            // FLE.D / FLT.D / FEQ.D
+            0x51 => {
+                let (value, flags) = float::compare_f64(rs1_double, rs2_double, inst.funct3);
+                (FpResult::Integer(value as u64), flags)
+            }
+            // FMV.X.W, which sign-extends the raw low word.
+            0x70 if inst.funct3 == 0b000 => (
+                FpResult::Integer(self.read_freg(inst.rs1) as i32 as i64 as u64),
+                0,
+            ),
+            // FCLASS.S
+            0x70 => (FpResult::Integer(float::classify_f32(rs1)), 0),
+            // FMV.X.D
+            0x71 if inst.funct3 == 0b000 => (FpResult::Integer(rs1_double), 0),
+            // FCLASS.D
+            0x71 => (FpResult::Integer(float::classify_f64(rs1_double)), 0),
+            // FMV.W.X
+            0x78 => (FpResult::Single(self.read_reg(inst.rs1) as u32), 0),
+            // FMV.D.X
+            0x79 => (FpResult::Double(self.read_reg(inst.rs1)), 0),
+            // FCVT.S.D
+            0x20 => single(float::f64_to_f32(rs1_double, rm())),
+            // FCVT.D.S
+            0x21 => double(float::f32_to_f64(rs1)),
+            _ => unreachable!("funct7 is validated during decode"),
+        };
+
+        match result {
+            FpResult::Single(value) => self.write_freg(inst.rd, float::box_f32(value)),
+            FpResult::Double(value) => self.write_freg(inst.rd, value),
+            FpResult::Integer(value) => self.write_reg(inst.rd, value),
+        }
+        self.csr.write(FFLAGS, self.csr.read(FFLAGS) | flags);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_op_32(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1) as u32;
+        let rs2 = self.read_reg(inst.rs2) as u32;
+        // The word shifts only look at the low 5 bits of rs2.
+        let shamt = rs2 & 0x1f;
+
+        let value = match inst.funct3 {
+            // ADDW / SUBW
+            0x0 if inst.funct7 == 0x20 => rs1.wrapping_sub(rs2),
+            0x0 => rs1.wrapping_add(rs2),
+            // SLLW
+            0x1 => rs1 << shamt,
+            // SRAW / SRLW
+            0x5 if inst.funct7 == 0x20 => ((rs1 as i32) >> shamt) as u32,
+            0x5 => rs1 >> shamt,
+            _ => unreachable!("funct3 is validated during decode"),
+        };
+
+        // Word results are sign-extended back to 64 bits.
+        self.write_reg(inst.rd, value as i32 as i64 as u64);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_op_imm(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+        let imm = inst.imm as i64 as u64;
+
+        let value = match inst.funct3 {
+            // ADDI
+            0x0 => rs1.wrapping_add(imm),
+            // SLTI
+            0x2 => ((rs1 as i64) < (imm as i64)) as u64,
+            // SLTIU
+            0x3 => (rs1 < imm) as u64,
+            // XORI
+            0x4 => rs1 ^ imm,
+            // ORI
+            0x6 => rs1 | imm,
+            // ANDI
+            0x7 => rs1 & imm,
+            _ => unreachable!("shifts are decoded as ISType"),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_op_imm_32(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        // ADDIW
+        let value = (self.read_reg(inst.rs1) as u32).wrapping_add(inst.imm as u32);
+        self.write_reg(inst.rd, value as i32 as i64 as u64);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_load(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        let addr = self.read_reg(inst.rs1).wrapping_add(inst.imm as i64 as u64);
+
+        let value = match inst.funct3 {
+            // LB
+            0x0 => self.load(addr, 1)? as i8 as i64 as u64,
+            // LH
+            0x1 => self.load(addr, 2)? as i16 as i64 as u64,
+            // LW
+            0x2 => self.load(addr, 4)? as i32 as i64 as u64,
+            // LD
+            0x3 => self.load(addr, 8)?,
+            // LBU
+            0x4 => self.load(addr, 1)?,
+            // LHU
+            0x5 => self.load(addr, 2)?,
+            // LWU
+            0x6 => self.load(addr, 4)?,
+            _ => unreachable!("funct3 is validated during decode"),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn execute_load_fp(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        let addr = self.read_reg(inst.rs1).wrapping_add(inst.imm as i64 as u64);
+        let value = match inst.funct3 {
+            // FLW
+            0b010 => float::box_f32(self.load(addr, 4)? as u32),
+            // FLD
+            _ => self.load(addr, 8)?,
+        };
+        self.write_freg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    /// Whether `target` is not a valid instruction address: without the C
+    /// extension every instruction is 4-byte aligned, with it 2-byte.
+    fn is_misaligned(&self, target: u64) -> bool {
+        let alignment = if self.extensions.c { 2 } else { 4 };
+        !target.is_multiple_of(alignment)
+    }
+
+    fn execute_jalr(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        // The target is computed before the link is written, as rd may equal
+        // rs1.
+        let target = self.read_reg(inst.rs1).wrapping_add(inst.imm as i64 as u64) & !1;
+        if self.is_misaligned(target) {
+            return Err(InstructionException::InstructionAddressMisaligned(target));
+        }
+
+        self.write_reg(inst.rd, self.pc.wrapping_add(self.instruction_size));
+        self.pc = target;
+
+        Ok(CounterState::Updated)
+    }
+}
+
+/// Executes an instruction word already known to have a given encoding.
+pub(crate) type Handler =
+    fn(&mut Hart, InstructionBytes) -> Result<CounterState, InstructionException>;
+
+/// Packs an instruction's opcode, funct3 and funct7 fields into an index
+/// into the dispatch table.
+fn dispatch_key(instruction: InstructionBytes) -> usize {
+    (instruction.funct7() << 10 | instruction.funct3() << 7 | instruction.opcode()) as usize
+}
+
+/// Maps every (opcode, funct3, funct7) key to the handler for the
+/// instructions it encodes, built once from the decoder.
+///
+/// For all but MISC-MEM and SYSTEM, those fields alone decide the format and
+/// whether the word is legal, so words are never decoded again on the hot
+/// path. Fences, system instructions and LR depend on the remaining bits and
+/// are still fully decoded, as are atomics, which need checking against the
+/// enabled extensions like floating-point instructions.
+fn dispatch_table() -> &'static [Handler] {
+    static TABLE: OnceLock<Vec<Handler>> = OnceLock::new();
+    TABLE.get_or_init(|| (0..1 << 17).map(handler_for).collect())
+}
+
+/// Picks the handler for a dispatch key by decoding a word with just those
+/// fields set.
+fn handler_for(key: u32) -> Handler {
+    let instruction = InstructionBytes((key >> 10) << 25 | ((key >> 7) & 0x7) << 12 | (key & 0x7f));
+    if instruction.is_compressed() {
+        return execute_slow;
+    }
+    if matches!(
+        instruction.opcode(),
+        opcode::MISC_MEM
+            | opcode::SYSTEM
+            | opcode::AMO
+            | opcode::LOAD_FP
+            | opcode::STORE_FP
+            | opcode::OP_FP
+    ) {
+        return execute_slow;
+    }
+
+    match Instruction::try_from(instruction) {
+        Ok(Instruction::R(_)) => execute_r,
+        Ok(Instruction::I(_)) => |hart, instruction| hart.process_i(instruction.into()),
+        Ok(Instruction::IS(_)) => |hart, instruction| hart.process_is(instruction.into()),
+        Ok(Instruction::S(_)) => |hart, instruction| hart.process_s(instruction.into()),
+        Ok(Instruction::B(_)) => |hart, instruction| hart.process_b(instruction.into()),
+        Ok(Instruction::U(_)) => |hart, instruction| hart.process_u(instruction.into()),
+        Ok(Instruction::J(_)) => |hart, instruction| hart.process_j(instruction.into()),
+        Ok(_) => execute_slow,
+        Err(_) => |_, instruction| Err(InstructionException::IllegalInstruction(instruction.0)),
+    }
+}
+
+/// The result of a floating-point instruction, and the register file it is
+/// written to.
+enum FpResult {
+    Single(u32),
+    Double(u64),
+    Integer(u64),
+}
+
+/// Tags the result of a single-precision operation.
+fn single((value, flags): (u32, u64)) -> (FpResult, u64) {
+    (FpResult::Single(value), flags)
+}
+
+/// Tags the result of a double-precision operation.
+fn double((value, flags): (u64, u64)) -> (FpResult, u64) {
+    (FpResult::Double(value), flags)
+}
+
+/// Register-register instructions still need checking against the enabled
+/// extensions, as M is optional.
+fn execute_r(
+    hart: &mut Hart,
+    instruction: InstructionBytes,
+) -> Result<CounterState, InstructionException> {
+    let inst = RFormat::from(instruction);
+    if !hart.extensions.supports(&Instruction::R(inst)) {
+        return Err(InstructionException::IllegalInstruction(instruction.0));
+    }
+
+    hart.process_r(inst)
+}
+
+fn execute_slow(
+    hart: &mut Hart,
+    instruction: InstructionBytes,
+) -> Result<CounterState, InstructionException> {
+    let decoded = Instruction::try_from(instruction)?;
+    hart.execute_decoded(instruction, decoded)
+}
+
+/// Performs the division selected by `funct3` (DIV, DIVU, REM or REMU) on
+/// the low `width` bits of the operands, sign-extending the `width`-bit
+/// result to 64 bits.
+///
+/// Division never traps: dividing by zero yields all ones for the quotient
+/// and the dividend for the remainder, and the signed overflow `MIN / -1`
+/// yields `MIN` with a remainder of zero.
+fn divide(funct3: u32, dividend: u64, divisor: u64, width: u32) -> u64 {
+    let shift = 64 - width;
+    let signed = |value: u64| ((value << shift) as i64) >> shift;
+    let unsigned = |value: u64| (value << shift) >> shift;
+
+    let result = match funct3 {
+        // DIV
+        0x4 if unsigned(divisor) == 0 => u64::MAX,
+        0x4 => signed(dividend).wrapping_div(signed(divisor)) as u64,
+        // DIVU
+        0x5 if unsigned(divisor) == 0 => u64::MAX,
+        0x5 => unsigned(dividend) / unsigned(divisor),
+        // REM
+        0x6 if unsigned(divisor) == 0 => dividend,
+        0x6 => signed(dividend).wrapping_rem(signed(divisor)) as u64,
+        // REMU
+        0x7 if unsigned(divisor) == 0 => dividend,
+        0x7 => unsigned(dividend) % unsigned(divisor),
+        _ => unreachable!("only funct3 0x4-0x7 are divisions"),
+    };
+
+    signed(result) as u64
+}
+
+impl InstructionProcessor for Hart {
+    fn process_r(&mut self, inst: RFormat) -> Result<CounterState, InstructionException> {
+        match (inst.opcode, inst.funct7) {
+            (opcode::OP_FP, _) => self.execute_op_fp(inst),
+            (opcode::AMO, _) => self.execute_amo(inst),
+            (opcode::OP, 0x01) => self.execute_op_m(inst),
+            (opcode::OP_32, 0x01) => self.execute_op_32_m(inst),
+            (opcode::OP_32, _) => self.execute_op_32(inst),
+            _ => self.execute_op(inst),
+        }
+    }
+
+    fn process_r4(&mut self, inst: R4Format) -> Result<CounterState, InstructionException> {
+        let rm = self
+            .rounding_mode(inst.funct3)
+            .expect("rounding mode is validated before dispatch");
+        // Each variant negates the product, the addend or both, which is the
+        // same as negating rs1 and rs3.
+        let (negate_product, negate_addend) = match inst.opcode {
+            // FMADD
+            opcode::MADD => (false, false),
+            // FMSUB
+            opcode::MSUB => (false, true),
+            // FNMSUB
+            opcode::NMSUB => (true, false),
+            // FNMADD
+            _ => (true, true),
+        };
+        let (rs1, rs2, rs3) = (
+            self.read_freg(inst.rs1),
+            self.read_freg(inst.rs2),
+            self.read_freg(inst.rs3),
+        );
+
+        let (value, flags) = match inst.fmt {
+            0b00 => {
+                let sign = |negate: bool| (negate as u32) << 31;
+                let (rs1, rs2, rs3) = (
+                    float::unbox_f32(rs1) ^ sign(negate_product),
+                    float::unbox_f32(rs2),
+                    float::unbox_f32(rs3) ^ sign(negate_addend),
+                );
+                let (value, flags) = float::fma_f32(rs1, rs2, rs3, rm);
+                (float::box_f32(value), flags)
+            }
+            _ => {
+                let sign = |negate: bool| (negate as u64) << 63;
+                float::fma_f64(
+                    rs1 ^ sign(negate_product),
+                    rs2,
+                    rs3 ^ sign(negate_addend),
+                    rm,
+                )
+            }
+        };
+        self.write_freg(inst.rd, value);
+        self.csr.write(FFLAGS, self.csr.read(FFLAGS) | flags);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn process_i(&mut self, inst: IFormat) -> Result<CounterState, InstructionException> {
+        match inst.opcode {
+            opcode::LOAD => self.execute_load(inst),
+            opcode::LOAD_FP => self.execute_load_fp(inst),
+            opcode::JALR => self.execute_jalr(inst),
+            opcode::OP_IMM_32 => self.execute_op_imm_32(inst),
+            _ => self.execute_op_imm(inst),
+        }
+    }
+
+    fn process_is(&mut self, inst: ISType) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+
+        let value = match (inst.opcode, inst.funct3, inst.funct6) {
+            // SLLI
+            (opcode::OP_IMM, 0x1, 0x00) => rs1 << inst.shamt,
+            // SRLI
+            (opcode::OP_IMM, 0x5, 0x00) => rs1 >> inst.shamt,
+            // SRAI
+            (opcode::OP_IMM, 0x5, 0x10) => ((rs1 as i64) >> inst.shamt) as u64,
+            // SLLIW
+            (opcode::OP_IMM_32, 0x1, 0x00) => ((rs1 as u32) << inst.shamt) as i32 as i64 as u64,
+            // SRLIW
+            (opcode::OP_IMM_32, 0x5, 0x00) => ((rs1 as u32) >> inst.shamt) as i32 as i64 as u64,
+            // SRAIW
+            (opcode::OP_IMM_32, 0x5, 0x10) => ((rs1 as i32) >> inst.shamt) as i64 as u64,
+            _ => unreachable!("funct6 is validated during decode"),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn process_s(&mut self, inst: SFormat) -> Result<CounterState, InstructionException> {
+        let addr = self.read_reg(inst.rs1).wrapping_add(inst.imm as i64 as u64);
+        if inst.opcode == opcode::STORE_FP {
+            // FSW stores the low word whether or not it is NaN-boxed, and FSD
+            // the whole register.
+            self.store(addr, 1 << inst.funct3, self.read_freg(inst.rs2))?;
+            return Ok(CounterState::NotUpdated);
+        }
+        let value = self.read_reg(inst.rs2);
+
+        // SB / SH / SW / SD
+        self.store(addr, 1 << inst.funct3, value)?;
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn process_b(&mut self, inst: BFormat) -> Result<CounterState, InstructionException> {
+        let rs1 = self.read_reg(inst.rs1);
+        let rs2 = self.read_reg(inst.rs2);
+
+        let taken = match inst.funct3 {
+            // BEQ
+            0x0 => rs1 == rs2,
+            // BNE
+            0x1 => rs1 != rs2,
+            // BLT
+            0x4 => (rs1 as i64) < (rs2 as i64),
+            // BGE
+            0x5 => (rs1 as i64) >= (rs2 as i64),
+            // BLTU
+            0x6 => rs1 < rs2,
+            // BGEU
+            0x7 => rs1 >= rs2,
+            _ => unreachable!("funct3 is validated during decode"),
+        };
+
+        if !taken {
+            return Ok(CounterState::NotUpdated);
+        }
+
+        let target = self.pc.wrapping_add(inst.imm as i64 as u64);
+        if self.is_misaligned(target) {
+            return Err(InstructionException::InstructionAddressMisaligned(target));
+        }
+        self.pc = target;
+
+        Ok(CounterState::Updated)
+    }
+
+    fn process_u(&mut self, inst: UFormat) -> Result<CounterState, InstructionException> {
+        let imm = inst.imm as i64 as u64;
+
+        let value = match inst.opcode {
+            opcode::LUI => imm,
+            opcode::AUIPC => self.pc.wrapping_add(imm),
+            _ => unreachable!("only LUI and AUIPC decode as UFormat"),
+        };
+
+        self.write_reg(inst.rd, value);
+
+        Ok(CounterState::NotUpdated)
+    }
+
+    fn process_j(&mut self, inst: JFormat) -> Result<CounterState, InstructionException> {
+        let target = self.pc.wrapping_add(inst.imm as i64 as u64);
+        if self.is_misaligned(target) {
+            return Err(InstructionException::InstructionAddressMisaligned(target));
+        }
+
+        self.write_reg(inst.rd, self.pc.wrapping_add(self.instruction_size));
+        self.pc = target;
+
+        Ok(CounterState::Updated)
+    }
+
+    fn process_csr(&mut self, inst: CsrFormat) -> Result<CounterState, InstructionException> {
+        let old = match inst.csr {
+            MISA => self.extensions.misa(),
+            _ => self.csr.read(inst.csr),
+        };
+        // The immediate forms use the rs1 field as a zero-extended value.
+        let source = if inst.funct3 & 0b100 != 0 {
+            inst.rs1 as u64
+        } else {
+            self.read_reg(inst.rs1)
+        };
+
+        if inst.writes_csr() {
+            let value = match inst.funct3 & 0b011 {
+                // CSRRW / CSRRWI
+                0b01 => source,
+                // CSRRS / CSRRSI
+                0b10 => old | source,
+                // CSRRC / CSRRCI
+                0b11 => old & !source,
+                _ => unreachable!("funct3 is validated during decode"),
+            };
+            // The extensions of a hart are fixed, so writes to misa are
+            // ignored.
+            if inst.csr != MISA {
+                self.csr.write(inst.csr, value);
+            }
+        }
+        self.write_reg(inst.rd, old);
+
+        Ok(CounterState::NotUpdated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asm;
+    use crate::clint::CLINT_BASE;
+    use crate::csr::*;
+    use crate::dram::parse_size;
+
+    /// A hart with a small DRAM holding `program` at its start.
+    fn hart(program: &[u32]) -> Hart {
+        let code = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+        Hart::with_memory(code, 0x10000)
+    }
+
+    fn execute(hart: &mut Hart, word: u32) {
+        hart.execute(InstructionBytes(word)).unwrap();
+    }
+
+    #[test]
+    fn add_and_sub() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 5);
+        hart.write_reg(3, 7);
+
+        execute(&mut hart, asm::add(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 12);
+        execute(&mut hart, asm::sub(1, 2, 3));
+        assert_eq!(hart.read_reg(1), -2i64 as u64);
+    }
+
+    #[test]
+    fn add_to_x0_is_discarded() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 5);
+        hart.write_reg(3, 7);
+
+        execute(&mut hart, asm::add(0, 2, 3));
+        assert_eq!(hart.read_reg(0), 0);
+        execute(&mut hart, asm::sub(0, 2, 3));
+        assert_eq!(hart.read_reg(0), 0);
+    }
+
+    #[test]
+    fn shifts_use_the_low_six_bits() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x8000_0000_0000_00f0);
+
+        hart.write_reg(3, 0);
+        execute(&mut hart, asm::sra(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0x8000_0000_0000_00f0);
+        hart.write_reg(3, 63);
+        execute(&mut hart, asm::sll(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+        execute(&mut hart, asm::srl(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 1);
+        hart.write_reg(3, 64 + 4);
+        execute(&mut hart, asm::srl(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0x0800_0000_0000_000f);
+    }
+
+    #[test]
+    fn sra_sign_extends_negative_values() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, -256i64 as u64);
+
+        hart.write_reg(3, 4);
+        execute(&mut hart, asm::sra(1, 2, 3));
+        assert_eq!(hart.read_reg(1), -16i64 as u64);
+        hart.write_reg(3, 63);
+        execute(&mut hart, asm::sra(1, 2, 3));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+    }
+
+    #[test]
+    fn slt_and_sltu() {
+        let mut hart = hart(&[]);
+
+        hart.write_reg(2, 7);
+        hart.write_reg(3, 7);
+        execute(&mut hart, asm::slt(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+        execute(&mut hart, asm::sltu(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+
+        // -1 is less than 1 signed, but the largest value unsigned.
+        hart.write_reg(2, -1i64 as u64);
+        hart.write_reg(3, 1);
+        execute(&mut hart, asm::slt(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 1);
+        execute(&mut hart, asm::sltu(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn sltu_from_x0_tests_for_nonzero() {
+        let mut hart = hart(&[]);
+
+        hart.write_reg(3, 5);
+        execute(&mut hart, asm::sltu(1, 0, 3));
+        assert_eq!(hart.read_reg(1), 1);
+        hart.write_reg(3, 0);
+        execute(&mut hart, asm::sltu(1, 0, 3));
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn and_with_all_ones_is_identity() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x1234_5678_9abc_def0);
+        hart.write_reg(3, u64::MAX);
+
+        execute(&mut hart, asm::and(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0x1234_5678_9abc_def0);
+        execute(&mut hart, asm::or(1, 2, 3));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+    }
+
+    #[test]
+    fn xor_with_itself_is_zero() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x1234_5678_9abc_def0);
+
+        execute(&mut hart, asm::xor(1, 2, 2));
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn addi_with_negative_immediate() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 10);
+
+        execute(&mut hart, asm::addi(1, 2, -4));
+        assert_eq!(hart.read_reg(1), 6);
+        execute(&mut hart, asm::addi(1, 0, -2048));
+        assert_eq!(hart.read_reg(1), -2048i64 as u64);
+    }
+
+    #[test]
+    fn sltiu_compares_the_sign_extended_immediate_unsigned() {
+        let mut hart = hart(&[]);
+
+        execute(&mut hart, asm::sltiu(1, 0, 1));
+        assert_eq!(hart.read_reg(1), 1);
+        // -1 sign-extends to the largest unsigned value.
+        hart.write_reg(2, u64::MAX - 1);
+        execute(&mut hart, asm::sltiu(1, 2, -1));
+        assert_eq!(hart.read_reg(1), 1);
+        execute(&mut hart, asm::slti(1, 2, -1));
+        assert_eq!(hart.read_reg(1), 1);
+    }
+
+    #[test]
+    fn logical_immediates() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0xf0);
+
+        execute(&mut hart, asm::xori(1, 2, -1));
+        assert_eq!(hart.read_reg(1), !0xf0);
+        execute(&mut hart, asm::ori(1, 2, 0x0f));
+        assert_eq!(hart.read_reg(1), 0xff);
+        execute(&mut hart, asm::andi(1, 2, 0x30));
+        assert_eq!(hart.read_reg(1), 0x30);
+    }
+
+    #[test]
+    fn step_fetches_and_executes_one_instruction() {
+        let mut hart = hart(&[asm::addi(1, 0, 42), asm::addi(1, 1, 1)]);
+
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(1), 42);
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+    }
+
+    #[test]
+    fn step_advances_pc_past_other_instructions() {
+        let mut hart = hart(&[asm::addi(1, 0, 1), asm::addi(1, 0, 2)]);
+
+        hart.step().unwrap();
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 8);
+    }
+
+    #[test]
+    fn writes_to_x0_read_back_as_zero() {
+        let mut hart = hart(&[]);
+
+        hart.write_reg(0, 0xdead_beef);
+        assert_eq!(hart.read_reg(0), 0);
+        hart.write_reg(1, 0xdead_beef);
+        assert_eq!(hart.read_reg(1), 0xdead_beef);
+    }
+
+    #[test]
+    fn lui_sign_extends() {
+        let mut hart = hart(&[]);
+
+        execute(&mut hart, asm::lui(1, 0xfffff));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_ffff_f000);
+        execute(&mut hart, asm::lui(1, 0x12345));
+        assert_eq!(hart.read_reg(1), 0x1234_5000);
+    }
+
+    #[test]
+    fn auipc_is_relative_to_its_own_pc() {
+        let mut hart = hart(&[asm::addi(0, 0, 0), asm::auipc(1, 1), asm::auipc(2, 0xfffff)]);
+
+        hart.step().unwrap();
+        hart.step().unwrap();
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(1), DRAM_BASE + 4 + 0x1000);
+        assert_eq!(hart.read_reg(2), DRAM_BASE + 8 - 0x1000);
+    }
+
+    #[test]
+    fn step_does_not_advance_pc_past_a_jump() {
+        let mut hart = hart(&[asm::jal(0, 12)]);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 12);
+    }
+
+    #[test]
+    fn jal_forward_links_the_next_instruction() {
+        let mut hart = hart(&[asm::jal(1, 16)]);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 16);
+        assert_eq!(hart.read_reg(1), DRAM_BASE + 4);
+    }
+
+    #[test]
+    fn jal_backward() {
+        let nop = asm::addi(0, 0, 0);
+        let mut hart = hart(&[nop, nop, asm::jal(1, -8)]);
+
+        for _ in 0..3 {
+            hart.step().unwrap();
+        }
+        assert_eq!(hart.pc, DRAM_BASE);
+        assert_eq!(hart.read_reg(1), DRAM_BASE + 12);
+    }
+
+    #[test]
+    fn jal_to_a_misaligned_target_faults_without_c() {
+        let mut hart = hart(&[]);
+        hart.extensions.c = false;
+
+        assert_eq!(
+            hart.execute(InstructionBytes(asm::jal(1, 6))),
+            Err(InstructionException::InstructionAddressMisaligned(
+                DRAM_BASE + 6
+            ))
+        );
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn jalr_clears_the_low_bit_of_the_target() {
+        let mut hart = hart(&[asm::jalr(1, 2, 0)]);
+        hart.write_reg(2, DRAM_BASE + 0x101);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+        assert_eq!(hart.read_reg(1), DRAM_BASE + 4);
+    }
+
+    #[test]
+    fn jalr_to_x0_still_jumps() {
+        let mut hart = hart(&[asm::jalr(0, 2, -4)]);
+        hart.write_reg(2, DRAM_BASE + 0x24);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x20);
+        assert_eq!(hart.read_reg(0), 0);
+    }
+
+    #[test]
+    fn branches_taken_and_not_taken() {
+        let negative = -1i64 as u64;
+        // Each branch with operands that take it, then ones that do not.
+        let cases = [
+            (asm::beq(1, 2, 16), (5, 5), (5, 6)),
+            (asm::bne(1, 2, 16), (5, 6), (5, 5)),
+            (asm::blt(1, 2, 16), (negative, 1), (1, negative)),
+            (asm::bge(1, 2, 16), (1, negative), (negative, 1)),
+            (asm::bltu(1, 2, 16), (1, negative), (negative, 1)),
+            (asm::bgeu(1, 2, 16), (negative, 1), (1, negative)),
+        ];
+        for (branch, taken, not_taken) in cases {
+            for ((rs1, rs2), target) in [(taken, DRAM_BASE + 16), (not_taken, DRAM_BASE + 4)] {
+                let mut hart = hart(&[branch]);
+                hart.write_reg(1, rs1);
+                hart.write_reg(2, rs2);
+
+                hart.step().unwrap();
+                assert_eq!(hart.pc, target, "{:#010x} {} {}", branch, rs1, rs2);
+            }
+        }
+    }
+
+    #[test]
+    fn loads_sign_or_zero_extend_each_width() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, DRAM_BASE + 0x1000);
+        hart.write_reg(3, 0x8888_8888_8888_8888);
+        execute(&mut hart, asm::sd(3, 2, 0));
+
+        let cases = [
+            (asm::lb(1, 2, 0), 0xffff_ffff_ffff_ff88),
+            (asm::lbu(1, 2, 0), 0x88),
+            (asm::lh(1, 2, 0), 0xffff_ffff_ffff_8888),
+            (asm::lhu(1, 2, 0), 0x8888),
+            (asm::lw(1, 2, 0), 0xffff_ffff_8888_8888),
+            (asm::lwu(1, 2, 0), 0x8888_8888),
+            (asm::ld(1, 2, 0), 0x8888_8888_8888_8888),
+        ];
+        for (load, value) in cases {
+            execute(&mut hart, load);
+            assert_eq!(hart.read_reg(1), value, "{:#010x}", load);
+        }
+    }
+
+    #[test]
+    fn load_from_unmapped_memory_faults() {
+        let mut hart = hart(&[]);
+
+        assert_eq!(
+            hart.execute(InstructionBytes(asm::ld(1, 0, 8))),
+            Err(InstructionException::LoadAccessFault(8))
+        );
+    }
+
+    #[test]
+    fn stores_then_loads_each_width() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, DRAM_BASE + 0x1000);
+        hart.write_reg(3, 0x0123_4567_7654_3210);
+
+        let cases = [
+            (asm::sb(3, 2, 0), asm::lbu(1, 2, 0), 0x10),
+            (asm::sh(3, 2, 8), asm::lhu(1, 2, 8), 0x3210),
+            (asm::sw(3, 2, 16), asm::lw(1, 2, 16), 0x7654_3210),
+            (asm::sd(3, 2, 24), asm::ld(1, 2, 24), 0x0123_4567_7654_3210),
+        ];
+        for (store, load, value) in cases {
+            execute(&mut hart, store);
+            execute(&mut hart, load);
+            assert_eq!(hart.read_reg(1), value, "{:#010x}", load);
+        }
+    }
+
+    #[test]
+    fn store_at_a_negative_offset() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, DRAM_BASE + 0x1000);
+        hart.write_reg(3, 0x1122_3344_5566_7788);
+
+        execute(&mut hart, asm::sd(3, 2, -8));
+        execute(&mut hart, asm::sw(3, 2, -2048));
+        assert_eq!(hart.load(DRAM_BASE + 0xff8, 8), Ok(0x1122_3344_5566_7788));
+        assert_eq!(hart.load(DRAM_BASE + 0x800, 8), Ok(0x5566_7788));
+    }
+
+    #[test]
+    fn addw_wraps_at_32_bits_and_sign_extends() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x7fff_ffff);
+        hart.write_reg(3, 1);
+
+        execute(&mut hart, asm::addw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_8000_0000);
+        execute(&mut hart, asm::subw(1, 0, 3));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+    }
+
+    #[test]
+    fn word_shifts_use_the_low_five_bits() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0xffff_ffff_8000_0000);
+        hart.write_reg(3, 32 + 4);
+
+        execute(&mut hart, asm::srlw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0x0800_0000);
+        execute(&mut hart, asm::sraw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_f800_0000);
+        hart.write_reg(2, 1);
+        hart.write_reg(3, 31);
+        execute(&mut hart, asm::sllw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_8000_0000);
+    }
+
+    #[test]
+    fn addiw_zero_sign_extends_the_low_word() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x1234_5678_8000_0001);
+
+        execute(&mut hart, asm::addiw(1, 2, 0));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_8000_0001);
+        execute(&mut hart, asm::addiw(1, 2, -2));
+        assert_eq!(hart.read_reg(1), 0x7fff_ffff);
+    }
+
+    #[test]
+    fn sraiw_of_a_negative_word() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x0000_0000_8000_0000);
+
+        execute(&mut hart, asm::sraiw(1, 2, 4));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_f800_0000);
+        execute(&mut hart, asm::srliw(1, 2, 4));
+        assert_eq!(hart.read_reg(1), 0x0800_0000);
+        execute(&mut hart, asm::slliw(1, 2, 1));
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn mulh_of_two_large_negatives_matches_a_reference() {
+        let mut hart = hart(&[]);
+        let (a, b) = (i64::MIN + 3, -0x1234_5678_9abc_def0_i64);
+        hart.write_reg(2, a as u64);
+        hart.write_reg(3, b as u64);
+
+        execute(&mut hart, asm::mulh(1, 2, 3));
+        let product = a as i128 * b as i128;
+        assert_eq!(hart.read_reg(1), (product >> 64) as u64);
+        execute(&mut hart, asm::mul(1, 2, 3));
+        assert_eq!(hart.read_reg(1), product as u64);
+    }
+
+    #[test]
+    fn mulhsu_and_mulhu_treat_rs2_as_unsigned() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, u64::MAX);
+        hart.write_reg(3, u64::MAX);
+
+        execute(&mut hart, asm::mulhu(1, 2, 3));
+        assert_eq!(hart.read_reg(1), u64::MAX - 1);
+        // -1 * (2^64 - 1) has all-ones upper bits.
+        execute(&mut hart, asm::mulhsu(1, 2, 3));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+    }
+
+    #[test]
+    fn multiplies_are_illegal_without_m() {
+        let mut hart = hart(&[]);
+        hart.extensions.m = false;
+
+        let word = asm::mul(1, 2, 3);
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 7);
+
+        execute(&mut hart, asm::div(1, 2, 0));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+        execute(&mut hart, asm::divu(1, 2, 0));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+        execute(&mut hart, asm::rem(1, 2, 0));
+        assert_eq!(hart.read_reg(1), 7);
+        execute(&mut hart, asm::remu(1, 2, 0));
+        assert_eq!(hart.read_reg(1), 7);
+    }
+
+    #[test]
+    fn signed_division_overflow() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, i64::MIN as u64);
+        hart.write_reg(3, -1_i64 as u64);
+
+        execute(&mut hart, asm::div(1, 2, 3));
+        assert_eq!(hart.read_reg(1), i64::MIN as u64);
+        execute(&mut hart, asm::rem(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+    }
+
+    #[test]
+    fn remuw_ignores_the_high_bits() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0xffff_ffff_0000_0017);
+        hart.write_reg(3, 0xabcd_0000_0000_0005);
+
+        execute(&mut hart, asm::remuw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 3);
+        execute(&mut hart, asm::divuw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 4);
+    }
+
+    #[test]
+    fn word_division_special_cases() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, i32::MIN as u64);
+        hart.write_reg(3, -1_i64 as u64);
+
+        execute(&mut hart, asm::divw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), i32::MIN as i64 as u64);
+        execute(&mut hart, asm::remw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), 0);
+        execute(&mut hart, asm::divw(1, 2, 0));
+        assert_eq!(hart.read_reg(1), u64::MAX);
+        execute(&mut hart, asm::remw(1, 2, 0));
+        assert_eq!(hart.read_reg(1), i32::MIN as i64 as u64);
+        execute(&mut hart, asm::mulw(1, 2, 3));
+        assert_eq!(hart.read_reg(1), i32::MIN as i64 as u64);
+    }
+
+    #[test]
+    fn csrrw_round_trips_a_scratch_csr() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x1234);
+
+        execute(&mut hart, asm::csrrw(0, MSCRATCH as u32, 2));
+        hart.write_reg(2, 0x5678);
+        execute(&mut hart, asm::csrrw(1, MSCRATCH as u32, 2));
+        assert_eq!(hart.read_reg(1), 0x1234);
+        assert_eq!(hart.csr.read(MSCRATCH), 0x5678);
+    }
+
+    #[test]
+    fn csrrs_with_x0_does_not_write() {
+        let mut hart = hart(&[]);
+        hart.csr.write(MSCRATCH, 0xff);
+
+        execute(&mut hart, asm::csrrs(1, MSCRATCH as u32, 0));
+        assert_eq!(hart.read_reg(1), 0xff);
+        assert_eq!(hart.csr.read(MSCRATCH), 0xff);
+        // Reading a read-only CSR only traps if it would be written.
+        execute(&mut hart, asm::csrrs(1, CYCLE as u32, 0));
+        let word = asm::csrrs(1, CYCLE as u32, 2);
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+    }
+
+    #[test]
+    fn unimplemented_csrs_trap() {
+        let mut hart = hart(&[]);
+
+        for csr in [0x7ff, 0x3a1, 0xfff] {
+            let word = asm::csrrs(1, csr, 0);
+            assert_eq!(
+                hart.execute(InstructionBytes(word)),
+                Err(InstructionException::IllegalInstruction(word))
+            );
+        }
+    }
+
+    #[test]
+    fn ecall_in_machine_mode_has_cause_11() {
+        let mut hart = hart(&[asm::ecall()]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+        let exception = hart.execute(InstructionBytes(asm::ecall())).unwrap_err();
+        assert_eq!(exception, InstructionException::EnvironmentCallFromMMode);
+        assert_eq!(exception.code(), 11);
+        hart.step().unwrap();
+        assert_eq!(hart.csr.read(MCAUSE), 11);
+    }
+
+    #[test]
+    fn ebreak_stops_the_run_loop() {
+        let mut hart = hart(&[asm::addi(1, 0, 1), asm::ebreak(), asm::addi(1, 0, 2)]);
+
+        let halt = hart.run();
+        assert_eq!(
+            halt,
+            Halt::Exception(InstructionException::Breakpoint(DRAM_BASE + 4))
+        );
+        assert_eq!(InstructionException::Breakpoint(0).code(), 3);
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+        assert_eq!(hart.read_reg(1), 1);
+    }
+
+    #[test]
+    fn illegal_instruction_traps_to_mtvec() {
+        let mut hart = hart(&[0]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+        assert_eq!(hart.csr.read(MEPC), DRAM_BASE);
+        assert_eq!(hart.csr.read(MCAUSE), 2);
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        assert_eq!(hart.csr.mstatus().mpp(), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn mret_restores_pc_mode_and_interrupt_enable() {
+        let mut hart = hart(&[]);
+        hart.csr.write(MEPC, DRAM_BASE + 0x40);
+        let mut mstatus = hart.csr.mstatus();
+        mstatus.set_mpp(PrivilegeMode::Supervisor);
+        mstatus.set_mpie(true);
+        mstatus.set_mie(false);
+        hart.csr.set_mstatus(mstatus);
+
+        execute(&mut hart, asm::mret());
+        assert_eq!(hart.pc, DRAM_BASE + 0x40);
+        assert_eq!(hart.privilege(), PrivilegeMode::Supervisor);
+        let mstatus = hart.csr.mstatus();
+        assert!(mstatus.mie());
+        assert!(mstatus.mpie());
+        assert_eq!(mstatus.mpp(), PrivilegeMode::User);
+    }
+
+    #[test]
+    fn machine_csrs_trap_in_user_mode() {
+        let mut hart = hart(&[]);
+        hart.mode = PrivilegeMode::User;
+
+        let word = asm::csrrs(1, MSCRATCH as u32, 0);
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+        hart.mode = PrivilegeMode::Supervisor;
+        execute(&mut hart, asm::csrrs(1, SSCRATCH as u32, 0));
+        assert_eq!(Csr::min_privilege(0x200), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn reset_state_is_relative_to_the_dram_base() {
+        let hart = hart(&[]);
+
+        assert_eq!(hart.pc, DRAM_BASE);
+        assert_eq!(hart.read_reg(2), DRAM_BASE + 0x10000);
+    }
+
+    #[test]
+    fn mtip_is_set_once_mtime_reaches_mtimecmp() {
+        let mut hart = hart(&[asm::jal(0, 0)]);
+        hart.bus.store(CLINT_BASE + 0x4000, 8, 10).unwrap();
+
+        while hart.bus.clint.mtime() < 10 {
+            assert_eq!(hart.csr.read(MIP) & MIP_MTIP, 0);
+            hart.step().unwrap();
+        }
+        assert_ne!(hart.csr.read(MIP) & MIP_MTIP, 0);
+        // Pushing mtimecmp back clears the interrupt again.
+        hart.bus.store(CLINT_BASE + 0x4000, 8, u64::MAX).unwrap();
+        hart.step().unwrap();
+        assert_eq!(hart.csr.read(MIP) & MIP_MTIP, 0);
+    }
+
+    #[test]
+    fn vectored_mtvec_sends_exceptions_to_the_base() {
+        let mut hart = hart(&[0]);
+        hart.csr.write(MTVEC, (DRAM_BASE + 0x100) | MTVEC_VECTORED);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+    }
+
+    /// A hart with a pending machine timer interrupt that is enabled in
+    /// `mie` and `mstatus`.
+    fn timer_interrupt_hart(mtvec: u64) -> Hart {
+        let mut hart = hart(&[asm::addi(1, 0, 1)]);
+        hart.bus.store(CLINT_BASE + 0x4000, 8, 0).unwrap();
+        hart.csr.write(MIE, MIP_MTIP);
+        hart.csr.write(MSTATUS, MSTATUS_MIE);
+        hart.csr.write(MTVEC, mtvec);
+        hart
+    }
+
+    #[test]
+    fn timer_interrupts_are_taken_between_steps() {
+        let mut hart = timer_interrupt_hart(DRAM_BASE + 0x100);
+
+        hart.step().unwrap();
+        assert_eq!(hart.csr.read(MCAUSE), 0x8000_0000_0000_0007);
+        assert_eq!(hart.csr.read(MEPC), DRAM_BASE);
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+        assert_eq!(hart.read_reg(1), 0);
+        assert!(!hart.csr.mstatus().mie());
+    }
+
+    #[test]
+    fn vectored_interrupts_jump_past_the_base() {
+        let mut hart = timer_interrupt_hart((DRAM_BASE + 0x100) | MTVEC_VECTORED);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x100 + 4 * 7);
+    }
+
+    #[test]
+    fn masked_interrupts_are_not_taken() {
+        let mut hart = timer_interrupt_hart(DRAM_BASE + 0x100);
+        hart.csr.write(MSTATUS, 0);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+        assert_eq!(hart.read_reg(1), 1);
+    }
+
+    #[test]
+    fn sfence_vma_traps_in_user_mode_and_under_tvm() {
+        let mut hart = hart(&[]);
+        let word = 0x1220_8073;
+        execute(&mut hart, word);
+
+        hart.mode = PrivilegeMode::Supervisor;
+        execute(&mut hart, word);
+        hart.csr.write(MSTATUS, MSTATUS_TVM);
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+        hart.mode = PrivilegeMode::User;
+        hart.csr.write(MSTATUS, 0);
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+    }
+
+    /// An output sink whose bytes can still be read after it has been handed
+    /// to the hart.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Capture {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn traces_each_instruction_and_its_writes() {
+        let mut hart = hart(&[asm::addi(10, 0, 42), asm::sd(10, 2, -8)]);
+        let output = Capture::default();
+        hart.trace_to(Box::new(output.clone()));
+
+        hart.step().unwrap();
+        hart.step().unwrap();
+        assert_eq!(
+            output.text().lines().collect::<Vec<_>>(),
+            [
+                "0x0000000080000000: 02a00513  addi a0, zero, 42",
+                "    a0   0x0000000000000000 -> 0x000000000000002a",
+                "0x0000000080000004: fea13c23  sd a0, -8(sp)",
+                "    mem  0x000000008000fff8 <- 0x000000000000002a",
+            ]
+        );
+    }
+
+    #[test]
+    fn run_executes_an_addi_program() {
+        let mut hart = hart(&[
+            asm::addi(29, 0, 5),
+            asm::addi(30, 0, 37),
+            asm::add(31, 30, 29),
+        ]);
+
+        // The zeroed memory after the program is an illegal instruction, and
+        // there is no trap handler to take it.
+        assert_eq!(
+            hart.run(),
+            Halt::Exception(InstructionException::IllegalInstruction(0))
+        );
+        assert_eq!(hart.read_reg(29), 5);
+        assert_eq!(hart.read_reg(30), 37);
+        assert_eq!(hart.read_reg(31), 42);
+        assert_eq!(hart.pc, DRAM_BASE + 12);
+    }
+
+    #[test]
+    fn fetching_past_the_end_of_memory_halts() {
+        // The trailing half of an instruction, whose low bits mark it as
+        // 32 bits long.
+        let mut code = asm::addi(1, 0, 1).to_le_bytes().to_vec();
+        code.extend([0x13, 0x00]);
+        let mut hart = Hart::with_memory(code, 6);
+
+        // The fault is reported at the missing second half.
+        assert_eq!(
+            hart.run(),
+            Halt::Exception(InstructionException::InstructionAccessFault(DRAM_BASE + 6))
+        );
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+        assert_eq!(hart.read_reg(1), 1);
+    }
+
+    #[test]
+    fn reset_restores_the_initial_state() {
+        let mut hart = hart(&[asm::addi(1, 0, 7), asm::csrrw(0, MSCRATCH as u32, 1)]);
+        hart.step().unwrap();
+        hart.step().unwrap();
+        hart.write_reg(2, 0);
+        hart.write_freg(3, 1);
+        hart.mode = PrivilegeMode::User;
+
+        hart.reset();
+        let initial = self::hart(&[]);
+        assert_eq!(hart.regs, initial.regs);
+        assert_eq!(hart.fregs, initial.fregs);
+        assert_eq!(hart.pc, DRAM_BASE);
+        assert_eq!(hart.csr.read(MSCRATCH), 0);
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        // Memory is left as it is, so the program can run again.
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(1), 7);
+    }
+
+    #[test]
+    fn memory_size_sets_the_stack_pointer() {
+        let size = parse_size("128K").unwrap();
+        let hart = Hart::with_memory(Vec::new(), size);
+
+        assert_eq!(hart.bus.dram.size(), 128 * 1024);
+        assert_eq!(hart.read_reg(2), DRAM_BASE + 128 * 1024);
+    }
+
+    #[test]
+    fn instret_counts_the_instructions_between_reads() {
+        let mut hart = hart(&[
+            asm::csrrs(5, INSTRET as u32, 0),
+            asm::addi(6, 0, 3),
+            asm::addi(6, 6, -1),
+            asm::bne(6, 0, -4),
+            asm::csrrs(7, INSTRET as u32, 0),
+            asm::ebreak(),
+        ]);
+
+        hart.run();
+        // The first read, the ADDI and three iterations of the loop.
+        assert_eq!(hart.read_reg(7) - hart.read_reg(5), 8);
+    }
+
+    #[test]
+    fn counters_are_gated_by_mcounteren_and_scounteren() {
+        let mut hart = hart(&[]);
+        let word = asm::csrrs(1, CYCLE as u32, 0);
+        let reads = |hart: &mut Hart| hart.execute(InstructionBytes(word)).is_ok();
+
+        for (mode, mcounteren, scounteren, allowed) in [
+            (PrivilegeMode::Machine, 0, 0, true),
+            (PrivilegeMode::Supervisor, 0, 1, false),
+            (PrivilegeMode::Supervisor, 1, 0, true),
+            (PrivilegeMode::User, 1, 0, false),
+            (PrivilegeMode::User, 0, 1, false),
+            (PrivilegeMode::User, 1, 1, true),
+        ] {
+            hart.mode = mode;
+            hart.csr.write(MCOUNTEREN, mcounteren);
+            hart.csr.write(SCOUNTEREN, scounteren);
+            assert_eq!(
+                reads(&mut hart),
+                allowed,
+                "{:?}",
+                (mode, mcounteren, scounteren)
+            );
+        }
+    }
+
+    #[test]
+    fn wfi_waits_for_an_interrupt() {
+        let mut hart = hart(&[asm::wfi(), asm::addi(1, 0, 1)]);
+        hart.bus.store(CLINT_BASE + 0x4000, 8, 20).unwrap();
+        hart.csr.write(MIE, MIP_MTIP);
+
+        hart.step().unwrap();
+        while hart.bus.clint.mtime() < 20 {
+            assert_eq!(hart.pc, DRAM_BASE + 4);
+            hart.step().unwrap();
+        }
+        // With mstatus.MIE clear, the interrupt is not taken, but still ends
+        // the wait, and the step that sees it pending resumes execution.
+        assert_eq!(hart.read_reg(1), 1);
+        assert_eq!(hart.pc, DRAM_BASE + 8);
+    }
+
+    #[test]
+    fn wfi_traps_below_machine_mode_under_tw() {
+        let mut hart = hart(&[]);
+        hart.mode = PrivilegeMode::Supervisor;
+        execute(&mut hart, asm::wfi());
+
+        hart.csr.write(MSTATUS, MSTATUS_TW);
+        assert_eq!(
+            hart.execute(InstructionBytes(asm::wfi())),
+            Err(InstructionException::IllegalInstruction(asm::wfi()))
+        );
+    }
+
+    #[test]
+    fn snapshot_restore_round_trip() {
+        let mut hart = hart(&[asm::addi(1, 0, 5), asm::sd(1, 2, -8)]);
+        hart.step().unwrap();
+        hart.step().unwrap();
+        hart.write_freg(4, 0x4010_0000_0000_0000);
+        hart.csr.write(MSCRATCH, 0xabc);
+        // A locked entry, whose pmpaddr0 no longer takes writes.
+        hart.csr.write(PMPADDR0, (DRAM_BASE + 0x1000) >> 2);
+        hart.csr.write(PMPCFG0, 0x8f);
+        hart.mode = PrivilegeMode::Supervisor;
+        let state = hart.snapshot();
+
+        let mut restored = self::hart(&[]);
+        restored.restore(&state);
+        assert_eq!(restored.snapshot(), state);
+        assert_eq!(restored.csr.read(PMPADDR0), (DRAM_BASE + 0x1000) >> 2);
+        assert_eq!(restored.pc, DRAM_BASE + 8);
+        assert_eq!(restored.bus.dram.load(DRAM_BASE + 0xfff8, 8), Ok(5));
+    }
+
+    #[test]
+    fn the_dispatch_table_matches_the_decoded_path() {
+        let program = [
+            asm::addi(5, 0, 50),
+            asm::lui(6, 0x12345),
+            asm::addi(7, 7, 3),
+            asm::mul(8, 7, 6),
+            asm::sraiw(9, 8, 3),
+            asm::sd(8, 2, -16),
+            asm::lw(10, 2, -16),
+            asm::csrrs(11, INSTRET as u32, 0),
+            asm::addi(5, 5, -1),
+            asm::bne(5, 0, -28),
+            // c.addi a1, 1, to check compressed words too.
+            0x0585_0585,
+            asm::ebreak(),
+        ];
+        let run = |profile: bool| {
+            let mut hart = hart(&program);
+            // Profiling executes the decoded instruction.
+            hart.set_profile(profile);
+            let halt = hart.run();
+            (halt, hart.regs, hart.pc, hart.csr.read(INSTRET))
+        };
+
+        assert_eq!(run(false), run(true));
+        // The last read of instret, plus the two C.ADDIs.
+        assert_eq!(run(false).1[11], 2 + 49 * 8 + 5 + 2);
+    }
+
+    #[test]
+    fn loops_hit_the_decode_cache() {
+        let mut hart = hart(&[
+            asm::addi(5, 0, 10),
+            asm::addi(5, 5, -1),
+            asm::bne(5, 0, -4),
+            asm::ebreak(),
+        ]);
+
+        hart.run();
+        // Every instruction misses once, and the loop then hits on its other
+        // nine iterations.
+        assert_eq!(hart.decode_cache.misses, 4);
+        assert_eq!(hart.decode_cache.hits, 2 * 9);
+    }
+
+    #[test]
+    fn stores_invalidate_cached_instructions() {
+        let mut hart = hart(&[asm::addi(1, 1, 1), asm::sw(5, 6, 0), asm::jal(0, -8)]);
+        hart.write_reg(5, asm::addi(1, 1, 16) as u64);
+        hart.write_reg(6, DRAM_BASE);
+
+        for _ in 0..4 {
+            hart.step().unwrap();
+        }
+        assert_eq!(hart.read_reg(1), 17);
+        assert_eq!(hart.decode_cache.misses, 4);
+    }
+
+    #[test]
+    fn htif_exit_command_halts_with_its_code() {
+        let mut hart = hart(&[asm::sd(6, 5, 0), asm::jal(0, 0)]);
+        let tohost = DRAM_BASE + 0x1000;
+        hart.bus.htif = Some(Htif::new(tohost, tohost + 8, Box::new(io::sink())));
+        hart.write_reg(5, tohost);
+        hart.write_reg(6, (21 << 1) | 1);
+
+        assert_eq!(hart.run(), Halt::Exit(21));
+        assert_eq!(hart.exit_code(), Some(21));
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+    }
+
+    #[test]
+    fn fsw_then_flw_round_trips_a_single() {
+        let mut hart = hart(&[]);
+        let addr = DRAM_BASE + 0x100;
+        hart.write_reg(5, addr);
+        hart.write_freg(1, float::box_f32(0x4049_0fdb));
+
+        execute(&mut hart, asm::s_type(0, 1, 5, 0b010, opcode::STORE_FP));
+        assert_eq!(hart.bus.dram.load(addr, 4), Ok(0x4049_0fdb));
+        // Only the word is stored, not the boxing above it.
+        assert_eq!(hart.bus.dram.load(addr + 4, 4), Ok(0));
+
+        execute(&mut hart, asm::i_type(0, 5, 0b010, 2, opcode::LOAD_FP));
+        assert_eq!(hart.read_freg(2), hart.read_freg(1));
+    }
+
+    #[test]
+    fn flw_nan_boxes_the_loaded_single() {
+        let mut hart = hart(&[]);
+        let addr = DRAM_BASE + 0x100;
+        hart.write_reg(5, addr);
+        // A signaling NaN pattern, which must be loaded unchanged.
+        hart.bus.dram.store(addr, 4, 0x7f80_0001).unwrap();
+        hart.write_freg(3, 0x1234_5678_9abc_def0);
+
+        execute(&mut hart, asm::i_type(0, 5, 0b010, 3, opcode::LOAD_FP));
+        assert_eq!(hart.read_freg(3), 0xffff_ffff_7f80_0001);
+    }
+
+    /// An OP-FP instruction word.
+    fn fp_op(funct7: u32, rs2: u32, rs1: u32, rm: u32, rd: u32) -> u32 {
+        asm::r_type(funct7, rs2, rs1, rm, rd, opcode::OP_FP)
+    }
+
+    #[test]
+    fn fdiv_by_zero_raises_dz() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(1.0f32.to_bits()));
+        hart.write_freg(2, float::box_f32(0.0f32.to_bits()));
+
+        // FDIV.S f3, f1, f2 with the dynamic rounding mode.
+        execute(&mut hart, fp_op(0x0c, 2, 1, 0b111, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(f32::INFINITY.to_bits()));
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_DZ);
+    }
+
+    #[test]
+    fn inexact_fadd_raises_nx_and_honors_the_rounding_mode() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(1.0f32.to_bits()));
+        // 2^-30 is far below the last place of 1.0.
+        hart.write_freg(2, float::box_f32(0x3080_0000));
+
+        // FADD.S f3, f1, f2 rounding to nearest.
+        execute(&mut hart, fp_op(0x00, 2, 1, 0b000, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(1.0f32.to_bits()));
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NX);
+
+        // Rounding up instead gives the next single above 1.0.
+        execute(&mut hart, fp_op(0x00, 2, 1, 0b011, 4));
+        assert_eq!(hart.read_freg(4), float::box_f32(0x3f80_0001));
+    }
+
+    #[test]
+    fn fcvt_w_s_saturates_out_of_range_values() {
+        // NaN, +inf, 2^32 and -inf, with the word each saturates to.
+        let cases = [
+            (f32::NAN.to_bits(), i32::MAX),
+            (f32::INFINITY.to_bits(), i32::MAX),
+            (0x4f80_0000, i32::MAX),
+            (f32::NEG_INFINITY.to_bits(), i32::MIN),
+        ];
+        for (value, expected) in cases {
+            let mut hart = hart(&[]);
+            hart.write_freg(1, float::box_f32(value));
+
+            // FCVT.W.S x5, f1 rounding towards zero.
+            execute(&mut hart, fp_op(0x60, 0, 1, 0b001, 5));
+            assert_eq!(hart.read_reg(5), expected as i64 as u64, "{value:#x}");
+            assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NV, "{value:#x}");
+        }
+    }
+
+    #[test]
+    fn fclass_classifies_subnormals_and_negative_zero() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(0x0000_0001));
+        hart.write_freg(2, float::box_f32((-0.0f32).to_bits()));
+
+        // FCLASS.S x5, f1 and FCLASS.S x6, f2.
+        execute(&mut hart, fp_op(0x70, 0, 1, 0b001, 5));
+        execute(&mut hart, fp_op(0x70, 0, 2, 0b001, 6));
+        assert_eq!(hart.read_reg(5), 1 << 5);
+        assert_eq!(hart.read_reg(6), 1 << 3);
+    }
+
+    #[test]
+    fn flt_with_a_nan_is_false_and_raises_nv() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(float::CANONICAL_NAN_F32));
+        hart.write_freg(2, float::box_f32(1.0f32.to_bits()));
+        hart.write_reg(5, 1);
+
+        // FLT.S x5, f1, f2; a quiet NaN is enough for FLT to signal.
+        execute(&mut hart, fp_op(0x50, 2, 1, 0b001, 5));
+        assert_eq!(hart.read_reg(5), 0);
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NV);
+    }
+
+    /// Checks that executing `halfword` changes the registers, memory and
+    /// control flow the way its 32-bit equivalent `word` does, starting
+    /// from `regs`.
+    fn assert_same_effect(halfword: u16, word: u32, regs: &[(usize, u64)]) {
+        let mut short = Hart::with_memory(halfword.to_le_bytes().to_vec(), 0x10000);
+        let mut long = hart(&[word]);
+        for hart in [&mut short, &mut long] {
+            for &(reg, value) in regs {
+                hart.write_reg(reg, value);
+            }
+            hart.bus
+                .dram
+                .store(DRAM_BASE + 0x100, 8, 0x0123_4567_89ab_cdef)
+                .unwrap();
+            hart.step().unwrap();
+        }
+
+        assert_eq!(short.regs, long.regs, "{halfword:#06x}");
+        assert_eq!(short.bus.dram.bytes()[4..], long.bus.dram.bytes()[4..]);
+        // Falling through advances by each instruction's own length.
+        if long.pc == DRAM_BASE + 4 {
+            assert_eq!(short.pc, DRAM_BASE + 2, "{halfword:#06x}");
+        } else {
+            assert_eq!(short.pc, long.pc, "{halfword:#06x}");
+        }
+    }
+
+    #[test]
+    fn compressed_instructions_match_their_expansions() {
+        let regs = [
+            (8, DRAM_BASE + 0xf8),
+            (9, 0xfedc_ba98_7654_3210),
+            (10, 5),
+            (12, 100),
+            (13, 7),
+        ];
+        // C.ADDI x10, -3
+        assert_same_effect(0x1575, asm::addi(10, 10, -3), &regs);
+        // C.LI x11, 17
+        assert_same_effect(0x45c5, asm::addi(11, 0, 17), &regs);
+        // C.MV x12, x13
+        assert_same_effect(0x8636, asm::add(12, 0, 13), &regs);
+        // C.ADD x12, x13
+        assert_same_effect(0x9636, asm::add(12, 12, 13), &regs);
+        // C.LW x9, 4(x8) and C.LD x9, 8(x8)
+        assert_same_effect(0x4044, asm::lw(9, 8, 4), &regs);
+        assert_same_effect(0x6404, asm::ld(9, 8, 8), &regs);
+        // C.SW x9, 4(x8) and C.SD x9, 8(x8)
+        assert_same_effect(0xc044, asm::sw(9, 8, 4), &regs);
+        assert_same_effect(0xe404, asm::sd(9, 8, 8), &regs);
+        // C.J 8
+        assert_same_effect(0xa021, asm::jal(0, 8), &regs);
+        // C.BEQZ x8, 8, both not taken and taken.
+        assert_same_effect(0xc401, asm::beq(8, 0, 8), &regs);
+        assert_same_effect(0xc401, asm::beq(8, 0, 8), &[(8, 0)]);
+    }
+
+    #[test]
+    fn executes_a_32_bit_instruction_after_a_c_nop() {
+        // C.NOP leaves the following ADDI at a 2-byte, not 4-byte, boundary.
+        let mut code = 0x0001u16.to_le_bytes().to_vec();
+        code.extend(asm::addi(1, 0, 1).to_le_bytes());
+        let mut hart = Hart::with_memory(code, 0x10000);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 2);
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 6);
+        assert_eq!(hart.read_reg(1), 1);
+    }
+
+    #[test]
+    fn fetching_a_compressed_instruction_reads_only_its_halfword() {
+        // A C.NOP filling the whole of memory.
+        let mut hart = Hart::with_memory(0x0001u16.to_le_bytes().to_vec(), 2);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 2);
+    }
+
+    #[test]
+    fn poked_memory_is_seen_by_loads() {
+        let mut hart = hart(&[asm::ld(5, 6, 0)]);
+        let addr = DRAM_BASE + 0x100;
+        hart.mem_write(addr, &0x1122_3344_5566_7788u64.to_le_bytes())
+            .unwrap();
+        hart.write_reg(6, addr);
+
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(5), 0x1122_3344_5566_7788);
+        assert_eq!(hart.mem_read(addr, 3), Ok(vec![0x88, 0x77, 0x66]));
+    }
+
+    #[test]
+    fn peek_and_poke_are_limited_to_dram() {
+        let mut hart = hart(&[]);
+        let end = DRAM_BASE + 0x10000;
+        assert_eq!(hart.mem_read(end - 2, 2), Ok(vec![0, 0]));
+        assert!(hart.mem_write(end - 2, &[1, 2]).is_ok());
+
+        // Past the end, straddling it, below DRAM and in a device.
+        for addr in [end, end - 1, DRAM_BASE - 1, UART_BASE] {
+            assert_eq!(
+                hart.mem_read(addr, 2),
+                Err(InstructionException::LoadAccessFault(addr))
+            );
+            assert_eq!(
+                hart.mem_write(addr, &[1, 2]),
+                Err(InstructionException::StoreAccessFault(addr))
+            );
+        }
+        // The failed write straddling the end left DRAM alone.
+        assert_eq!(hart.mem_read(end - 1, 1), Ok(vec![2]));
+    }
+
+    #[test]
+    fn maps_register_names_both_ways() {
+        assert_eq!(reg_index("sp"), Some(2));
+        assert_eq!(reg_name(2), "sp");
+        assert_eq!(reg_index("x31"), Some(31));
+        assert_eq!(reg_index("t6"), Some(31));
+        assert_eq!(reg_name(31), "t6");
+        assert_eq!(reg_index("fp"), reg_index("s0"));
+
+        for garbage in ["", "x", "x32", "x05", "x+5", "t7", "SP", "sp "] {
+            assert_eq!(reg_index(garbage), None, "{garbage:?}");
+        }
+    }
+
+    #[test]
+    fn run_until_stops_an_infinite_loop_after_max_steps() {
+        let mut hart = hart(&[asm::addi(5, 5, 1), asm::jal(0, -4)]);
+
+        assert_eq!(hart.run_until(100), None);
+        assert_eq!(hart.csr.read(INSTRET), 100);
+        // Each iteration adds one in 2 instructions.
+        assert_eq!(hart.read_reg(5), 50);
+        assert_eq!(hart.pc, DRAM_BASE);
+    }
+
+    #[test]
+    fn profile_counts_each_instruction() {
+        let mut hart = hart(&[
+            asm::addi(5, 0, 3),
+            asm::addi(5, 5, -1),
+            asm::bne(5, 0, -4),
+            asm::ebreak(),
+        ]);
+        hart.set_profile(true);
+
+        hart.run();
+        assert_eq!(
+            hart.instruction_counts(),
+            BTreeMap::from([("addi", 4), ("bne", 3), ("ebreak", 1)])
+        );
+    }
+
+    #[test]
+    fn breakpoints_stop_each_pass_through_a_loop() {
+        let mut hart = hart(&[
+            asm::addi(5, 0, 3),
+            asm::addi(6, 6, 1),
+            asm::addi(5, 5, -1),
+            asm::bne(5, 0, -8),
+            asm::ebreak(),
+        ]);
+        hart.add_breakpoint(DRAM_BASE + 8);
+
+        for pass in 1..=3 {
+            assert_eq!(hart.run(), Halt::Breakpoint(DRAM_BASE + 8));
+            assert_eq!(hart.read_reg(6), pass);
+        }
+        hart.remove_breakpoint(DRAM_BASE + 8);
+        assert_eq!(
+            hart.run(),
+            Halt::Exception(InstructionException::Breakpoint(DRAM_BASE + 16))
+        );
+    }
+
+    #[test]
+    fn write_watchpoints_stop_after_the_store() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[
+            asm::addi(6, 0, 0x55),
+            // Neither the byte after the word nor a load hit.
+            asm::sb(6, 7, 4),
+            asm::lw(8, 7, 0),
+            asm::sb(6, 7, 3),
+            asm::addi(9, 0, 1),
+        ]);
+        hart.write_reg(7, addr);
+        hart.add_watchpoint(addr, 4, WatchKind::Write);
+
+        assert_eq!(
+            hart.run(),
+            Halt::Watchpoint(WatchpointHit {
+                pc: DRAM_BASE + 12,
+                addr: addr + 3,
+                value: 0x55,
+                kind: WatchKind::Write,
+            })
+        );
+        // The store has completed, and nothing after it has run.
+        assert_eq!(hart.pc, DRAM_BASE + 16);
+        assert_eq!(hart.mem_read(addr + 3, 1), Ok(vec![0x55]));
+        assert_eq!(hart.read_reg(9), 0);
+    }
+
+    #[test]
+    fn executing_an_unimplemented_opcode_is_an_error() {
+        let mut hart = hart(&[]);
+        // The 0x7f major opcode is reserved for longer instructions.
+        let word = asm::i_type(0, 1, 0b000, 5, 0x7f);
+
+        assert_eq!(
+            hart.execute(InstructionBytes(word)),
+            Err(InstructionException::IllegalInstruction(word))
+        );
+        assert_eq!(hart.read_reg(5), 0);
+    }
+
+    #[test]
+    fn shifts_by_immediate_use_all_six_shamt_bits() {
+        let mut hart = hart(&[]);
+        hart.write_reg(2, 0x8000_0000_0000_0f00);
+
+        execute(&mut hart, asm::slli(1, 2, 36));
+        assert_eq!(hart.read_reg(1), 0xf000_0000_0000);
+        // SRAI copies the sign bit in, where SRLI shifts in zeros.
+        execute(&mut hart, asm::srai(1, 2, 60));
+        assert_eq!(hart.read_reg(1), 0xffff_ffff_ffff_fff8);
+        execute(&mut hart, asm::srli(1, 2, 60));
+        assert_eq!(hart.read_reg(1), 0x8);
+        execute(&mut hart, asm::srai(1, 2, 0));
+        assert_eq!(hart.read_reg(1), 0x8000_0000_0000_0f00);
+    }
+
+    #[test]
+    fn execution_begins_at_the_entry_point() {
+        let code = [asm::addi(5, 0, 1), asm::addi(5, 0, 2), asm::addi(6, 0, 3)]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let mut hart = Hart::with_entry(code, DRAM_BASE + 8);
+
+        assert_eq!(hart.fetch(), Ok(InstructionBytes(asm::addi(6, 0, 3))));
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(5), 0);
+        assert_eq!(hart.read_reg(6), 3);
+
+        hart.reset();
+        assert_eq!(hart.pc, DRAM_BASE + 8);
+    }
+
+    #[test]
+    fn delegated_user_ecalls_trap_to_supervisor_mode() {
+        let mut hart = hart(&[asm::ecall()]);
+        hart.csr.write(MEDELEG, 1 << 8);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+        hart.csr.write(STVEC, DRAM_BASE + 0x200);
+        hart.mode = PrivilegeMode::User;
+
+        hart.step().unwrap();
+        assert_eq!(hart.mode, PrivilegeMode::Supervisor);
+        assert_eq!(hart.pc, DRAM_BASE + 0x200);
+        assert_eq!(hart.csr.read(SCAUSE), 8);
+        assert_eq!(hart.csr.read(SEPC), DRAM_BASE);
+        assert_eq!(hart.csr.mstatus().spp(), PrivilegeMode::User);
+        // The Machine-mode trap registers are untouched.
+        assert_eq!(hart.csr.read(MCAUSE), 0);
+        assert_eq!(hart.csr.read(MEPC), 0);
+    }
+
+    #[test]
+    fn undelegated_user_ecalls_trap_to_machine_mode() {
+        let mut hart = hart(&[asm::ecall()]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+        hart.csr.write(STVEC, DRAM_BASE + 0x200);
+        hart.mode = PrivilegeMode::User;
+
+        hart.step().unwrap();
+        assert_eq!(hart.mode, PrivilegeMode::Machine);
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+        assert_eq!(hart.csr.read(MCAUSE), 8);
+        assert_eq!(hart.csr.read(SCAUSE), 0);
+    }
+
+    /// An AMO instruction word, with `funct5` selecting the operation.
+    fn amo(funct5: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+        asm::r_type(funct5 << 2, rs2, rs1, funct3, rd, opcode::AMO)
+    }
+
+    #[test]
+    fn sc_w_succeeds_after_lr_w() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[amo(0b00010, 0, 5, 0b010, 7), amo(0b00011, 6, 5, 0b010, 8)]);
+        hart.bus.dram.store(addr, 4, 0x8000_0001).unwrap();
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 42);
+        hart.write_reg(8, 7);
+
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(7), 0xffff_ffff_8000_0001);
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(8), 0);
+        assert_eq!(hart.bus.dram.load(addr, 4), Ok(42));
+    }
+
+    #[test]
+    fn sc_w_fails_after_a_store_to_the_reserved_word() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[
+            amo(0b00010, 0, 5, 0b010, 7),
+            asm::sw(9, 5, 0),
+            amo(0b00011, 6, 5, 0b010, 8),
+        ]);
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 42);
+        hart.write_reg(9, 5);
+
+        for _ in 0..3 {
+            hart.step().unwrap();
+        }
+        assert_eq!(hart.read_reg(8), 1);
+        assert_eq!(hart.bus.dram.load(addr, 4), Ok(5));
+    }
+
+    #[test]
+    fn amomaxu_w_compares_unsigned_words() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[]);
+        hart.bus.dram.store(addr, 4, 0x8000_0000).unwrap();
+        hart.write_reg(5, addr);
+
+        // 0x7fffffff is below 0x80000000 unsigned, though not signed.
+        hart.write_reg(6, 0x7fff_ffff);
+        execute(&mut hart, amo(0b11100, 6, 5, 0b010, 7));
+        assert_eq!(hart.read_reg(7), 0xffff_ffff_8000_0000);
+        assert_eq!(hart.bus.dram.load(addr, 4), Ok(0x8000_0000));
+
+        // Only the low word of rs2 takes part.
+        hart.write_reg(6, 0x1234_5678_ffff_fffe);
+        execute(&mut hart, amo(0b11100, 6, 5, 0b010, 7));
+        assert_eq!(hart.read_reg(7), 0xffff_ffff_8000_0000);
+        assert_eq!(hart.bus.dram.load(addr, 8), Ok(0xffff_fffe));
+    }
+
+    #[test]
+    fn amoadd_d_returns_the_old_doubleword() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[]);
+        hart.bus.dram.store(addr, 8, 0x1_ffff_ffff).unwrap();
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 1);
+
+        execute(&mut hart, amo(0b00000, 6, 5, 0b011, 7));
+        assert_eq!(hart.read_reg(7), 0x1_ffff_ffff);
+        assert_eq!(hart.bus.dram.load(addr, 8), Ok(0x2_0000_0000));
+    }
+
+    #[test]
+    fn misaligned_sw_is_emulated_by_default() {
+        let addr = DRAM_BASE + 0x101;
+        let mut hart = hart(&[]);
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 0x1122_3344);
+
+        execute(&mut hart, asm::sw(6, 5, 0));
+        assert_eq!(
+            hart.mem_read(addr - 1, 6),
+            Ok(vec![0, 0x44, 0x33, 0x22, 0x11, 0])
+        );
+    }
+
+    #[test]
+    fn misaligned_sw_traps_when_asked_to() {
+        let addr = DRAM_BASE + 0x101;
+        let mut hart = hart(&[]);
+        hart.set_misaligned(MisalignedAccess::Trap);
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 0x1122_3344);
+
+        assert_eq!(
+            hart.execute(InstructionBytes(asm::sw(6, 5, 0))),
+            Err(InstructionException::StoreAddressMisaligned(addr))
+        );
+        assert_eq!(hart.mem_read(addr, 4), Ok(vec![0; 4]));
+    }
+
+    #[test]
+    fn json_traces_each_retired_instruction_and_its_writes() {
+        let mut hart = hart(&[asm::addi(10, 0, 42), asm::sd(10, 2, -8)]);
+        let output = Capture::default();
+        hart.trace_json_to(Box::new(output.clone()));
+
+        hart.step().unwrap();
+        hart.step().unwrap();
+        assert_eq!(
+            output.text().lines().collect::<Vec<_>>(),
+            [
+                concat!(
+                    r#"{"pc":"0x0000000080000000","bytes":"02a00513","disasm":"addi a0, zero, 42","#,
+                    r#""mode":"M","writes":[{"reg":"a0","value":"0x000000000000002a"}]}"#,
+                ),
+                concat!(
+                    r#"{"pc":"0x0000000080000004","bytes":"fea13c23","disasm":"sd a0, -8(sp)","#,
+                    r#""mode":"M","writes":[{"mem":"0x000000008000fff8","size":8,"#,
+                    r#""value":"0x000000000000002a"}]}"#,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn registers_json_has_the_final_state() {
+        let mut hart = hart(&[asm::addi(10, 0, 42)]);
+        hart.step().unwrap();
+
+        let json = hart.registers_json();
+        assert!(json.starts_with(r#"{"pc":"0x0000000080000004","mode":"M","x":["#));
+        let x: Vec<&str> = json
+            .split_once(r#""x":["#)
+            .and_then(|(_, x)| x.strip_suffix("]}"))
+            .unwrap()
+            .split(',')
+            .collect();
+        assert_eq!(x.len(), 32);
+        assert_eq!(x[0], r#""0x0000000000000000""#);
+        assert_eq!(x[10], r#""0x000000000000002a""#);
+    }
+
+    #[test]
+    fn programs_can_be_loaded_at_an_offset() {
+        let code: Vec<u8> = [asm::addi(5, 0, 7), asm::addi(6, 0, 8)]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let addr = DRAM_BASE + 0x20_0000;
+        let mut hart = Hart::with_load_addr(&code, 0x40_0000, addr).unwrap();
+
+        assert_eq!(hart.pc, addr);
+        assert_eq!(hart.fetch(), Ok(InstructionBytes(asm::addi(5, 0, 7))));
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(5), 7);
+        // Nothing was loaded at the start of DRAM.
+        assert_eq!(hart.mem_read(DRAM_BASE, 8), Ok(vec![0; 8]));
+
+        assert!(Hart::with_load_addr(&code, 0x40_0000, DRAM_BASE + 0x40_0000 - 4).is_none());
+        assert!(Hart::with_load_addr(&code, 0x40_0000, DRAM_BASE - 4).is_none());
+    }
+
+    #[test]
+    fn misa_reports_only_the_enabled_extensions() {
+        let mut hart = hart(&[]);
+        hart.extensions = Extensions {
+            m: true,
+            ..Extensions::from_isa("rv64i").unwrap()
+        };
+
+        execute(&mut hart, asm::csrrs(5, MISA as u32, 0));
+        let misa = hart.read_reg(5);
+        let has = |letter: u8| misa & (1 << (letter - b'A')) != 0;
+        assert_eq!(misa >> 62, 2);
+        assert!(has(b'I') && has(b'M'));
+        assert!(!has(b'A') && !has(b'F') && !has(b'D') && !has(b'C'));
+
+        let amoadd_w = amo(0b00000, 6, 5, 0b010, 7);
+        assert_eq!(
+            hart.execute(InstructionBytes(amoadd_w)),
+            Err(InstructionException::IllegalInstruction(amoadd_w))
+        );
+    }
+
+    #[test]
+    fn sc_d_succeeds_after_lr_d() {
+        let addr = DRAM_BASE + 0x100;
+        let mut hart = hart(&[amo(0b00010, 0, 5, 0b011, 7), amo(0b00011, 6, 5, 0b011, 8)]);
+        hart.bus.dram.store(addr, 8, 0x8000_0000_0000_0001).unwrap();
+        hart.write_reg(5, addr);
+        hart.write_reg(6, 0x1_0000_0000);
+        hart.write_reg(8, 7);
+
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(7), 0x8000_0000_0000_0001);
+        hart.step().unwrap();
+        assert_eq!(hart.read_reg(8), 0);
+        assert_eq!(hart.bus.dram.load(addr, 8), Ok(0x1_0000_0000));
+    }
+
+    #[test]
+    fn mul_needs_the_m_extension() {
+        let mul = asm::mul(5, 6, 7);
+        let mut hart = hart(&[]);
+        hart.extensions = Extensions::from_isa("rv64i").unwrap();
+        hart.write_reg(6, 6);
+        hart.write_reg(7, 7);
+        assert_eq!(
+            hart.execute(InstructionBytes(mul)),
+            Err(InstructionException::IllegalInstruction(mul))
+        );
+        assert_eq!(hart.read_reg(5), 0);
+
+        hart.extensions = Extensions::from_isa("rv64im").unwrap();
+        execute(&mut hart, mul);
+        assert_eq!(hart.read_reg(5), 42);
+    }
+
+    #[test]
+    fn napot_read_only_region_faults_stores_from_supervisor_mode() {
+        let region = DRAM_BASE + 0x8000;
+        let mut hart = hart(&[]);
+        // Entry 0 makes a 4 KiB NAPOT region read-only, and entry 1 allows
+        // everything else.
+        hart.csr.write(PMPADDR0, (region + 0x7ff) >> 2);
+        hart.csr.write(PMPADDR0 + 1, pmp::PMPADDR_MASK);
+        hart.csr.write(PMPCFG0, 0x1f19);
+        hart.bus.dram.store(region + 0x10, 8, 0x1234).unwrap();
+        hart.mode = PrivilegeMode::Supervisor;
+        hart.write_reg(5, region + 0x10);
+        hart.write_reg(6, 0x5678);
+
+        execute(&mut hart, asm::ld(7, 5, 0));
+        assert_eq!(hart.read_reg(7), 0x1234);
+        assert_eq!(
+            hart.execute(InstructionBytes(asm::sd(6, 5, 0))),
+            Err(InstructionException::StoreAccessFault(region + 0x10))
+        );
+        assert_eq!(hart.bus.dram.load(region + 0x10, 8), Ok(0x1234));
+        // The doubleword after the region is writable.
+        hart.write_reg(5, region + 0x1000);
+        execute(&mut hart, asm::sd(6, 5, 0));
+        assert_eq!(hart.bus.dram.load(region + 0x1000, 8), Ok(0x5678));
+    }
+
+    #[test]
+    fn step_records_list_the_store() {
+        let word = asm::sw(6, 5, 4);
+        let mut hart = hart(&[word]);
+        hart.write_reg(5, DRAM_BASE + 0x100);
+        hart.write_reg(6, 0xdead_beef_cafe_f00d);
+
+        assert_eq!(
+            hart.step_verbose(),
+            StepRecord {
+                pc: DRAM_BASE,
+                instruction: Instruction::try_from(InstructionBytes(word)).ok(),
+                writes: vec![TraceWrite::Mem {
+                    addr: DRAM_BASE + 0x104,
+                    size: 4,
+                    value: 0xcafe_f00d,
+                }],
+                next_pc: DRAM_BASE + 4,
+                trap: None,
+                exception: None,
+            }
+        );
+        assert_eq!(
+            hart.mem_read(DRAM_BASE + 0x104, 4),
+            Ok(vec![0x0d, 0xf0, 0xfe, 0xca])
+        );
+    }
+
+    #[test]
+    fn step_records_show_traps_taken() {
+        let mut hart = hart(&[asm::ecall()]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+        let record = hart.step_verbose();
+        assert_eq!(record.trap, Some(11));
+        assert_eq!(record.next_pc, DRAM_BASE + 0x100);
+        assert_eq!(record.exception, None);
+    }
+
+    #[test]
+    fn mbe_flips_the_byte_order_of_data_accesses() {
+        let addr = DRAM_BASE + 0x100;
+        for (mstatus, bytes) in [
+            (0, [8, 7, 6, 5, 4, 3, 2, 1]),
+            (MSTATUS_MBE, [1, 2, 3, 4, 5, 6, 7, 8]),
+        ] {
+            // Fetches stay little-endian, so the program runs either way.
+            let mut hart = hart(&[asm::sd(6, 5, 0), asm::lbu(7, 5, 0), asm::ld(8, 5, 0)]);
+            hart.csr.write(MSTATUS, mstatus);
+            hart.write_reg(5, addr);
+            hart.write_reg(6, 0x0102_0304_0506_0708);
+
+            for _ in 0..3 {
+                hart.step().unwrap();
+            }
+            assert_eq!(hart.mem_read(addr, 8), Ok(bytes.to_vec()));
+            assert_eq!(hart.read_reg(7), bytes[0] as u64);
+            assert_eq!(hart.read_reg(8), 0x0102_0304_0506_0708);
+        }
+    }
+
+    #[test]
+    fn run_to_stops_at_the_target_address() {
+        let mut hart = hart(&[
+            asm::addi(5, 0, 1),
+            asm::addi(5, 5, 1),
+            asm::addi(5, 5, 1),
+            // The target.
+            asm::addi(5, 5, 1),
+            asm::ebreak(),
+        ]);
+
+        assert_eq!(
+            hart.run_to(DRAM_BASE + 12, 100),
+            Some(Halt::Breakpoint(DRAM_BASE + 12))
+        );
+        assert_eq!(hart.pc, DRAM_BASE + 12);
+        assert_eq!(hart.csr.read(INSTRET), 3);
+        assert_eq!(hart.read_reg(5), 3);
+
+        // The target is not left behind as a breakpoint.
+        assert_eq!(
+            hart.run(),
+            Halt::Exception(InstructionException::Breakpoint(DRAM_BASE + 16))
+        );
+    }
+
+    #[test]
+    fn run_to_gives_up_after_max_steps() {
+        let mut hart = hart(&[asm::addi(5, 5, 1), asm::jal(0, -4)]);
+
+        assert_eq!(hart.run_to(DRAM_BASE + 8, 10), None);
+        assert_eq!(hart.csr.read(INSTRET), 10);
+    }
+
+    #[test]
+    fn fadd_d_uses_the_whole_register() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, 1.5f64.to_bits());
+        hart.write_freg(2, 2.25f64.to_bits());
+
+        // FADD.D f3, f1, f2
+        execute(&mut hart, fp_op(0x01, 2, 1, 0b000, 3));
+        assert_eq!(hart.read_freg(3), 3.75f64.to_bits());
+        assert_eq!(hart.csr.read(FFLAGS), 0);
+    }
+
+    #[test]
+    fn fsd_then_fld_round_trips_a_double() {
+        let mut hart = hart(&[]);
+        let addr = DRAM_BASE + 0x100;
+        hart.write_reg(5, addr);
+        hart.write_freg(1, std::f64::consts::PI.to_bits());
+
+        execute(&mut hart, asm::s_type(8, 1, 5, 0b011, opcode::STORE_FP));
+        assert_eq!(
+            hart.bus.dram.load(addr + 8, 8),
+            Ok(std::f64::consts::PI.to_bits())
+        );
+        execute(&mut hart, asm::i_type(8, 5, 0b011, 2, opcode::LOAD_FP));
+        assert_eq!(hart.read_freg(2), 0x4009_21fb_5444_2d18);
+    }
+
+    #[test]
+    fn double_comparisons_and_classes() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, (-0.0f64).to_bits());
+        hart.write_freg(2, 0x0000_0000_0000_0001);
+        hart.write_freg(3, float::CANONICAL_NAN_F64);
+
+        // FLT.D x5, f1, f2, and FEQ.D x6, f1, f3, which is quiet.
+        execute(&mut hart, fp_op(0x51, 2, 1, 0b001, 5));
+        execute(&mut hart, fp_op(0x51, 3, 1, 0b010, 6));
+        assert_eq!((hart.read_reg(5), hart.read_reg(6)), (1, 0));
+        assert_eq!(hart.csr.read(FFLAGS), 0);
+        // FLE.D x6, f1, f3 signals for any NaN.
+        execute(&mut hart, fp_op(0x51, 3, 1, 0b000, 6));
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NV);
+
+        // FCLASS.D x7, f1 and FCLASS.D x8, f2.
+        execute(&mut hart, fp_op(0x71, 0, 1, 0b001, 7));
+        execute(&mut hart, fp_op(0x71, 0, 2, 0b001, 8));
+        assert_eq!((hart.read_reg(7), hart.read_reg(8)), (1 << 3, 1 << 5));
+    }
+
+    #[test]
+    fn fcvt_between_doubles_and_integers() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, 1e10f64.to_bits());
+        hart.write_freg(2, (-2.5f64).to_bits());
+
+        // FCVT.W.D x5, f1 saturates.
+        execute(&mut hart, fp_op(0x61, 0, 1, 0b001, 5));
+        assert_eq!(hart.read_reg(5), i32::MAX as u64);
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NV);
+        // FCVT.L.D x6, f2 rounds to even.
+        hart.csr.write(FFLAGS, 0);
+        execute(&mut hart, fp_op(0x61, 2, 2, 0b000, 6));
+        assert_eq!(hart.read_reg(6), -2i64 as u64);
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NX);
+
+        // FCVT.D.W f3, x7 is exact, while FCVT.D.LU f4, x8 rounds up to 2^64.
+        hart.csr.write(FFLAGS, 0);
+        hart.write_reg(7, -7i64 as u64);
+        execute(&mut hart, fp_op(0x69, 0, 7, 0b000, 3));
+        assert_eq!(hart.read_freg(3), (-7.0f64).to_bits());
+        assert_eq!(hart.csr.read(FFLAGS), 0);
+        hart.write_reg(8, u64::MAX);
+        execute(&mut hart, fp_op(0x69, 3, 8, 0b000, 4));
+        assert_eq!(hart.read_freg(4), 2f64.powi(64).to_bits());
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NX);
+    }
+
+    #[test]
+    fn compressed_double_loads_and_stores() {
+        // C.FSD f9, 8(x8), C.FLD f10, 8(x8), C.FSDSP f1, 8(sp) and
+        // C.FLDSP f2, 8(sp).
+        let code = [0xa404u16, 0x2408, 0xa406, 0x2122]
+            .iter()
+            .flat_map(|halfword| halfword.to_le_bytes())
+            .collect();
+        let mut hart = Hart::with_memory(code, 0x10000);
+        hart.write_reg(8, DRAM_BASE + 0x100);
+        hart.write_reg(2, DRAM_BASE + 0x200);
+        hart.write_freg(9, std::f64::consts::PI.to_bits());
+        hart.write_freg(1, std::f64::consts::E.to_bits());
+
+        for _ in 0..4 {
+            hart.step().unwrap();
+        }
+        assert_eq!(hart.read_freg(10), std::f64::consts::PI.to_bits());
+        assert_eq!(hart.read_freg(2), std::f64::consts::E.to_bits());
+        assert_eq!(
+            hart.bus.dram.load(DRAM_BASE + 0x208, 8),
+            Ok(std::f64::consts::E.to_bits())
+        );
+
+        // They are illegal without D.
+        hart.extensions.d = false;
+        assert_eq!(
+            hart.execute(InstructionBytes(0x2408)),
+            Err(InstructionException::IllegalInstruction(0x2408))
+        );
+    }
+
+    /// A fused multiply-add instruction word, with `fmt` 0 for singles and
+    /// 1 for doubles.
+    fn fma(opcode: u32, rs3: u32, fmt: u32, rs2: u32, rs1: u32, rd: u32) -> u32 {
+        asm::r_type(rs3 << 2 | fmt, rs2, rs1, 0b000, rd, opcode)
+    }
+
+    #[test]
+    fn fmadd_d_rounds_once() {
+        let mut hart = hart(&[]);
+        let (a, b, c) = (1.0 + 2f64.powi(-30), 1.0 - 2f64.powi(-30), -1.0f64);
+        hart.write_freg(1, a.to_bits());
+        hart.write_freg(2, b.to_bits());
+        hart.write_freg(3, c.to_bits());
+
+        // FMADD.D f4, f1, f2, f3
+        execute(&mut hart, fma(opcode::MADD, 3, 1, 2, 1, 4));
+        assert_eq!(hart.read_freg(4), a.mul_add(b, c).to_bits());
+        // Rounding the product first would have lost it entirely.
+        assert_eq!(a * b + c, 0.0);
+        assert_eq!(f64::from_bits(hart.read_freg(4)), -(2f64.powi(-60)));
+    }
+
+    #[test]
+    fn fnmsub_negates_the_product() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, 2.0f64.to_bits());
+        hart.write_freg(2, 3.0f64.to_bits());
+        hart.write_freg(3, 1.0f64.to_bits());
+
+        // FNMSUB.D f4, f1, f2, f3 is -(f1 * f2) + f3.
+        execute(&mut hart, fma(opcode::NMSUB, 3, 1, 2, 1, 4));
+        assert_eq!(f64::from_bits(hart.read_freg(4)), -5.0);
+        // FNMADD.D f5, f1, f2, f3 is -(f1 * f2) - f3.
+        execute(&mut hart, fma(opcode::NMADD, 3, 1, 2, 1, 5));
+        assert_eq!(f64::from_bits(hart.read_freg(5)), -7.0);
+    }
+
+    #[test]
+    fn fsgnjn_with_itself_negates() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(2.5f32.to_bits()));
+
+        // FSGNJN.S f2, f1, f1 is FNEG.S f2, f1.
+        execute(&mut hart, fp_op(0x10, 1, 1, 0b001, 2));
+        assert_eq!(hart.read_freg(2), float::box_f32((-2.5f32).to_bits()));
+        execute(&mut hart, fp_op(0x10, 2, 2, 0b001, 3));
+        assert_eq!(hart.read_freg(3), hart.read_freg(1));
+    }
+
+    #[test]
+    fn fmin_with_a_quiet_nan_returns_the_other_operand() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(float::CANONICAL_NAN_F32));
+        hart.write_freg(2, float::box_f32(2.5f32.to_bits()));
+
+        // FMIN.S f3, f1, f2
+        execute(&mut hart, fp_op(0x14, 2, 1, 0b000, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(2.5f32.to_bits()));
+        assert_eq!(hart.csr.read(FFLAGS), 0);
+
+        // A signaling NaN also raises NV.
+        hart.write_freg(1, float::box_f32(0x7f80_0001));
+        execute(&mut hart, fp_op(0x14, 2, 1, 0b000, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(2.5f32.to_bits()));
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NV);
+    }
+
+    #[test]
+    fn fmin_orders_negative_zero_below_positive_zero() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32(0.0f32.to_bits()));
+        hart.write_freg(2, float::box_f32((-0.0f32).to_bits()));
+
+        execute(&mut hart, fp_op(0x14, 2, 1, 0b000, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32((-0.0f32).to_bits()));
+        // FMAX.S
+        execute(&mut hart, fp_op(0x14, 2, 1, 0b001, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(0.0f32.to_bits()));
+    }
+
+    #[test]
+    fn fmv_x_w_sign_extends_the_single() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, float::box_f32((-1.0f32).to_bits()));
+
+        // FMV.X.W x5, f1
+        execute(&mut hart, fp_op(0x70, 0, 1, 0b000, 5));
+        assert_eq!(hart.read_reg(5), 0xffff_ffff_bf80_0000);
+        // FMV.W.X f2, x5 takes the low word back, boxed.
+        execute(&mut hart, fp_op(0x78, 0, 5, 0b000, 2));
+        assert_eq!(hart.read_freg(2), hart.read_freg(1));
+    }
+
+    #[test]
+    fn fcvt_s_d_rounds_and_raises_nx() {
+        let mut hart = hart(&[]);
+        hart.write_freg(1, 0.1f64.to_bits());
+
+        // FCVT.S.D f2, f1 rounding to nearest.
+        execute(&mut hart, fp_op(0x20, 1, 1, 0b000, 2));
+        assert_eq!(hart.read_freg(2), float::box_f32(0.1f32.to_bits()));
+        assert_eq!(hart.csr.read(FFLAGS), float::FLAG_NX);
+
+        // Rounding towards zero gives the single below instead.
+        execute(&mut hart, fp_op(0x20, 1, 1, 0b001, 3));
+        assert_eq!(hart.read_freg(3), float::box_f32(0.1f32.to_bits() - 1));
+    }
+
+    #[test]
+    fn timer_fires_after_mtimecmp_steps() {
+        for (time_per_step, steps) in [(1, 10), (5, 2)] {
+            let mut hart = hart(&[asm::addi(1, 1, 1), asm::jal(0, -4)]);
+            hart.set_time_per_step(time_per_step);
+            hart.bus.store(CLINT_BASE + 0x4000, 8, 10).unwrap();
+            hart.csr.write(MIE, MIP_MTIP);
+            hart.csr.write(MSTATUS, MSTATUS_MIE);
+            hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+            let taken = (1..=100)
+                .find(|_| {
+                    hart.step().unwrap();
+                    hart.pc == DRAM_BASE + 0x100
+                })
+                .unwrap();
+            // The interrupt is taken on the step that brings mtime up to
+            // mtimecmp, in place of an instruction.
+            assert_eq!(taken, steps, "{time_per_step} per step");
+            assert_eq!(hart.csr.read(INSTRET), steps - 1);
+            assert_eq!(hart.bus.clint.mtime(), time_per_step * steps);
+        }
+    }
+
+    #[test]
+    fn sret_returns_to_the_mode_in_spp() {
+        let mut hart = hart(&[asm::sret()]);
+        hart.mode = PrivilegeMode::Supervisor;
+        hart.csr.write(SEPC, DRAM_BASE + 0x40);
+        hart.csr.write(MSTATUS, MSTATUS_SPIE | MSTATUS_SPP);
+
+        hart.step().unwrap();
+        assert_eq!(hart.pc, DRAM_BASE + 0x40);
+        assert_eq!(hart.mode, PrivilegeMode::Supervisor);
+        let mstatus = hart.csr.mstatus();
+        assert!(mstatus.sie());
+        assert!(mstatus.spie());
+        assert_eq!(mstatus.spp(), PrivilegeMode::User);
+
+        // SPP was cleared, so a second SRET drops to User mode.
+        hart.csr.write(SEPC, DRAM_BASE + 0x80);
+        execute(&mut hart, asm::sret());
+        assert_eq!(hart.pc, DRAM_BASE + 0x80);
+        assert_eq!(hart.mode, PrivilegeMode::User);
+    }
+
+    #[test]
+    fn sret_traps_in_user_mode_and_under_tsr() {
+        let sret = asm::sret();
+        let mut hart = hart(&[]);
+        hart.mode = PrivilegeMode::User;
+        assert_eq!(
+            hart.execute(InstructionBytes(sret)),
+            Err(InstructionException::IllegalInstruction(sret))
+        );
+
+        hart.mode = PrivilegeMode::Supervisor;
+        hart.csr.write(MSTATUS, MSTATUS_TSR);
+        assert_eq!(
+            hart.execute(InstructionBytes(sret)),
+            Err(InstructionException::IllegalInstruction(sret))
+        );
+        // Machine mode is not affected by TSR.
+        hart.mode = PrivilegeMode::Machine;
+        execute(&mut hart, sret);
+    }
+
+    #[test]
+    fn zeroed_memory_traps_as_an_illegal_instruction() {
+        let mut hart = hart(&[]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+        hart.step().unwrap();
+        assert_eq!(hart.csr.read(MCAUSE), 2);
+        assert_eq!(hart.csr.read(MEPC), DRAM_BASE);
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+    }
+
+    #[test]
+    fn ecall_halts_with_a0_when_asked_to() {
+        let mut hart = hart(&[asm::addi(10, 0, 42), asm::ecall()]);
+        hart.set_halt_on_ecall(true);
+
+        assert_eq!(hart.run(), Halt::Exit(42));
+        assert_eq!(hart.pc, DRAM_BASE + 4);
+    }
+
+    #[test]
+    fn ecall_traps_by_default() {
+        let mut hart = hart(&[asm::addi(10, 0, 42), asm::ecall()]);
+        hart.csr.write(MTVEC, DRAM_BASE + 0x100);
+
+        assert_eq!(hart.run_until(2), None);
+        assert_eq!(hart.csr.read(MCAUSE), 11);
+        assert_eq!(hart.csr.read(MEPC), DRAM_BASE + 4);
+        assert_eq!(hart.pc, DRAM_BASE + 0x100);
+    }
+}
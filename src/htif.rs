@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use crate::bus::MemoryDevice;
+use crate::dram::Dram;
+use crate::exception::InstructionException;
+
+/// Command for device 0, the syscall proxy, and device 1, the console.
+const SYSCALL: (u64, u64) = (0, 0);
+const PUTCHAR: (u64, u64) = (1, 1);
+
+/// Proxied syscall numbers, as in the RISC-V Linux ABI.
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+const EBADF: u64 = 9;
+const EFAULT: u64 = 14;
+const ENOSYS: u64 = 38;
+
+const PAYLOAD_MASK: u64 = (1 << 48) - 1;
+
+/// Spike's host-target interface: a pair of `tohost` and `fromhost` words
+/// that the guest uses to send commands to the host and read its replies.
+///
+/// A `tohost` command has a device in bits [63:56], a command in bits
+/// [55:48] and a payload below that. An odd payload to the syscall device
+/// exits with the payload shifted right by one; an even one points at a
+/// block of eight doublewords holding a syscall number and its arguments.
+/// Commands are consumed as soon as they are written, so `tohost` always
+/// reads as zero.
+pub struct Htif {
+    tohost: u64,
+    fromhost: u64,
+    output: Box<dyn Write>,
+    fromhost_value: u64,
+    /// The address of a syscall block waiting for [`Htif::service`].
+    syscall: Option<u64>,
+    exit_code: Option<u64>,
+}
+
+impl Htif {
+    /// Creates an interface whose words are at physical addresses `tohost`
+    /// and `fromhost`, writing console output and proxied writes to
+    /// `output`.
+    pub fn new(tohost: u64, fromhost: u64, output: Box<dyn Write>) -> Self {
+        Htif {
+            tohost,
+            fromhost,
+            output,
+            fromhost_value: 0,
+            syscall: None,
+            exit_code: None,
+        }
+    }
+
+    /// The physical address of `tohost`, which device offsets are relative
+    /// to.
+    pub fn base(&self) -> u64 {
+        self.tohost
+    }
+
+    /// Whether all `len` bytes at `addr` fall within `tohost` or `fromhost`.
+    pub fn covers(&self, addr: u64, len: usize) -> bool {
+        let within = |word: u64| word <= addr && addr.saturating_add(len as u64) <= word + 8;
+        within(self.tohost) || within(self.fromhost)
+    }
+
+    /// The code the guest exited with, once it has.
+    pub fn exit_code(&self) -> Option<u64> {
+        self.exit_code
+    }
+
+    /// Carries out a syscall written to `tohost`, which needs access to the
+    /// guest's memory, and replies through `fromhost`.
+    pub fn service(&mut self, dram: &mut Dram) {
+        let Some(block) = self.syscall.take() else {
+            return;
+        };
+
+        let mut args = [0; 8];
+        for (i, arg) in args.iter_mut().enumerate() {
+            match dram.load(block.wrapping_add(8 * i as u64), 8) {
+                Ok(value) => *arg = value,
+                Err(_) => return,
+            }
+        }
+
+        let result = match args[0] {
+            SYS_WRITE => self.write(dram, args[1], args[2], args[3]),
+            SYS_EXIT => {
+                self.exit_code = Some(args[1]);
+                return;
+            }
+            _ => ENOSYS.wrapping_neg(),
+        };
+        let _ = dram.store(block, 8, result);
+        self.fromhost_value = 1;
+    }
+
+    /// Writes `len` bytes at `buf` to the output if `fd` is stdout or
+    /// stderr, returning the syscall's result.
+    fn write(&mut self, dram: &Dram, fd: u64, buf: u64, len: u64) -> u64 {
+        if fd != 1 && fd != 2 {
+            return EBADF.wrapping_neg();
+        }
+        let Some(bytes) = buf
+            .checked_sub(dram.base())
+            .and_then(|start| dram.bytes().get(start as usize..)?.get(..len as usize))
+        else {
+            return EFAULT.wrapping_neg();
+        };
+
+        let _ = self.output.write_all(bytes);
+        let _ = self.output.flush();
+        len
+    }
+
+    fn command(&mut self, value: u64) {
+        let payload = value & PAYLOAD_MASK;
+        match (value >> 56, (value >> 48) & 0xff) {
+            SYSCALL if payload & 1 != 0 => self.exit_code = Some(payload >> 1),
+            SYSCALL => self.syscall = Some(payload),
+            PUTCHAR => {
+                let _ = self.output.write_all(&[payload as u8]);
+                let _ = self.output.flush();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MemoryDevice for Htif {
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, InstructionException> {
+        let addr = self.tohost.wrapping_add(addr);
+        if self.fromhost <= addr && addr < self.fromhost + 8 {
+            let shift = 8 * (addr - self.fromhost);
+            return Ok((self.fromhost_value >> shift) & mask(size));
+        }
+
+        Ok(0)
+    }
+
+    fn store(&mut self, addr: u64, size: u8, val: u64) -> Result<(), InstructionException> {
+        let addr = self.tohost.wrapping_add(addr);
+        if self.fromhost <= addr && addr < self.fromhost + 8 {
+            let shift = 8 * (addr - self.fromhost);
+            let mask = mask(size) << shift;
+            self.fromhost_value = (self.fromhost_value & !mask) | ((val << shift) & mask);
+        } else if addr == self.tohost && val != 0 {
+            // The riscv-tests write the low word only, so take the rest of
+            // the command as zero.
+            self.command(val & mask(size));
+        }
+
+        Ok(())
+    }
+}
+
+fn mask(size: u8) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
@@ -0,0 +1,732 @@
+/// Major opcodes, found in bits [6:0] of every 32-bit instruction.
+pub mod opcode {
+    pub const LOAD: u32 = 0b0000011;
+    pub const LOAD_FP: u32 = 0b0000111;
+    pub const MISC_MEM: u32 = 0b0001111;
+    pub const OP_IMM: u32 = 0b0010011;
+    pub const AUIPC: u32 = 0b0010111;
+    pub const OP_IMM_32: u32 = 0b0011011;
+    pub const STORE: u32 = 0b0100011;
+    pub const STORE_FP: u32 = 0b0100111;
+    pub const AMO: u32 = 0b0101111;
+    pub const OP: u32 = 0b0110011;
+    pub const LUI: u32 = 0b0110111;
+    pub const OP_32: u32 = 0b0111011;
+    pub const MADD: u32 = 0b1000011;
+    pub const MSUB: u32 = 0b1000111;
+    pub const NMSUB: u32 = 0b1001011;
+    pub const NMADD: u32 = 0b1001111;
+    pub const OP_FP: u32 = 0b1010011;
+    pub const BRANCH: u32 = 0b1100011;
+    pub const JALR: u32 = 0b1100111;
+    pub const JAL: u32 = 0b1101111;
+    pub const SYSTEM: u32 = 0b1110011;
+}
+
+use crate::compressed;
+use crate::exception::InstructionException;
+
+/// A decoded instruction, tagged with its encoding format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    R(RFormat),
+    R4(R4Format),
+    I(IFormat),
+    IS(ISType),
+    S(SFormat),
+    B(BFormat),
+    U(UFormat),
+    J(JFormat),
+    Csr(CsrFormat),
+    Fence(FenceFormat),
+    FenceI,
+    Ecall,
+    Ebreak,
+    Mret,
+    Sret,
+    Wfi,
+    SfenceVma(RFormat),
+}
+
+impl TryFrom<InstructionBytes> for Instruction {
+    type Error = InstructionException;
+
+    fn try_from(instruction: InstructionBytes) -> Result<Self, Self::Error> {
+        let illegal = InstructionException::IllegalInstruction(instruction.0);
+        if instruction.is_compressed() {
+            // The expansion is always a legal 32-bit instruction, but faults
+            // report the compressed bits.
+            let expanded = compressed::expand(instruction.0 as u16).ok_or(illegal)?;
+            return Instruction::try_from(InstructionBytes(expanded)).map_err(|_| illegal);
+        }
+        let funct7 = instruction.funct7();
+        let funct6 = bits(instruction.0, 31, 26);
+
+        let decoded = match (instruction.opcode(), instruction.funct3()) {
+            (opcode::OP, 0b000 | 0b101) if funct7 == 0x20 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::OP, _) if funct7 == 0x00 => Instruction::R(RFormat::from(instruction)),
+            (opcode::OP, _) if funct7 == 0x01 => Instruction::R(RFormat::from(instruction)),
+            (opcode::OP_32, 0b000 | 0b101) if funct7 == 0x20 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::OP_32, 0b000 | 0b001 | 0b101) if funct7 == 0x00 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::OP_32, 0b000 | 0b100..=0b111) if funct7 == 0x01 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // The rm field is decoded here, but a dynamic rounding mode is only
+            // checked against frm when executed.
+            (opcode::OP_FP, 0b000..=0b100 | 0b111)
+                if matches!(
+                    funct7,
+                    0x00 | 0x04 | 0x08 | 0x0c | 0x01 | 0x05 | 0x09 | 0x0d
+                ) =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::OP_FP, 0b000..=0b100 | 0b111)
+                if matches!(funct7, 0x2c | 0x2d) && instruction.rs2() == 0 =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FCVT between singles or doubles and integers, with the integer
+            // type in rs2.
+            (opcode::OP_FP, 0b000..=0b100 | 0b111)
+                if matches!(funct7, 0x60 | 0x68 | 0x61 | 0x69) && instruction.rs2() <= 3 =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FSGNJ / FSGNJN / FSGNJX, in single or double precision.
+            (opcode::OP_FP, 0b000..=0b010) if matches!(funct7, 0x10 | 0x11) => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FMIN / FMAX, in single or double precision.
+            (opcode::OP_FP, 0b000 | 0b001) if matches!(funct7, 0x14 | 0x15) => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FLE / FLT / FEQ, in single or double precision.
+            (opcode::OP_FP, 0b000..=0b010) if matches!(funct7, 0x50 | 0x51) => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FCLASS.S / FCLASS.D
+            (opcode::OP_FP, 0b001) if matches!(funct7, 0x70 | 0x71) && instruction.rs2() == 0 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FMV.X.W / FMV.X.D / FMV.W.X / FMV.D.X
+            (opcode::OP_FP, 0b000)
+                if matches!(funct7, 0x70 | 0x71 | 0x78 | 0x79) && instruction.rs2() == 0 =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // FCVT.S.D / FCVT.D.S, with the source format in rs2.
+            (opcode::OP_FP, 0b000..=0b100 | 0b111)
+                if (funct7 == 0x20 && instruction.rs2() == 1)
+                    || (funct7 == 0x21 && instruction.rs2() == 0) =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // The fused multiply-adds, in single or double precision.
+            (
+                opcode::MADD | opcode::MSUB | opcode::NMSUB | opcode::NMADD,
+                0b000..=0b100 | 0b111,
+            ) if funct7 & 0b11 <= 0b01 => Instruction::R4(R4Format::from(instruction)),
+            (opcode::OP_IMM, 0b001) if funct6 == 0x00 => Instruction::IS(ISType::from(instruction)),
+            (opcode::OP_IMM, 0b101) if funct6 == 0x00 || funct6 == 0x10 => {
+                Instruction::IS(ISType::from(instruction))
+            }
+            (opcode::OP_IMM, 0b000 | 0b010 | 0b011 | 0b100 | 0b110 | 0b111) => {
+                Instruction::I(IFormat::from(instruction))
+            }
+            (opcode::OP_IMM_32, 0b000) => Instruction::I(IFormat::from(instruction)),
+            // The word shifts have a 5-bit shamt, so imm[5] must be clear.
+            (opcode::OP_IMM_32, 0b001) if funct7 == 0x00 => {
+                Instruction::IS(ISType::from(instruction))
+            }
+            (opcode::OP_IMM_32, 0b101) if funct7 == 0x00 || funct7 == 0x20 => {
+                Instruction::IS(ISType::from(instruction))
+            }
+            // LR and SC on words or doublewords, whose funct7 also holds the
+            // aq and rl bits.
+            (opcode::AMO, 0b010 | 0b011) if funct7 >> 2 == 0b00010 && instruction.rs2() == 0 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::AMO, 0b010 | 0b011) if funct7 >> 2 == 0b00011 => {
+                Instruction::R(RFormat::from(instruction))
+            }
+            // AMOSWAP, AMOADD, AMOXOR, AMOAND, AMOOR, AMOMIN, AMOMAX, AMOMINU
+            // and AMOMAXU.
+            (opcode::AMO, 0b010 | 0b011)
+                if matches!(
+                    funct7 >> 2,
+                    0b00000
+                        | 0b00001
+                        | 0b00100
+                        | 0b01100
+                        | 0b01000
+                        | 0b10000
+                        | 0b10100
+                        | 0b11000
+                        | 0b11100
+                ) =>
+            {
+                Instruction::R(RFormat::from(instruction))
+            }
+            (opcode::STORE, 0b000..=0b011) => Instruction::S(SFormat::from(instruction)),
+            (opcode::BRANCH, 0b000 | 0b001 | 0b100 | 0b101 | 0b110 | 0b111) => {
+                Instruction::B(BFormat::from(instruction))
+            }
+            (opcode::LOAD, 0b000..=0b110) => Instruction::I(IFormat::from(instruction)),
+            (opcode::LOAD_FP, 0b010 | 0b011) => Instruction::I(IFormat::from(instruction)),
+            (opcode::STORE_FP, 0b010 | 0b011) => Instruction::S(SFormat::from(instruction)),
+            (opcode::JALR, 0b000) => Instruction::I(IFormat::from(instruction)),
+            (opcode::LUI | opcode::AUIPC, _) => Instruction::U(UFormat::from(instruction)),
+            (opcode::JAL, _) => Instruction::J(JFormat::from(instruction)),
+            (opcode::MISC_MEM, 0b000) => {
+                let fence = FenceFormat::from(instruction);
+                // Only the normal and TSO fence modes are defined, and TSO
+                // must order reads and writes before and after.
+                match fence.fm {
+                    0b0000 => Instruction::Fence(fence),
+                    0b1000 if fence.pred == 0b0011 && fence.succ == 0b0011 => {
+                        Instruction::Fence(fence)
+                    }
+                    _ => return Err(illegal),
+                }
+            }
+            (opcode::MISC_MEM, 0b001) => Instruction::FenceI,
+            (opcode::SYSTEM, 0b000) if instruction.0 == 0x0000_0073 => Instruction::Ecall,
+            (opcode::SYSTEM, 0b000) if instruction.0 == 0x0010_0073 => Instruction::Ebreak,
+            (opcode::SYSTEM, 0b000) if instruction.0 == 0x3020_0073 => Instruction::Mret,
+            (opcode::SYSTEM, 0b000) if instruction.0 == 0x1020_0073 => Instruction::Sret,
+            (opcode::SYSTEM, 0b000) if instruction.0 == 0x1050_0073 => Instruction::Wfi,
+            // SFENCE.VMA takes the address in rs1 and the ASID in rs2.
+            (opcode::SYSTEM, 0b000) if funct7 == 0x09 && instruction.rd() == 0 => {
+                Instruction::SfenceVma(RFormat::from(instruction))
+            }
+            (opcode::SYSTEM, 0b001..=0b011 | 0b101..=0b111) => {
+                Instruction::Csr(CsrFormat::from(instruction))
+            }
+            _ => return Err(illegal),
+        };
+
+        Ok(decoded)
+    }
+}
+
+/// A raw, undecoded 32-bit instruction word, or a 16-bit compressed
+/// instruction in the low half.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstructionBytes(pub u32);
+
+impl InstructionBytes {
+    pub fn is_compressed(&self) -> bool {
+        compressed::is_compressed(self.0 as u16)
+    }
+
+    /// The length of the instruction in bytes.
+    pub fn size(&self) -> u64 {
+        if self.is_compressed() {
+            2
+        } else {
+            4
+        }
+    }
+
+    pub fn opcode(&self) -> u32 {
+        bits(self.0, 6, 0)
+    }
+
+    pub fn rd(&self) -> usize {
+        bits(self.0, 11, 7) as usize
+    }
+
+    pub fn funct3(&self) -> u32 {
+        bits(self.0, 14, 12)
+    }
+
+    pub fn rs1(&self) -> usize {
+        bits(self.0, 19, 15) as usize
+    }
+
+    pub fn rs2(&self) -> usize {
+        bits(self.0, 24, 20) as usize
+    }
+
+    pub fn funct7(&self) -> u32 {
+        bits(self.0, 31, 25)
+    }
+}
+
+/// Register-register operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RFormat {
+    pub rd: usize,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub funct3: u32,
+    pub funct7: u32,
+    pub opcode: u32,
+}
+
+impl RFormat {
+    /// Whether funct3 is a rounding mode, as it is for the floating-point
+    /// instructions that round their result. The others use it to select
+    /// the operation.
+    pub fn has_rounding_mode(&self) -> bool {
+        self.opcode == opcode::OP_FP
+            && !matches!(
+                self.funct7,
+                0x10 | 0x11 | 0x14 | 0x15 | 0x50 | 0x51 | 0x70 | 0x71 | 0x78 | 0x79
+            )
+    }
+}
+
+impl From<InstructionBytes> for RFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        RFormat {
+            rd: instruction.rd(),
+            rs1: instruction.rs1(),
+            rs2: instruction.rs2(),
+            funct3: instruction.funct3(),
+            funct7: instruction.funct7(),
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// The fused multiply-adds, which take a third source register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct R4Format {
+    pub rd: usize,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub rs3: usize,
+    /// The rounding mode.
+    pub funct3: u32,
+    /// The precision: 00 for singles, 01 for doubles.
+    pub fmt: u32,
+    pub opcode: u32,
+}
+
+impl From<InstructionBytes> for R4Format {
+    fn from(instruction: InstructionBytes) -> Self {
+        R4Format {
+            rd: instruction.rd(),
+            rs1: instruction.rs1(),
+            rs2: instruction.rs2(),
+            rs3: bits(instruction.0, 31, 27) as usize,
+            funct3: instruction.funct3(),
+            fmt: bits(instruction.0, 26, 25),
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// Register-immediate operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IFormat {
+    pub rd: usize,
+    pub rs1: usize,
+    /// The sign-extended 12-bit immediate.
+    pub imm: i32,
+    pub funct3: u32,
+    pub opcode: u32,
+}
+
+impl From<InstructionBytes> for IFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        let imm = sign_extend(bits(instruction.0, 31, 20), 12) as i32;
+
+        IFormat {
+            rd: instruction.rd(),
+            rs1: instruction.rs1(),
+            imm,
+            funct3: instruction.funct3(),
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// Shift-by-immediate operations (SLLI/SRLI/SRAI), an I-type variant whose
+/// immediate is split into a shift amount and a shift kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ISType {
+    pub rd: usize,
+    pub rs1: usize,
+    /// The 6-bit shift amount, imm[5:0].
+    pub shamt: u32,
+    pub funct3: u32,
+    /// imm[11:6]; `0b010000` selects an arithmetic right shift.
+    pub funct6: u32,
+    pub opcode: u32,
+}
+
+impl From<InstructionBytes> for ISType {
+    fn from(instruction: InstructionBytes) -> Self {
+        ISType {
+            rd: instruction.rd(),
+            rs1: instruction.rs1(),
+            shamt: bits(instruction.0, 25, 20),
+            funct3: instruction.funct3(),
+            funct6: bits(instruction.0, 31, 26),
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// Stores, whose immediate is split around the rs2 field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SFormat {
+    pub rs1: usize,
+    pub rs2: usize,
+    /// The sign-extended 12-bit offset.
+    pub imm: i32,
+    pub funct3: u32,
+    pub opcode: u32,
+}
+
+impl From<InstructionBytes> for SFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        let imm = bits(instruction.0, 31, 25) << 5 | bits(instruction.0, 11, 7);
+        let imm = sign_extend(imm, 12) as i32;
+
+        SFormat {
+            rs1: instruction.rs1(),
+            rs2: instruction.rs2(),
+            imm,
+            funct3: instruction.funct3(),
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// Conditional branches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BFormat {
+    pub rs1: usize,
+    pub rs2: usize,
+    /// The sign-extended 13-bit branch offset; bit 0 is always clear.
+    pub imm: i32,
+    pub funct3: u32,
+}
+
+impl From<InstructionBytes> for BFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        let imm = bits(instruction.0, 31, 31) << 12
+            | bits(instruction.0, 7, 7) << 11
+            | bits(instruction.0, 30, 25) << 5
+            | bits(instruction.0, 11, 8) << 1;
+        let imm = sign_extend(imm, 13) as i32;
+
+        BFormat {
+            rs1: instruction.rs1(),
+            rs2: instruction.rs2(),
+            imm,
+            funct3: instruction.funct3(),
+        }
+    }
+}
+
+/// Upper-immediate operations (LUI/AUIPC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UFormat {
+    pub rd: usize,
+    /// imm[31:12], already shifted into place with the low 12 bits clear.
+    pub imm: i32,
+    pub opcode: u32,
+}
+
+impl From<InstructionBytes> for UFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        UFormat {
+            rd: instruction.rd(),
+            imm: (bits(instruction.0, 31, 12) << 12) as i32,
+            opcode: instruction.opcode(),
+        }
+    }
+}
+
+/// Unconditional jumps (JAL).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JFormat {
+    pub rd: usize,
+    /// The sign-extended 21-bit jump offset; bit 0 is always clear.
+    pub imm: i32,
+}
+
+impl From<InstructionBytes> for JFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        let imm = bits(instruction.0, 31, 31) << 20
+            | bits(instruction.0, 19, 12) << 12
+            | bits(instruction.0, 20, 20) << 11
+            | bits(instruction.0, 30, 21) << 1;
+        let imm = sign_extend(imm, 21) as i32;
+
+        JFormat {
+            rd: instruction.rd(),
+            imm,
+        }
+    }
+}
+
+/// Control and status register accesses (the Zicsr extension).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsrFormat {
+    pub rd: usize,
+    /// The source register, or the 5-bit zero-extended immediate for the
+    /// CSRR*I variants.
+    pub rs1: usize,
+    pub csr: usize,
+    pub funct3: u32,
+}
+
+impl CsrFormat {
+    /// Whether executing this instruction writes to the CSR. CSRRS/CSRRC
+    /// and their immediate forms do not write when the source is zero.
+    pub fn writes_csr(&self) -> bool {
+        match self.funct3 {
+            0b001 | 0b101 => true,
+            _ => self.rs1 != 0,
+        }
+    }
+}
+
+impl From<InstructionBytes> for CsrFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        CsrFormat {
+            rd: instruction.rd(),
+            rs1: instruction.rs1(),
+            csr: bits(instruction.0, 31, 20) as usize,
+            funct3: instruction.funct3(),
+        }
+    }
+}
+
+/// Memory ordering fences. Each set has one bit per access kind: input (I),
+/// output (O), read (R) and write (W), from most to least significant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FenceFormat {
+    /// Accesses that must complete before the fence.
+    pub pred: u32,
+    /// Accesses that must not start before the fence.
+    pub succ: u32,
+    /// The fence mode; `0b1000` is FENCE.TSO.
+    pub fm: u32,
+}
+
+impl From<InstructionBytes> for FenceFormat {
+    fn from(instruction: InstructionBytes) -> Self {
+        FenceFormat {
+            pred: bits(instruction.0, 27, 24),
+            succ: bits(instruction.0, 23, 20),
+            fm: bits(instruction.0, 31, 28),
+        }
+    }
+}
+
+/// Bits [hi:lo] of `word`, shifted down to bit 0.
+pub fn bits(word: u32, hi: u32, lo: u32) -> u32 {
+    (word >> lo) & (u32::MAX >> (31 - (hi - lo)))
+}
+
+/// Sign-extends the low `bits` bits of `value`, for `bits` from 1 to 32.
+pub fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value as i64) << shift) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn decode(word: u32) -> Result<Instruction, InstructionException> {
+        Instruction::try_from(InstructionBytes(word))
+    }
+
+    #[test]
+    fn decodes_each_opcode_to_its_format() {
+        assert!(matches!(decode(asm::ld(1, 2, 8)), Ok(Instruction::I(_))));
+        // flw fa0, 8(a1)
+        assert!(matches!(decode(0x0085_a507), Ok(Instruction::I(_))));
+        // fence rw, w
+        assert!(matches!(decode(0x0310_000f), Ok(Instruction::Fence(_))));
+        assert!(matches!(decode(asm::addi(1, 2, 3)), Ok(Instruction::I(_))));
+        assert!(matches!(decode(asm::slli(1, 2, 3)), Ok(Instruction::IS(_))));
+        assert!(matches!(decode(asm::auipc(1, 1)), Ok(Instruction::U(_))));
+        assert!(matches!(decode(asm::addiw(1, 2, 3)), Ok(Instruction::I(_))));
+        assert!(matches!(decode(asm::sd(1, 2, 8)), Ok(Instruction::S(_))));
+        // fsw fa0, 8(a1)
+        assert!(matches!(decode(0x00a5_a427), Ok(Instruction::S(_))));
+        // amoadd.w a0, a1, (a2)
+        assert!(matches!(decode(0x00b6_252f), Ok(Instruction::R(_))));
+        assert!(matches!(decode(asm::add(1, 2, 3)), Ok(Instruction::R(_))));
+        assert!(matches!(decode(asm::lui(1, 1)), Ok(Instruction::U(_))));
+        assert!(matches!(decode(asm::addw(1, 2, 3)), Ok(Instruction::R(_))));
+        // fmadd.s fa0, fa1, fa2, fa3
+        assert!(matches!(decode(0x68c5_f543), Ok(Instruction::R4(_))));
+        // fadd.s fa0, fa1, fa2
+        assert!(matches!(decode(0x00c5_f553), Ok(Instruction::R(_))));
+        assert!(matches!(decode(asm::beq(1, 2, 8)), Ok(Instruction::B(_))));
+        assert!(matches!(decode(asm::jalr(1, 2, 8)), Ok(Instruction::I(_))));
+        assert!(matches!(decode(asm::jal(1, 8)), Ok(Instruction::J(_))));
+        assert!(matches!(
+            decode(asm::csrrw(1, 0x340, 2)),
+            Ok(Instruction::Csr(_))
+        ));
+        assert_eq!(decode(asm::ecall()), Ok(Instruction::Ecall));
+    }
+
+    #[test]
+    fn addi_immediates_are_sign_extended() {
+        // addi x1, x2, imm
+        for (word, imm) in [(0xfff1_0093, -1), (0x8001_0093, -2048), (0x7ff1_0093, 2047)] {
+            let expected = IFormat {
+                rd: 1,
+                rs1: 2,
+                imm,
+                funct3: 0,
+                opcode: opcode::OP_IMM,
+            };
+            assert_eq!(decode(word), Ok(Instruction::I(expected)));
+        }
+    }
+
+    #[test]
+    fn decodes_shifts_by_immediate_as_istype() {
+        // slli x1, x2, 63
+        let expected = ISType {
+            rd: 1,
+            rs1: 2,
+            shamt: 63,
+            funct3: 0b001,
+            funct6: 0,
+            opcode: opcode::OP_IMM,
+        };
+        assert_eq!(decode(0x03f1_1093), Ok(Instruction::IS(expected)));
+        // srai x1, x2, 63
+        assert!(matches!(
+            decode(0x43f1_5093),
+            Ok(Instruction::IS(ISType {
+                shamt: 63,
+                funct6: 0x10,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn garbage_words_are_illegal() {
+        for word in [0x0000_007f, 0xdead_beeb, 0xffff_ff8b] {
+            assert_eq!(
+                decode(word),
+                Err(InstructionException::IllegalInstruction(word))
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_a_backward_branch_offset() {
+        // beq x1, x2, -8
+        let expected = BFormat {
+            rs1: 1,
+            rs2: 2,
+            imm: -8,
+            funct3: 0,
+        };
+        assert_eq!(decode(0xfe20_8ce3), Ok(Instruction::B(expected)));
+    }
+
+    #[test]
+    fn decodes_a_negative_store_offset() {
+        // sd x1, -8(x2)
+        let expected = SFormat {
+            rs1: 2,
+            rs2: 1,
+            imm: -8,
+            funct3: 0b011,
+            opcode: opcode::STORE,
+        };
+        assert_eq!(decode(0xfe11_3c23), Ok(Instruction::S(expected)));
+    }
+
+    #[test]
+    fn decodes_fence_fields() {
+        // fence rw, w
+        let Ok(Instruction::Fence(fence)) = decode(0x0310_000f) else {
+            panic!("not a fence");
+        };
+        assert_eq!((fence.pred, fence.succ, fence.fm), (0b0011, 0b0001, 0));
+        // fence.tso
+        let Ok(Instruction::Fence(fence)) = decode(0x8330_000f) else {
+            panic!("not a fence");
+        };
+        assert_eq!((fence.pred, fence.succ, fence.fm), (0b0011, 0b0011, 0b1000));
+        assert!(matches!(decode(0x0000_100f), Ok(Instruction::FenceI)));
+        // A reserved fence mode, and TSO ordering only writes.
+        assert!(decode(0x1ff0_000f).is_err());
+        assert!(decode(0x8110_000f).is_err());
+    }
+
+    #[test]
+    fn decodes_sfence_vma() {
+        // sfence.vma x1, x2
+        let Ok(Instruction::SfenceVma(r)) = decode(0x1220_8073) else {
+            panic!("not an SFENCE.VMA");
+        };
+        assert_eq!((r.rs1, r.rs2), (1, 2));
+        // A nonzero rd is reserved.
+        assert!(decode(0x1220_80f3).is_err());
+    }
+
+    #[test]
+    fn sign_extends_every_value_of_each_immediate_width() {
+        for width in [12, 13, 20, 21] {
+            let half = 1i64 << (width - 1);
+            for value in 0..1u32 << width {
+                let expected = if (value as i64) < half {
+                    value as i64
+                } else {
+                    value as i64 - 2 * half
+                };
+                assert_eq!(sign_extend(value, width), expected, "{value:#x} at {width}");
+            }
+        }
+    }
+
+    #[test]
+    fn extracts_bit_fields() {
+        assert_eq!(bits(0xdead_beef, 31, 31), 1);
+        assert_eq!(bits(0xdead_beef, 31, 0), 0xdead_beef);
+        assert_eq!(bits(0xdead_beef, 15, 8), 0xbe);
+        assert_eq!(bits(0xdead_beef, 4, 4), 0);
+    }
+
+    #[test]
+    fn instruction_bytes_accessors_pick_out_each_field() {
+        // sra x3, x4, x5
+        let word = InstructionBytes(asm::sra(3, 4, 5));
+        assert_eq!(word.0, 0x4052_51b3);
+        assert_eq!(word.opcode(), opcode::OP);
+        assert_eq!(word.rd(), 3);
+        assert_eq!(word.funct3(), 0b101);
+        assert_eq!(word.rs1(), 4);
+        assert_eq!(word.rs2(), 5);
+        assert_eq!(word.funct7(), 0x20);
+        assert!(!word.is_compressed());
+        assert_eq!(word.size(), 4);
+    }
+
+    #[test]
+    fn all_zeros_and_all_ones_are_illegal() {
+        for word in [0x0000_0000, 0xffff_ffff] {
+            assert_eq!(
+                decode(word),
+                Err(InstructionException::IllegalInstruction(word))
+            );
+        }
+    }
+}
@@ -0,0 +1,24 @@
+pub mod asm;
+pub mod bus;
+pub mod clint;
+pub mod compliance;
+pub mod compressed;
+pub mod csr;
+pub mod decode_cache;
+pub mod disassembler;
+pub mod dram;
+pub mod elf;
+pub mod exception;
+pub mod extensions;
+pub mod float;
+pub mod gdb;
+pub mod hart;
+pub mod htif;
+pub mod instruction;
+pub mod mmu;
+pub mod plic;
+pub mod pmp;
+pub mod runner;
+pub mod snapshot;
+pub mod trace;
+pub mod uart;
@@ -1,3 +1,206 @@
+use std::cmp::Reverse;
+use std::env;
+use std::fs::{self, File};
+use std::io::LineWriter;
+use std::process;
+
+use re64::compliance::{self, TestOutcome};
+use re64::dram::{self, DRAM_SIZE};
+use re64::elf;
+use re64::extensions::Extensions;
+use re64::gdb;
+use re64::hart::{Halt, Hart};
+
 fn main() {
-    unimplemented!("Nothing here!")
+    let mut trace = false;
+    let mut trace_json = None;
+    let mut dump_json = None;
+    let mut memory_size = DRAM_SIZE;
+    let mut gdb_port = None;
+    let mut max_steps = None;
+    let mut entry = None;
+    let mut load_addr = None;
+    let mut extensions = Extensions::default();
+    let mut testmode = false;
+    let mut profile = false;
+    let mut halt_on_ecall = false;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--trace-json" => trace_json = Some(args.next().unwrap_or_else(|| usage())),
+            "--dump-json" => dump_json = Some(args.next().unwrap_or_else(|| usage())),
+            "--testmode" => testmode = true,
+            "--profile" => profile = true,
+            "--halt-on-ecall" => halt_on_ecall = true,
+            "--memory" => {
+                let size = args.next().and_then(|size| dram::parse_size(&size));
+                memory_size = size.unwrap_or_else(|| usage());
+            }
+            "--gdb" => {
+                let port = args.next().and_then(|port| port.parse::<u16>().ok());
+                gdb_port = Some(port.unwrap_or_else(|| usage()));
+            }
+            "--entry" => {
+                let addr = args.next().and_then(|addr| parse_addr(&addr));
+                entry = Some(addr.unwrap_or_else(|| usage()));
+            }
+            "--isa" => {
+                let isa = args.next().and_then(|isa| Extensions::from_isa(&isa));
+                extensions = isa.unwrap_or_else(|| usage());
+            }
+            "--load-addr" => {
+                let addr = args.next().and_then(|addr| parse_addr(&addr));
+                load_addr = Some(addr.unwrap_or_else(|| usage()));
+            }
+            "--max-steps" => {
+                let steps = args.next().and_then(|steps| steps.parse::<u64>().ok());
+                max_steps = Some(steps.unwrap_or_else(|| usage()));
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    // The hart has one trace sink, so only one format can be traced.
+    if trace && trace_json.is_some() {
+        usage();
+    }
+
+    let code = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1);
+    });
+
+    if testmode {
+        match compliance::run(
+            &code,
+            memory_size,
+            max_steps.unwrap_or(compliance::MAX_STEPS),
+        ) {
+            Ok(TestOutcome::Pass) => println!("PASS"),
+            Ok(TestOutcome::Fail(test)) => {
+                println!("FAIL: test {}", test);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to run {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Anything that is not an ELF executable is run as a flat binary.
+    let mut hart = if elf::is_elf(&code) {
+        if load_addr.is_some() {
+            eprintln!("--load-addr only applies to flat binaries");
+            process::exit(1);
+        }
+        Hart::from_elf(&code, memory_size).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", path, e);
+            process::exit(1);
+        })
+    } else if let Some(addr) = load_addr {
+        Hart::with_load_addr(&code, memory_size, addr).unwrap_or_else(|| {
+            eprintln!("{} does not fit in memory at {:#x}", path, addr);
+            process::exit(1);
+        })
+    } else {
+        if code.len() > memory_size {
+            eprintln!("{} does not fit in {} bytes of memory", path, memory_size);
+            process::exit(1);
+        }
+        Hart::with_memory(code, memory_size)
+    };
+    // ELF executables start at their entry point, and flat binaries where
+    // they are loaded, unless overridden.
+    if let Some(entry) = entry {
+        hart.reset_vector = entry;
+        hart.pc = entry;
+    }
+    hart.extensions = extensions;
+    hart.set_trace(trace);
+    if let Some(trace_path) = trace_json {
+        let file = File::create(&trace_path).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", trace_path, e);
+            process::exit(1);
+        });
+        // Line buffered, as the process may exit without dropping the hart.
+        hart.trace_json_to(Box::new(LineWriter::new(file)));
+    }
+    hart.set_profile(profile);
+    hart.set_halt_on_ecall(halt_on_ecall);
+
+    if let Some(port) = gdb_port {
+        eprintln!("Waiting for GDB on port {}", port);
+        if let Err(e) = gdb::serve(&mut hart, port) {
+            eprintln!("GDB connection failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let halt = match max_steps {
+        Some(steps) => hart.run_until(steps),
+        None => Some(hart.run()),
+    };
+    if profile {
+        print_profile(&hart);
+    }
+    if let Some(dump_path) = dump_json {
+        if let Err(e) = fs::write(&dump_path, hart.registers_json() + "\n") {
+            eprintln!("Failed to write {}: {}", dump_path, e);
+            process::exit(1);
+        }
+    }
+    match halt {
+        Some(Halt::Exit(code)) => process::exit(code as i32),
+        Some(Halt::Exception(exception)) => {
+            println!("Halted: {:?}", exception);
+            hart.dump_registers();
+        }
+        Some(Halt::Breakpoint(_) | Halt::Watchpoint(_)) => {
+            unreachable!("no breakpoints are set")
+        }
+        None => {
+            println!("Stopped after reaching the step limit");
+            hart.dump_registers();
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints how many times each instruction executed to stderr, most
+/// frequent first.
+fn print_profile(hart: &Hart) {
+    let mut counts: Vec<_> = hart.instruction_counts().into_iter().collect();
+    counts.sort_by_key(|&(_, count)| Reverse(count));
+    let total: u64 = counts.iter().map(|(_, count)| count).sum();
+    for (mnemonic, count) in counts {
+        eprintln!(
+            "{:<12} {:>12} {:>6.2}%",
+            mnemonic,
+            count,
+            100.0 * count as f64 / total as f64
+        );
+    }
+}
+
+/// Parses an address in hexadecimal with a `0x` prefix, or in decimal.
+fn parse_addr(addr: &str) -> Option<u64> {
+    match addr.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => addr.parse().ok(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: re64 [--trace | --trace-json <file>] [--dump-json <file>] [--testmode] \
+         [--profile] [--halt-on-ecall] [--isa <isa>] [--memory <size>] [--load-addr <addr>] \
+         [--entry <addr>] [--gdb <port>] [--max-steps <n>] <binary>"
+    );
+    process::exit(1);
 }
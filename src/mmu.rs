@@ -0,0 +1,271 @@
+use crate::bus::Bus;
+use crate::csr::{Csr, SATP};
+use crate::exception::InstructionException;
+use crate::hart::PrivilegeMode;
+use crate::pmp;
+
+/// `satp.MODE` value selecting Sv39 translation.
+pub const SATP_MODE_SV39: u64 = 8;
+
+pub const PAGE_SIZE: u64 = 4096;
+const LEVELS: usize = 3;
+const PTE_SIZE: u64 = 8;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+/// Bits [63:54] of a PTE, reserved for extensions this hart lacks.
+const PTE_RESERVED: u64 = 0x3ff << 54;
+
+/// The kind of memory access being translated, which selects the permission
+/// checked and the page fault raised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Instruction,
+    Load,
+    Store,
+}
+
+impl AccessType {
+    fn page_fault(self, addr: u64) -> InstructionException {
+        match self {
+            AccessType::Instruction => InstructionException::InstructionPageFault(addr),
+            AccessType::Load => InstructionException::LoadPageFault(addr),
+            AccessType::Store => InstructionException::StorePageFault(addr),
+        }
+    }
+
+    pub(crate) fn access_fault(self, addr: u64) -> InstructionException {
+        match self {
+            AccessType::Instruction => InstructionException::InstructionAccessFault(addr),
+            AccessType::Load => InstructionException::LoadAccessFault(addr),
+            AccessType::Store => InstructionException::StoreAccessFault(addr),
+        }
+    }
+}
+
+/// Translates virtual address `addr` into a physical address by walking the
+/// Sv39 page table rooted at `satp`.
+///
+/// The A and D bits are not updated by the walk: an access to a page whose
+/// A bit is clear, or a store to a page whose D bit is clear, page faults so
+/// that software can set them. PMP must allow reading each PTE, as a
+/// Supervisor-mode load, or the access faults.
+pub fn translate(
+    bus: &mut Bus,
+    csr: &Csr,
+    mode: PrivilegeMode,
+    addr: u64,
+    access: AccessType,
+) -> Result<u64, InstructionException> {
+    let (satp, mstatus) = (csr.read(SATP), csr.mstatus());
+    let fault = access.page_fault(addr);
+
+    // Bits [63:39] must all equal bit 38.
+    if ((addr as i64) << 25 >> 25) as u64 != addr {
+        return Err(fault);
+    }
+
+    let vpn = [
+        (addr >> 12) & 0x1ff,
+        (addr >> 21) & 0x1ff,
+        (addr >> 30) & 0x1ff,
+    ];
+    let mut table = (satp & 0xfff_ffff_ffff) * PAGE_SIZE;
+    let mut level = LEVELS - 1;
+    let pte = loop {
+        let pte_addr = table + vpn[level] * PTE_SIZE;
+        if !pmp::permits(
+            csr,
+            pte_addr,
+            PTE_SIZE as usize,
+            AccessType::Load,
+            PrivilegeMode::Supervisor,
+        ) {
+            return Err(access.access_fault(addr));
+        }
+        let pte = bus
+            .load(pte_addr, 8)
+            .map_err(|_| access.access_fault(addr))?;
+
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) || pte & PTE_RESERVED != 0 {
+            return Err(fault);
+        }
+        if pte & (PTE_R | PTE_X) != 0 {
+            break pte;
+        }
+        if level == 0 {
+            return Err(fault);
+        }
+        level -= 1;
+        table = ((pte >> 10) & 0xfff_ffff_ffff) * PAGE_SIZE;
+    };
+
+    let permitted = match access {
+        AccessType::Instruction => pte & PTE_X != 0,
+        // MXR makes executable pages readable too.
+        AccessType::Load => pte & PTE_R != 0 || (mstatus.mxr() && pte & PTE_X != 0),
+        AccessType::Store => pte & PTE_W != 0,
+    };
+    // User pages are only accessible from Supervisor mode for loads and
+    // stores, and only with SUM set.
+    let user_ok = match mode {
+        PrivilegeMode::User => pte & PTE_U != 0,
+        _ if pte & PTE_U == 0 => true,
+        _ => access != AccessType::Instruction && mstatus.sum(),
+    };
+    if !permitted || !user_ok {
+        return Err(fault);
+    }
+    if pte & PTE_A == 0 || (access == AccessType::Store && pte & PTE_D == 0) {
+        return Err(fault);
+    }
+
+    // A superpage leaf must be aligned to its size, and the VPN fields below
+    // its level pass through into the physical address.
+    let ppn = (pte >> 10) & 0xfff_ffff_ffff;
+    let page_mask = (1 << (9 * level)) - 1;
+    if ppn & page_mask != 0 {
+        return Err(fault);
+    }
+    let ppn = ppn | ((addr >> 12) & page_mask);
+
+    Ok((ppn * PAGE_SIZE) | (addr & (PAGE_SIZE - 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::{PMPADDR0, PMPCFG0};
+    use crate::dram::{Dram, DRAM_BASE};
+
+    const ROOT: u64 = DRAM_BASE + 0x1000;
+    const SATP_VALUE: u64 = (SATP_MODE_SV39 << 60) | (ROOT / PAGE_SIZE);
+
+    /// A leaf or pointer PTE for physical address `paddr`.
+    fn pte(paddr: u64, flags: u64) -> u64 {
+        ((paddr / PAGE_SIZE) << 10) | flags | PTE_V
+    }
+
+    /// A bus whose root page table maps its own 1 GiB of DRAM one to one,
+    /// and virtual page 0x4000_0000 to DRAM_BASE + 0x5000 through two more
+    /// levels of tables.
+    fn bus() -> Bus {
+        let mut bus = Bus::new(Dram::new(DRAM_BASE, 0x10000, &[]));
+        let leaf = PTE_R | PTE_W | PTE_A | PTE_D;
+        bus.store(ROOT + 2 * PTE_SIZE, 8, pte(DRAM_BASE, leaf | PTE_X))
+            .unwrap();
+        bus.store(ROOT + PTE_SIZE, 8, pte(DRAM_BASE + 0x2000, 0))
+            .unwrap();
+        bus.store(DRAM_BASE + 0x2000, 8, pte(DRAM_BASE + 0x3000, 0))
+            .unwrap();
+        bus.store(DRAM_BASE + 0x3000, 8, pte(DRAM_BASE + 0x5000, leaf))
+            .unwrap();
+        bus
+    }
+
+    /// CSRs selecting the page table at `ROOT`.
+    fn csr() -> Csr {
+        let mut csr = Csr::new();
+        csr.write(SATP, SATP_VALUE);
+        csr
+    }
+
+    fn translate_load(bus: &mut Bus, addr: u64) -> Result<u64, InstructionException> {
+        translate(
+            bus,
+            &csr(),
+            PrivilegeMode::Supervisor,
+            addr,
+            AccessType::Load,
+        )
+    }
+
+    #[test]
+    fn identity_mapped_superpage() {
+        let mut bus = bus();
+        bus.store(DRAM_BASE + 0x8123, 1, 0x5a).unwrap();
+
+        let paddr = translate_load(&mut bus, DRAM_BASE + 0x8123).unwrap();
+        assert_eq!(paddr, DRAM_BASE + 0x8123);
+        assert_eq!(bus.load(paddr, 1), Ok(0x5a));
+    }
+
+    #[test]
+    fn three_level_walk() {
+        let mut bus = bus();
+        bus.store(DRAM_BASE + 0x5010, 1, 0xa5).unwrap();
+
+        let paddr = translate_load(&mut bus, 0x4000_0010).unwrap();
+        assert_eq!(paddr, DRAM_BASE + 0x5010);
+        assert_eq!(bus.load(paddr, 1), Ok(0xa5));
+        // The neighbouring page is not mapped.
+        assert_eq!(
+            translate_load(&mut bus, 0x4000_1000),
+            Err(InstructionException::LoadPageFault(0x4000_1000))
+        );
+    }
+
+    #[test]
+    fn permissions_are_checked() {
+        let mut bus = bus();
+
+        // The 4 KiB page is not executable, nor a User page.
+        let fault = |access: AccessType| access.page_fault(0x4000_0000);
+        for (mode, access) in [
+            (PrivilegeMode::Supervisor, AccessType::Instruction),
+            (PrivilegeMode::User, AccessType::Load),
+        ] {
+            assert_eq!(
+                translate(&mut bus, &csr(), mode, 0x4000_0000, access),
+                Err(fault(access))
+            );
+        }
+        // Non-canonical addresses never translate.
+        assert_eq!(
+            translate_load(&mut bus, 1 << 40),
+            Err(InstructionException::LoadPageFault(1 << 40))
+        );
+    }
+
+    #[test]
+    fn pmp_must_allow_reading_the_page_tables() {
+        let mut bus = bus();
+        // A 4 KiB NAPOT entry over the root table, without R permission,
+        // and a second one allowing everything else.
+        let mut csr = csr();
+        csr.write(PMPADDR0, (ROOT + 0x7ff) >> 2);
+        csr.write(PMPADDR0 + 1, pmp::PMPADDR_MASK);
+        csr.write(PMPCFG0, 0x1f1e);
+
+        for access in [AccessType::Load, AccessType::Store, AccessType::Instruction] {
+            assert_eq!(
+                translate(
+                    &mut bus,
+                    &csr,
+                    PrivilegeMode::Supervisor,
+                    0x4000_0010,
+                    access
+                ),
+                Err(access.access_fault(0x4000_0010))
+            );
+        }
+
+        // With R permission the walk succeeds.
+        csr.write(PMPCFG0, 0x1f19);
+        assert_eq!(
+            translate(
+                &mut bus,
+                &csr,
+                PrivilegeMode::Supervisor,
+                0x4000_0010,
+                AccessType::Load
+            ),
+            Ok(DRAM_BASE + 0x5010)
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::bus::MemoryDevice;
+use crate::exception::InstructionException;
+
+/// Physical address at which the PLIC's registers are mapped.
+pub const PLIC_BASE: u64 = 0x0c00_0000;
+/// Size of the PLIC's register window.
+pub const PLIC_SIZE: u64 = 0x400_0000;
+
+/// Number of interrupt sources, including the reserved source 0.
+pub const PLIC_SOURCES: usize = 32;
+
+/// Offsets of the per-source priorities, the pending bits, and the enable
+/// bits, threshold and claim/complete register of the hart's machine-mode
+/// context.
+const PRIORITY: u64 = 0x0;
+const PENDING: u64 = 0x1000;
+const ENABLE: u64 = 0x2000;
+const THRESHOLD: u64 = 0x20_0000;
+const CLAIM: u64 = 0x20_0004;
+
+/// The platform-level interrupt controller, routing external interrupt
+/// sources to a single machine-mode context.
+///
+/// Sources are numbered from 1; source 0 means "no interrupt". A claimed
+/// source is not offered again until its claim is completed.
+pub struct Plic {
+    priority: [u32; PLIC_SOURCES],
+    pending: u32,
+    enable: u32,
+    threshold: u32,
+    in_service: u32,
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Plic {
+            priority: [0; PLIC_SOURCES],
+            pending: 0,
+            enable: 0,
+            threshold: 0,
+            in_service: 0,
+        }
+    }
+
+    /// Marks interrupt `source` as pending.
+    pub fn raise(&mut self, source: usize) {
+        if source != 0 && source < PLIC_SOURCES {
+            self.pending |= 1 << source;
+        }
+    }
+
+    /// Whether an enabled source is pending above the threshold, asserting
+    /// the external interrupt line toward the hart.
+    pub fn interrupt_pending(&self) -> bool {
+        self.best() != 0
+    }
+
+    /// The highest-priority source that may be claimed, preferring the lowest
+    /// number on ties, or 0 if there is none.
+    fn best(&self) -> usize {
+        let ready = self.pending & self.enable & !self.in_service;
+        (1..PLIC_SOURCES)
+            .filter(|&source| ready & (1 << source) != 0)
+            .filter(|&source| self.priority[source] > self.threshold)
+            .fold(0, |best, source| {
+                if best == 0 || self.priority[source] > self.priority[best] {
+                    source
+                } else {
+                    best
+                }
+            })
+    }
+
+    /// Claims the best pending source, clearing its pending bit.
+    fn claim(&mut self) -> u32 {
+        let source = self.best();
+        if source != 0 {
+            self.pending &= !(1 << source);
+            self.in_service |= 1 << source;
+        }
+        source as u32
+    }
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryDevice for Plic {
+    fn load(&mut self, addr: u64, _size: u8) -> Result<u64, InstructionException> {
+        let value = match addr {
+            PRIORITY..=0xfff => self.priority.get((addr / 4) as usize).copied().unwrap_or(0),
+            PENDING => self.pending,
+            ENABLE => self.enable,
+            THRESHOLD => self.threshold,
+            CLAIM => self.claim(),
+            _ => 0,
+        };
+
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, addr: u64, _size: u8, val: u64) -> Result<(), InstructionException> {
+        let val = val as u32;
+        match addr {
+            PRIORITY..=0xfff => {
+                let source = (addr / 4) as usize;
+                if source != 0 && source < PLIC_SOURCES {
+                    self.priority[source] = val;
+                }
+            }
+            // Source 0 does not exist, so its enable bit is hardwired to 0.
+            ENABLE => self.enable = val & !1,
+            THRESHOLD => self.threshold = val,
+            // Completing a claim lets the source be offered again.
+            CLAIM if (val as usize) < PLIC_SOURCES => self.in_service &= !(1 << val),
+            // Pending bits are read-only; they are set by the sources.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plic() -> Plic {
+        let mut plic = Plic::new();
+        plic.store(PRIORITY + 4, 4, 1).unwrap();
+        plic.store(PRIORITY + 8, 4, 2).unwrap();
+        plic.store(ENABLE, 4, 0b110).unwrap();
+        plic
+    }
+
+    #[test]
+    fn claim_and_complete() {
+        let mut plic = plic();
+        plic.raise(1);
+        assert!(plic.interrupt_pending());
+
+        assert_eq!(plic.load(CLAIM, 4), Ok(1));
+        assert_eq!(plic.load(PENDING, 4), Ok(0));
+        assert!(!plic.interrupt_pending());
+        // A source in service is not offered again until completed.
+        plic.raise(1);
+        assert_eq!(plic.load(CLAIM, 4), Ok(0));
+        plic.store(CLAIM, 4, 1).unwrap();
+        assert_eq!(plic.load(CLAIM, 4), Ok(1));
+    }
+
+    #[test]
+    fn claims_the_highest_priority_source_above_the_threshold() {
+        let mut plic = plic();
+        plic.raise(1);
+        plic.raise(2);
+        plic.raise(3);
+
+        assert_eq!(plic.load(CLAIM, 4), Ok(2));
+        plic.store(THRESHOLD, 4, 1).unwrap();
+        assert!(!plic.interrupt_pending());
+        assert_eq!(plic.load(CLAIM, 4), Ok(0));
+    }
+}
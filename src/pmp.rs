@@ -0,0 +1,122 @@
+use crate::csr::{Csr, PMPADDR0, PMPCFG0};
+use crate::hart::PrivilegeMode;
+use crate::mmu::AccessType;
+
+/// Number of PMP entries.
+pub const PMP_COUNT: usize = 64;
+
+/// Bits [55:2] of a physical address, the part held in `pmpaddr`.
+pub const PMPADDR_MASK: u64 = (1 << 54) - 1;
+
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+/// The entry also applies in Machine mode, and cannot be changed until reset.
+pub const PMP_L: u8 = 1 << 7;
+
+/// Address-matching modes, in bits [4:3] of an entry's configuration.
+const PMP_A_OFF: u8 = 0;
+const PMP_A_TOR: u8 = 1;
+const PMP_A_NA4: u8 = 2;
+const PMP_A_NAPOT: u8 = 3;
+
+/// The configuration byte of entry `i`. On RV64, each of the even-numbered
+/// `pmpcfg` registers holds eight.
+pub fn config(csr: &Csr, i: usize) -> u8 {
+    (csr.read(PMPCFG0 + i / 8 * 2) >> (8 * (i % 8))) as u8
+}
+
+/// Whether a write to `pmpaddr{i}` is ignored: it is locked along with
+/// entry `i`, and with entry `i + 1` if that is a locked TOR range, whose
+/// bottom it sets.
+pub fn addr_locked(csr: &Csr, i: usize) -> bool {
+    let next = (i + 1 < PMP_COUNT).then(|| config(csr, i + 1));
+    config(csr, i) & PMP_L != 0
+        || next.is_some_and(|next| next & PMP_L != 0 && mode(next) == PMP_A_TOR)
+}
+
+/// Writes `value` into the `pmpcfg` register holding `old`, leaving the
+/// bytes of locked entries unchanged.
+pub fn write_config(old: u64, value: u64) -> u64 {
+    (0..8).fold(0, |config, byte| {
+        let shift = 8 * byte;
+        let from = if (old >> shift) as u8 & PMP_L != 0 {
+            old
+        } else {
+            value
+        };
+        config | from & (0xff << shift)
+    })
+}
+
+/// Whether PMP allows an `access` to the `len` bytes at physical address
+/// `addr` from `mode`.
+///
+/// The lowest-numbered entry matching any of the bytes decides, and fails
+/// the access unless it matches all of them. Machine mode is only checked
+/// against locked entries, and succeeds if none match. The other modes fail
+/// if none match, unless no entry is enabled at all, which is taken to mean
+/// PMP is not in use.
+pub fn permits(csr: &Csr, addr: u64, len: usize, access: AccessType, mode: PrivilegeMode) -> bool {
+    // Most harts never configure PMP, so check for that quickly.
+    if (0..PMP_COUNT / 8).all(|reg| csr.read(PMPCFG0 + 2 * reg) == 0) {
+        return true;
+    }
+
+    let end = addr.saturating_add(len as u64);
+    let mut enabled = false;
+    for i in 0..PMP_COUNT {
+        let config = config(csr, i);
+        let Some((start, limit)) = range(csr, i, config) else {
+            continue;
+        };
+        enabled = true;
+        if end <= start || limit <= addr {
+            continue;
+        }
+        if addr < start || limit < end {
+            return false;
+        }
+        if mode == PrivilegeMode::Machine && config & PMP_L == 0 {
+            return true;
+        }
+
+        let permission = match access {
+            AccessType::Instruction => PMP_X,
+            AccessType::Load => PMP_R,
+            AccessType::Store => PMP_W,
+        };
+        return config & permission != 0;
+    }
+
+    mode == PrivilegeMode::Machine || !enabled
+}
+
+fn mode(config: u8) -> u8 {
+    (config >> 3) & 0b11
+}
+
+/// The physical addresses entry `i` with configuration `config` matches, as
+/// a start and an exclusive end, or `None` if it is off.
+fn range(csr: &Csr, i: usize, config: u8) -> Option<(u64, u64)> {
+    let pmpaddr = csr.read(PMPADDR0 + i);
+    match mode(config) {
+        PMP_A_OFF => None,
+        // The range starts where the previous entry's address ends.
+        PMP_A_TOR => {
+            let start = match i {
+                0 => 0,
+                _ => csr.read(PMPADDR0 + i - 1) << 2,
+            };
+            Some((start, pmpaddr << 2))
+        }
+        PMP_A_NA4 => Some((pmpaddr << 2, (pmpaddr << 2) + 4)),
+        // The trailing ones give the size: a range of 2^(3 + ones) bytes.
+        PMP_A_NAPOT => {
+            let ones = pmpaddr.trailing_ones();
+            let start = (pmpaddr & !((1 << ones) - 1)) << 2;
+            Some((start, start + (8 << ones)))
+        }
+        _ => unreachable!("the address-matching mode is a 2-bit field"),
+    }
+}
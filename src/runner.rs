@@ -0,0 +1,75 @@
+use std::array;
+
+use crate::hart::{Halt, Hart};
+
+/// The state a program left a hart in when [`run_program`] stopped it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// The integer registers, indexed by register number.
+    pub regs: [u64; 32],
+    pub pc: u64,
+    /// The code the program exited with through the HTIF, if it did.
+    pub exit_code: Option<u64>,
+    /// Why the hart stopped, or `None` if it reached the step limit.
+    pub halt: Option<Halt>,
+}
+
+/// Runs the flat binary `code` on a fresh hart, as [`Hart::new`] loads it,
+/// for at most `max_steps` steps. Each run has its own hart and memory, so
+/// programs can run one after another or on several threads at once.
+///
+/// # Panics
+///
+/// Panics if `code` does not fit in DRAM.
+pub fn run_program(code: &[u8], max_steps: u64) -> RunOutcome {
+    let mut hart = Hart::new(code.to_vec());
+    let halt = hart.run_until(max_steps);
+
+    RunOutcome {
+        regs: array::from_fn(|i| hart.read_reg(i)),
+        pc: hart.pc,
+        exit_code: hart.exit_code(),
+        halt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::dram::DRAM_BASE;
+    use crate::exception::InstructionException;
+
+    fn code(program: &[u32]) -> Vec<u8> {
+        program.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn runs_programs_back_to_back() {
+        let breakpoint = run_program(&code(&[asm::addi(10, 0, 42), asm::ebreak()]), 100);
+        let looping = run_program(&code(&[asm::addi(11, 11, 1), asm::jal(0, -4)]), 100);
+        let illegal = run_program(&code(&[asm::addi(12, 0, 1)]), 100);
+
+        assert_eq!(
+            breakpoint.halt,
+            Some(Halt::Exception(InstructionException::Breakpoint(
+                DRAM_BASE + 4
+            )))
+        );
+        assert_eq!(breakpoint.regs[10], 42);
+        assert_eq!(breakpoint.pc, DRAM_BASE + 4);
+
+        assert_eq!(looping.halt, None);
+        assert_eq!(looping.regs[11], 50);
+        // Nothing the first program did carried over.
+        assert_eq!(looping.regs[10], 0);
+
+        assert_eq!(
+            illegal.halt,
+            Some(Halt::Exception(InstructionException::IllegalInstruction(0)))
+        );
+        assert_eq!(illegal.regs[12], 1);
+        assert_eq!(illegal.pc, DRAM_BASE + 4);
+        assert_eq!(illegal.exit_code, None);
+    }
+}
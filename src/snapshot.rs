@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hart::PrivilegeMode;
+
+/// The architectural state of a hart and the contents of its DRAM, as
+/// captured by `Hart::snapshot`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineState {
+    pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    pub pc: u64,
+    /// The CSRs holding a nonzero value, by address.
+    pub csrs: Vec<(usize, u64)>,
+    pub privilege: PrivilegeMode,
+    /// DRAM run-length encoded, as it is mostly zero.
+    pub dram: Vec<Run>,
+}
+
+/// `len` consecutive copies of `byte`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Run {
+    pub byte: u8,
+    pub len: u64,
+}
+
+/// Run-length encodes `bytes`.
+pub fn encode(bytes: &[u8]) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for &byte in bytes {
+        match runs.last_mut() {
+            Some(run) if run.byte == byte => run.len += 1,
+            _ => runs.push(Run { byte, len: 1 }),
+        }
+    }
+    runs
+}
+
+/// The number of bytes `runs` decodes to.
+pub fn decoded_len(runs: &[Run]) -> u64 {
+    runs.iter().map(|run| run.len).sum()
+}
+
+/// Decodes `runs` into `bytes`, which must be exactly `decoded_len` long.
+pub fn decode_into(runs: &[Run], bytes: &mut [u8]) {
+    let mut offset = 0;
+    for run in runs {
+        let end = offset + run.len as usize;
+        bytes[offset..end].fill(run.byte);
+        offset = end;
+    }
+}
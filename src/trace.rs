@@ -0,0 +1,156 @@
+use std::io::Write;
+
+use crate::hart::PrivilegeMode;
+use crate::instruction::InstructionBytes;
+
+/// How traced instructions are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The disassembled instruction, followed by one indented line per
+    /// write.
+    Text,
+    /// One JSON object per retired instruction, one per line.
+    Json,
+}
+
+/// A change made by a traced instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceWrite {
+    /// The register with ABI name `name` changed from `old` to `new`.
+    Reg {
+        name: &'static str,
+        old: u64,
+        new: u64,
+    },
+    /// The low `size` bytes of `value` were stored at virtual address
+    /// `addr`.
+    Mem { addr: u64, size: usize, value: u64 },
+}
+
+/// Where executed instructions are traced to, and how.
+pub(crate) struct Tracer {
+    output: Box<dyn Write>,
+    format: TraceFormat,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, format: TraceFormat) -> Self {
+        Tracer { output, format }
+    }
+
+    /// Starts tracing the instruction at `pc`, which `symbol` describes
+    /// if it is in a known function. Text traces show it before it
+    /// executes, so that it appears even if it faults.
+    pub fn start(
+        &mut self,
+        pc: u64,
+        symbol: Option<String>,
+        instruction: InstructionBytes,
+        disassembly: &str,
+    ) {
+        if self.format == TraceFormat::Text {
+            let symbol = symbol.map_or(String::new(), |symbol| format!(" <{}>", symbol));
+            let line = format!(
+                "{:#018x}{}: {:<8}  {}",
+                pc,
+                symbol,
+                hex(instruction),
+                disassembly
+            );
+            self.line(&line);
+        }
+    }
+
+    /// Finishes tracing the instruction at `pc`, executed in `mode`, with
+    /// the `writes` it made. JSON traces only show instructions that
+    /// `retired` without an exception.
+    pub fn finish(
+        &mut self,
+        pc: u64,
+        mode: PrivilegeMode,
+        instruction: InstructionBytes,
+        disassembly: &str,
+        writes: &[TraceWrite],
+        retired: bool,
+    ) {
+        match self.format {
+            TraceFormat::Text => {
+                for write in writes {
+                    let line = match *write {
+                        TraceWrite::Reg { name, old, new } => {
+                            format!("    {:<4} {:#018x} -> {:#018x}", name, old, new)
+                        }
+                        TraceWrite::Mem { addr, size, value } => {
+                            format!(
+                                "    mem  {:#018x} <- {:#0width$x}",
+                                addr,
+                                value,
+                                width = 2 + 2 * size
+                            )
+                        }
+                    };
+                    self.line(&line);
+                }
+            }
+            TraceFormat::Json if retired => {
+                let writes: Vec<String> = writes.iter().map(json_write).collect();
+                let line = format!(
+                    "{{\"pc\":\"{:#018x}\",\"bytes\":\"{}\",\"disasm\":{},\"mode\":\"{}\",\"writes\":[{}]}}",
+                    pc,
+                    hex(instruction),
+                    json_string(disassembly),
+                    mode.letter(),
+                    writes.join(",")
+                );
+                self.line(&line);
+            }
+            TraceFormat::Json => {}
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        // A failing trace sink should not stop the program being traced.
+        let _ = writeln!(self.output, "{}", line);
+    }
+}
+
+/// The instruction bits in hex: four digits if compressed, otherwise eight.
+fn hex(instruction: InstructionBytes) -> String {
+    if instruction.is_compressed() {
+        format!("{:04x}", instruction.0)
+    } else {
+        format!("{:08x}", instruction.0)
+    }
+}
+
+/// Values are written as hex strings, as JSON numbers cannot hold every
+/// 64-bit value exactly.
+fn json_write(write: &TraceWrite) -> String {
+    match *write {
+        TraceWrite::Reg { name, new, .. } => {
+            format!("{{\"reg\":\"{}\",\"value\":\"{:#018x}\"}}", name, new)
+        }
+        TraceWrite::Mem { addr, size, value } => format!(
+            "{{\"mem\":\"{:#018x}\",\"size\":{},\"value\":\"{:#0width$x}\"}}",
+            addr,
+            size,
+            value,
+            width = 2 + 2 * size
+        ),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
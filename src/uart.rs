@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::bus::MemoryDevice;
+use crate::exception::InstructionException;
+
+/// Physical address at which the UART's registers are mapped.
+pub const UART_BASE: u64 = 0x1000_0000;
+/// Size of the UART's register window.
+pub const UART_SIZE: u64 = 0x100;
+
+/// Receive holding register (read) and transmit holding register (write).
+const RHR: u64 = 0;
+const THR: u64 = 0;
+/// Line status register.
+const LSR: u64 = 5;
+
+/// `LSR` bit set while received data is waiting in `RHR`.
+const LSR_DATA_READY: u8 = 1 << 0;
+/// `LSR` bits set while the transmitter can accept another byte.
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+/// Where a UART receives the bytes typed at its console from.
+pub trait ConsoleInput {
+    /// The next byte received, or `None` if there is none yet. Must not
+    /// block.
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+/// A fixed sequence of bytes, received in order.
+impl ConsoleInput for VecDeque<u8> {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.pop_front()
+    }
+}
+
+/// Console input from the host's stdin.
+///
+/// Stdin is not touched until the first byte is asked for. From then on, a
+/// thread reads it and hands over bytes as they arrive, so that reads never
+/// block the hart.
+#[derive(Default)]
+pub struct StdinInput {
+    bytes: Option<Receiver<u8>>,
+}
+
+impl ConsoleInput for StdinInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        let bytes = self.bytes.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    let Ok(byte) = byte else { break };
+                    if sender.send(byte).is_err() {
+                        break;
+                    }
+                }
+            });
+            receiver
+        });
+        bytes.try_recv().ok()
+    }
+}
+
+/// A minimal 16550-style UART.
+///
+/// Bytes written to `THR` go straight to the output sink, so the transmitter
+/// is always empty. Reads from `RHR` return the bytes queued with
+/// [`Uart::push_input`], and then those polled from the console input. The
+/// remaining registers read as zero and ignore writes.
+pub struct Uart {
+    output: Box<dyn Write>,
+    input: VecDeque<u8>,
+    console: Box<dyn ConsoleInput>,
+}
+
+impl Uart {
+    /// Creates a UART that transmits to `output` and receives from stdin.
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self::with_input(output, Box::new(StdinInput::default()))
+    }
+
+    /// Creates a UART that transmits to `output` and receives from
+    /// `console`.
+    pub fn with_input(output: Box<dyn Write>, console: Box<dyn ConsoleInput>) -> Self {
+        Uart {
+            output,
+            input: VecDeque::new(),
+            console,
+        }
+    }
+
+    /// Queues `bytes` to be received by the guest through `RHR`.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Polls the console for a byte if none is waiting.
+    fn poll(&mut self) {
+        if self.input.is_empty() {
+            self.input.extend(self.console.read_byte());
+        }
+    }
+
+    fn line_status(&mut self) -> u8 {
+        self.poll();
+        let mut lsr = LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY;
+        if !self.input.is_empty() {
+            lsr |= LSR_DATA_READY;
+        }
+        lsr
+    }
+}
+
+impl MemoryDevice for Uart {
+    fn load(&mut self, addr: u64, _size: u8) -> Result<u64, InstructionException> {
+        let value = match addr {
+            RHR => {
+                self.poll();
+                self.input.pop_front().unwrap_or(0)
+            }
+            LSR => self.line_status(),
+            _ => 0,
+        };
+
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, addr: u64, _size: u8, val: u64) -> Result<(), InstructionException> {
+        if addr == THR {
+            // The guest has no way to observe a failing host sink, so the
+            // byte is dropped.
+            let _ = self.output.write_all(&[val as u8]);
+            let _ = self.output.flush();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// An output sink whose bytes can still be read after it has been handed
+    /// to a UART.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn uart(output: &Capture) -> Uart {
+        Uart::new(Box::new(output.clone()))
+    }
+
+    #[test]
+    fn thr_writes_reach_the_output() {
+        let output = Capture::default();
+        let mut uart = uart(&output);
+
+        uart.store(THR, 1, b'A' as u64).unwrap();
+        assert_eq!(*output.0.borrow(), b"A");
+    }
+
+    #[test]
+    fn rhr_reads_queued_input() {
+        let mut uart = uart(&Capture::default());
+        let ready = (LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY) as u64;
+        assert_eq!(uart.load(LSR, 1), Ok(ready));
+
+        uart.push_input(b"x");
+        assert_eq!(uart.load(LSR, 1), Ok(ready | LSR_DATA_READY as u64));
+        assert_eq!(uart.load(RHR, 1), Ok(b'x' as u64));
+        assert_eq!(uart.load(RHR, 1), Ok(0));
+    }
+
+    #[test]
+    fn console_input_is_read_a_byte_at_a_time() {
+        let console = VecDeque::from(b"hi\n".to_vec());
+        let mut uart = Uart::with_input(Box::new(io::sink()), Box::new(console));
+
+        for &byte in b"hi\n" {
+            assert_ne!(uart.load(LSR, 1).unwrap() & LSR_DATA_READY as u64, 0);
+            assert_eq!(uart.load(RHR, 1), Ok(byte as u64));
+        }
+        assert_eq!(uart.load(LSR, 1).unwrap() & LSR_DATA_READY as u64, 0);
+        assert_eq!(uart.load(RHR, 1), Ok(0));
+    }
+}
@@ -0,0 +1,71 @@
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+/// Runs the `re64` binary with `args`, from the crate root.
+fn re64(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_re64"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn rejects_both_trace_formats_at_once() {
+    let trace = env::temp_dir().join("re64-cli-trace.json");
+    let output = re64(&[
+        "--trace",
+        "--trace-json",
+        trace.to_str().unwrap(),
+        "tests/fixtures/tiny.elf",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Usage: re64"));
+    assert!(!trace.exists());
+}
+
+#[test]
+fn dumps_the_final_registers_as_json() {
+    let dump = env::temp_dir().join("re64-cli-dump.json");
+    let output = re64(&[
+        "--dump-json",
+        dump.to_str().unwrap(),
+        "tests/fixtures/tiny.elf",
+    ]);
+
+    assert!(output.status.success());
+    let json = fs::read_to_string(&dump).unwrap();
+    assert!(json.starts_with(r#"{"pc":"#));
+    // The values are pc and then x0 to x31, and tiny.elf loads 42 into a0
+    // before its EBREAK.
+    let values: Vec<&str> = json.split('"').filter(|s| s.starts_with("0x")).collect();
+    assert_eq!(values.len(), 33);
+    assert_eq!(values[1 + 10], "0x000000000000002a");
+}
+
+#[test]
+fn isa_selects_the_extensions() {
+    // mul a0, a0, a0, then EBREAK.
+    let program = env::temp_dir().join("re64-cli-mul.bin");
+    fs::write(&program, [0x33, 0x05, 0xa5, 0x02, 0x73, 0x00, 0x10, 0x00]).unwrap();
+    let program = program.to_str().unwrap();
+
+    let rv64i = re64(&["--isa", "rv64i", program]);
+    let stdout = String::from_utf8_lossy(&rv64i.stdout);
+    assert!(stdout.starts_with("Halted: IllegalInstruction"), "{stdout}");
+
+    let rv64im = re64(&["--isa", "rv64im", program]);
+    let stdout = String::from_utf8_lossy(&rv64im.stdout);
+    assert!(stdout.starts_with("Halted: Breakpoint"), "{stdout}");
+}
+
+#[test]
+fn halt_on_ecall_exits_with_a0() {
+    // li a0, 42, then ECALL.
+    let program = env::temp_dir().join("re64-cli-ecall.bin");
+    fs::write(&program, [0x13, 0x05, 0xa0, 0x02, 0x73, 0x00, 0x00, 0x00]).unwrap();
+
+    let output = re64(&["--halt-on-ecall", program.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(42));
+}
@@ -0,0 +1,32 @@
+use re64::compliance::{self, TestError, TestOutcome, MAX_STEPS};
+
+/// riscv-tests' rv64ui-p-add, built from `fixtures/rv64ui-p-add.S`.
+const ADD: &[u8] = include_bytes!("fixtures/rv64ui-p-add");
+
+const MEMORY_SIZE: usize = 1 << 20;
+
+#[test]
+fn rv64ui_p_add_passes() {
+    assert_eq!(
+        compliance::run(ADD, MEMORY_SIZE, MAX_STEPS),
+        Ok(TestOutcome::Pass)
+    );
+}
+
+#[test]
+fn running_out_of_steps_fails() {
+    assert_eq!(
+        compliance::run(ADD, MEMORY_SIZE, 100),
+        Err(TestError::Timeout(100))
+    );
+}
+
+#[test]
+fn programs_without_tohost_are_rejected() {
+    let tiny = include_bytes!("fixtures/tiny.elf");
+
+    assert_eq!(
+        compliance::run(tiny, MEMORY_SIZE, MAX_STEPS),
+        Err(TestError::NoTohost)
+    );
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use re64::dram::DRAM_BASE;
+use re64::elf::ElfError;
+use re64::exception::InstructionException;
+use re64::hart::{Halt, Hart};
+
+/// Built from `fixtures/tiny.S`: a padding word, then code at `_start` that
+/// loads 42 from its data segment and stops on an EBREAK.
+const TINY: &[u8] = include_bytes!("fixtures/tiny.elf");
+
+/// Built from `fixtures/functions.S`: `_start` calls `double` on 21 and
+/// stops on an EBREAK when it returns.
+const FUNCTIONS: &[u8] = include_bytes!("fixtures/functions.elf");
+
+const MEMORY_SIZE: usize = 0x10000;
+
+#[test]
+fn loads_segments_and_starts_at_the_entry_point() {
+    let mut hart = Hart::from_elf(TINY, MEMORY_SIZE).unwrap();
+
+    assert_eq!(hart.pc, DRAM_BASE + 4);
+    assert_eq!(hart.bus.dram.bytes()[..4], 0xdead_beef_u32.to_le_bytes());
+    assert_eq!(hart.bus.dram.load(DRAM_BASE + 0x1000, 8), Ok(42));
+    assert!(matches!(
+        hart.run(),
+        Halt::Exception(InstructionException::Breakpoint(_))
+    ));
+    assert_eq!(hart.read_reg(10), 42);
+}
+
+#[test]
+fn rejects_other_machines() {
+    let mut bytes = TINY.to_vec();
+    // e_machine, set to x86-64.
+    bytes[18..20].copy_from_slice(&62_u16.to_le_bytes());
+
+    assert_eq!(
+        Hart::from_elf(&bytes, MEMORY_SIZE).err(),
+        Some(ElfError::NotRiscV(62))
+    );
+}
+
+#[test]
+fn rejects_32_bit_and_big_endian_files() {
+    // EI_CLASS and EI_DATA.
+    for (index, value, error) in [
+        (4, 1, ElfError::Not64Bit),
+        (5, 2, ElfError::NotLittleEndian),
+    ] {
+        let mut bytes = TINY.to_vec();
+        bytes[index] = value;
+
+        assert_eq!(Hart::from_elf(&bytes, MEMORY_SIZE).err(), Some(error));
+    }
+}
+
+#[test]
+fn rejects_segments_outside_memory() {
+    assert_eq!(
+        Hart::from_elf(TINY, 0x1000).err(),
+        Some(ElfError::SegmentOutOfMemory(DRAM_BASE + 0x1000))
+    );
+}
+
+/// A trace sink whose bytes can still be read after it has been handed to
+/// the hart.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn traces_show_the_enclosing_function() {
+    let mut hart = Hart::from_elf(FUNCTIONS, MEMORY_SIZE).unwrap();
+    let output = Capture::default();
+    hart.trace_to(Box::new(output.clone()));
+
+    hart.run();
+    let trace = String::from_utf8(output.0.borrow().clone()).unwrap();
+    let pcs: Vec<&str> = trace
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .map(|line| line.split_once(": ").unwrap().0)
+        .collect();
+    assert_eq!(
+        pcs,
+        [
+            "0x0000000080000000 <_start>",
+            "0x0000000080000004 <_start+0x4>",
+            "0x000000008000000c <double>",
+            "0x0000000080000010 <double+0x4>",
+            "0x0000000080000008 <_start+0x8>",
+        ]
+    );
+    assert_eq!(hart.read_reg(10), 42);
+}
@@ -0,0 +1,151 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use re64::asm;
+use re64::clint::CLINT_BASE;
+use re64::dram::DRAM_BASE;
+use re64::gdb::GdbStub;
+use re64::hart::Hart;
+
+/// The debugger's end of a connection.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Sends `data` as a packet and returns the reply.
+    fn send(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.send_raw(&format!("${}#{:02x}", data, checksum));
+        assert_eq!(self.read_byte(), b'+');
+        self.reply()
+    }
+
+    fn send_raw(&mut self, packet: &str) {
+        self.stream.write_all(packet.as_bytes()).unwrap();
+    }
+
+    /// Reads a reply packet and acknowledges it.
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+
+        String::from_utf8(data).unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+/// Serves `hart` to a debugger that runs `session` on another thread, and
+/// returns what the session returns once the stub is done.
+fn debug<T: Send + 'static>(hart: &mut Hart, session: fn(&mut Client) -> T) -> T {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let debugger = thread::spawn(move || {
+        let mut client = Client {
+            stream: TcpStream::connect(addr).unwrap(),
+        };
+        session(&mut client)
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    GdbStub::new(stream).run(hart).unwrap();
+    debugger.join().unwrap()
+}
+
+fn hart() -> Hart {
+    let program = [
+        asm::addi(10, 0, 1),
+        asm::addi(11, 0, 2),
+        asm::addi(12, 0, 3),
+        asm::ebreak(),
+    ];
+    let code = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Hart::with_memory(code, 0x10000)
+}
+
+/// The little-endian hex encoding GDB uses for a 64-bit register.
+fn reg(value: u64) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[test]
+fn session_over_loopback() {
+    let mut hart = hart();
+
+    let replies = debug(&mut hart, |client| {
+        let replies = [
+            client.send("?"),
+            client.send("g"),
+            client.send(&format!("m{:x},8", DRAM_BASE)),
+            client.send(&format!("Z0,{:x},4", DRAM_BASE + 8)),
+            client.send("c"),
+            client.send(&format!("p{:x}", 32)),
+            client.send("pb"),
+        ];
+        client.send("D");
+        replies
+    });
+
+    assert_eq!(replies[0], "S05");
+    let regs = &replies[1];
+    assert_eq!(regs.len(), 33 * 16);
+    assert_eq!(regs[2 * 16..3 * 16], reg(DRAM_BASE + 0x10000));
+    assert_eq!(regs[32 * 16..], reg(DRAM_BASE));
+    let code: String = [asm::addi(10, 0, 1), asm::addi(11, 0, 2)]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(replies[2], code);
+    assert_eq!(replies[3], "OK");
+    assert_eq!(replies[4], "S05");
+    assert_eq!(replies[5], reg(DRAM_BASE + 8));
+    assert_eq!(replies[6], reg(2));
+    assert_eq!(hart.pc, DRAM_BASE + 8);
+    assert_eq!(hart.read_reg(12), 0);
+}
+
+#[test]
+fn malformed_packets() {
+    let mut hart = hart();
+
+    let replies = debug(&mut hart, |client| {
+        // A bad checksum is rejected, and the packet sent again.
+        client.send_raw("$?#00");
+        assert_eq!(client.read_byte(), b'-');
+        let replies = [
+            client.send("?"),
+            client.send(""),
+            client.send("\u{e9}"),
+            // mtime, which is only reachable through MMIO.
+            client.send(&format!("m{:x},8", CLINT_BASE + 0xbff8)),
+            client.send(&format!("M{:x},2:abcd", DRAM_BASE + 0x100)),
+            client.send(&format!("m{:x},2", DRAM_BASE + 0x100)),
+        ];
+        // Killing the target gets no reply.
+        client.send_raw("$k#6b");
+        assert_eq!(client.read_byte(), b'+');
+        replies
+    });
+
+    assert_eq!(replies, ["S05", "", "", "E01", "OK", "abcd"]);
+}